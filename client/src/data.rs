@@ -1,6 +1,9 @@
 use tokio::sync::Mutex as AsyncMutex;
 
-use crate::types::{ActiveSession, AppChannels, AppConfig, ConnectionConfig, Messages, Sessions};
+use crate::types::{
+    ActiveSession, AppChannels, AppConfig, ConnectionConfig, HistoryStates, Messages,
+    PendingMessages, Sessions,
+};
 use std::{
     collections::HashMap,
     sync::{Arc, LazyLock, Mutex},
@@ -26,6 +29,18 @@ pub static CHANNELS: LazyLock<Arc<AsyncMutex<AppChannels>>> =
 pub static MESSAGES: LazyLock<Arc<AsyncMutex<HashMap<String, Messages>>>> =
     LazyLock::new(|| Arc::new(AsyncMutex::new(HashMap::new())));
 
+/// Encrypted messages received for sessions that haven't been opened yet.
+pub static PENDING_MESSAGES: LazyLock<PendingMessages> =
+    LazyLock::new(|| Arc::new(AsyncMutex::new(HashMap::new())));
+
+/// Paging state of the server-side history of every session.
+pub static HISTORY: LazyLock<HistoryStates> =
+    LazyLock::new(|| Arc::new(AsyncMutex::new(HashMap::new())));
+
 /// Shared mutable terminal state.
 pub static APP_STATE: LazyLock<Arc<Mutex<AppConfig>>> =
     LazyLock::new(|| Arc::new(Mutex::new(AppConfig::new())));
+
+/// Resumption ticket issued by the server on the last successful handshake.
+pub static RESUMPTION_TICKET: LazyLock<Arc<AsyncMutex<Option<Vec<u8>>>>> =
+    LazyLock::new(|| Arc::new(AsyncMutex::new(None)));
@@ -14,6 +14,7 @@
 //!   - Writer task: sends queued messages to the client.
 //!   - Command task: processes commands and coordinates between
 //!     reader and writer tasks.
+//! - **Restore**: re-joins the sessions of a previous connection.
 //! - **Shutdown**: when the command task finishes, the writer task
 //!   is aborted and the connection is cleaned up.
 //!
@@ -25,11 +26,11 @@
 //! - [`crate::types`] – Defines types such as [`LogMessage`] and [`LogLevel`].
 
 use crate::{
-    handlers::task,
+    handlers::{restore_sessions, task},
     types::{LogLevel, LogMessage},
     utils::perform_handshake,
 };
-use common::net::AsyncStream;
+use common::net::{AsyncStream, FrameReader, FrameWriter};
 use std::sync::Arc;
 use tokio::sync::Mutex;
 
@@ -45,41 +46,55 @@ use tokio::sync::Mutex;
 ///    wrapped in [`Arc`] + [`tokio::sync::Mutex`] for shared access.
 /// 2. Performs a handshake with the client using [`perform_handshake`].
 ///    - On failure, logs an error via [`LogMessage`] and terminates early.
-/// 3. Spawns three asynchronous tasks:
+/// 3. Re-joins every known session using [`restore_sessions`], so that
+///    DMs and groups resume after a reconnect.
+/// 4. Spawns three asynchronous tasks:
 ///    - **Writer task**: Sends outgoing messages to the client.
 ///    - **Reader task**: Receives incoming messages from the client.
 ///    - **Command task**: Orchestrates user commands and coordinates with
 ///      the reader and writer tasks.
-/// 4. Awaits the command task until completion, and aborts the writer
+/// 5. Awaits the command task until completion, and aborts the writer
 ///    task when shutting down.
 ///
 /// # Parameters
 ///
 /// - `stream`: The accepted [`TcpStream`] for the connected client.
+/// - `secure`: Whether the stream is encrypted or local, the resumption
+///   ticket is only presented then.
+///
+/// # Returns
+///
+/// `true` if the handshake succeeded and the connection was used until
+/// it closed, `false` if the handshake failed.
 ///
 /// # Notes
 ///
 /// - The reader and writer halves are stored in [`Arc<Mutex<...>>`] so
 ///   that multiple tasks can access them safely.
 /// - If the handshake fails, the connection is closed immediately.
-/// - When the command task ends, the writer task is aborted to clean up.
+/// - The command task ends when the connection is lost, the writer task
+///   is then aborted to clean up.
 ///
 /// [`TcpStream`]: tokio::net::TcpStream
 /// [`perform_handshake`]: crate::utils::perform_handshake
+/// [`restore_sessions`]: crate::handlers::restore_sessions
 /// [`LogMessage`]: crate::types::LogMessage
-pub async fn handle_client(stream: Box<dyn AsyncStream>) {
+pub async fn handle_client(stream: Box<dyn AsyncStream>, secure: bool) -> bool {
     let (rd, wt) = tokio::io::split(stream);
-    let rd = Arc::new(Mutex::new(rd));
-    let wt = Arc::new(Mutex::new(wt));
+    let rd = Arc::new(Mutex::new(FrameReader::new(rd)));
+    let wt = Arc::new(Mutex::new(FrameWriter::new(wt)));
 
-    let _ = match perform_handshake(rd.clone(), wt.clone()).await {
+    let _ = match perform_handshake(rd.clone(), wt.clone(), secure).await {
         Ok(session_key) => session_key,
         Err(e) => {
             let _ = LogMessage::log(LogLevel::ERROR, format!("Handshake failed: {}", e), 0).await;
-            return;
+            return false;
         }
     };
 
+    // Resume the sessions of the previous connection
+    restore_sessions(rd.clone(), wt.clone()).await;
+
     // 2. Message Writer Transmitter Task
     let wt_task = task::start_writer_task(wt.clone()).await;
 
@@ -91,4 +106,6 @@ pub async fn handle_client(stream: Box<dyn AsyncStream>) {
 
     let _ = cmd_task.await;
     wt_task.abort();
+
+    true
 }
@@ -1,10 +1,19 @@
 use crate::{
     data,
-    handlers::{add_group_member, create_new_group, new_session, rm_connection},
-    types::{LogLevel, LogMessage, app::update_session},
+    handlers::{
+        accept_contact, add_group_member, block_user, create_channel, create_invite,
+        create_new_group, delete_group, dm_handle, ignore_user, join_group, leave_group,
+        list_blocked, list_contact_requests, list_devices, list_group_members, load_group_members,
+        load_history, mark_session_read, new_session, reject_contact, rekey_group,
+        remove_group_member, rename_group, revoke_device, rm_connection, set_group_admin,
+        set_group_role, set_handle, set_status, show_receipts, subscribe_channel,
+        task::flush_pending_messages, transfer_group, unsubscribe_channel, whois,
+    },
+    types::{LogLevel, LogMessage, Session, app::update_session},
 };
 use common::{
     net::{StreamReader, StreamWriter},
+    types::ChatMode,
     utils::enc::public_key_to_user_id,
 };
 use std::collections::HashMap;
@@ -47,10 +56,258 @@ pub async fn process_command(cmd: &str, rd: StreamReader, wt: StreamWriter) {
             "addgpm",
             CommandInfo {
                 name: "addgpm".into(),
-                desc: "Add a user to a group".into(),
+                desc: "Add a user to the active group".into(),
                 usage: "addgpm <user_id>".into(),
             },
         ),
+        (
+            "rmgpm",
+            CommandInfo {
+                name: "rmgpm".into(),
+                desc: "Remove a member from the active group".into(),
+                usage: "rmgpm <user_id>".into(),
+            },
+        ),
+        (
+            "leave",
+            CommandInfo {
+                name: "leave".into(),
+                desc: "Leave the active group".into(),
+                usage: "leave".into(),
+            },
+        ),
+        (
+            "rngp",
+            CommandInfo {
+                name: "rngp".into(),
+                desc: "Rename the active group".into(),
+                usage: "rngp <name>".into(),
+            },
+        ),
+        (
+            "rmgp",
+            CommandInfo {
+                name: "rmgp".into(),
+                desc: "Delete the active group".into(),
+                usage: "rmgp".into(),
+            },
+        ),
+        (
+            "promote",
+            CommandInfo {
+                name: "promote".into(),
+                desc: "Make a member of the active group an admin".into(),
+                usage: "promote <user_id>".into(),
+            },
+        ),
+        (
+            "demote",
+            CommandInfo {
+                name: "demote".into(),
+                desc: "Make an admin of the active group a plain member".into(),
+                usage: "demote <user_id>".into(),
+            },
+        ),
+        (
+            "role",
+            CommandInfo {
+                name: "role".into(),
+                desc: "Give a member of the active group a role: read-only, member, moderator or admin".into(),
+                usage: "role <user_id> <role>".into(),
+            },
+        ),
+        (
+            "members",
+            CommandInfo {
+                name: "members".into(),
+                desc: "List the members of the active group with their roles".into(),
+                usage: "members".into(),
+            },
+        ),
+        (
+            "rekey",
+            CommandInfo {
+                name: "rekey".into(),
+                desc: "Replace the session key of the active group".into(),
+                usage: "rekey".into(),
+            },
+        ),
+        (
+            "invite",
+            CommandInfo {
+                name: "invite".into(),
+                desc: "Create an invite token to the active group, e.g. invite 5 24h".into(),
+                usage: "invite [max_uses|-] [expiry]".into(),
+            },
+        ),
+        (
+            "join",
+            CommandInfo {
+                name: "join".into(),
+                desc: "Join a group with an invite token".into(),
+                usage: "join <token>".into(),
+            },
+        ),
+        (
+            "mkch",
+            CommandInfo {
+                name: "mkch".into(),
+                desc: "Create a broadcast channel, only publishers can post in it".into(),
+                usage: "mkch <name>".into(),
+            },
+        ),
+        (
+            "sub",
+            CommandInfo {
+                name: "sub".into(),
+                desc: "Subscribe to a broadcast channel".into(),
+                usage: "sub <channel_id>".into(),
+            },
+        ),
+        (
+            "unsub",
+            CommandInfo {
+                name: "unsub".into(),
+                desc: "Unsubscribe from the active channel".into(),
+                usage: "unsub".into(),
+            },
+        ),
+        (
+            "transfer",
+            CommandInfo {
+                name: "transfer".into(),
+                desc: "Make a member the owner of the active group".into(),
+                usage: "transfer <user_id>".into(),
+            },
+        ),
+        (
+            "history",
+            CommandInfo {
+                name: "history".into(),
+                desc: "Load older messages of the active session".into(),
+                usage: "history".into(),
+            },
+        ),
+        (
+            "devices",
+            CommandInfo {
+                name: "devices".into(),
+                desc: "List the devices logged in with your key".into(),
+                usage: "devices".into(),
+            },
+        ),
+        (
+            "revoke",
+            CommandInfo {
+                name: "revoke".into(),
+                desc: "Revoke another device and disconnect it".into(),
+                usage: "revoke <device_id>".into(),
+            },
+        ),
+        (
+            "status",
+            CommandInfo {
+                name: "status".into(),
+                desc: "Set your presence and an optional status line".into(),
+                usage: "status <online|away|dnd> [status]".into(),
+            },
+        ),
+        (
+            "receipts",
+            CommandInfo {
+                name: "receipts".into(),
+                desc: "Show who received and read your selected or last message".into(),
+                usage: "receipts".into(),
+            },
+        ),
+        (
+            "handle",
+            CommandInfo {
+                name: "handle".into(),
+                desc: "Claim a handle others can find you by, or change yours".into(),
+                usage: "handle <name>".into(),
+            },
+        ),
+        (
+            "whois",
+            CommandInfo {
+                name: "whois".into(),
+                desc: "Show the user ID and key a handle belongs to".into(),
+                usage: "whois <handle>".into(),
+            },
+        ),
+        (
+            "dm",
+            CommandInfo {
+                name: "dm".into(),
+                desc: "Start a DM with the user a handle belongs to".into(),
+                usage: "dm <handle>".into(),
+            },
+        ),
+        (
+            "requests",
+            CommandInfo {
+                name: "requests".into(),
+                desc: "List the users waiting for you to accept their contact request".into(),
+                usage: "requests".into(),
+            },
+        ),
+        (
+            "accept",
+            CommandInfo {
+                name: "accept".into(),
+                desc: "Accept a contact request and open the DM".into(),
+                usage: "accept <user_id>".into(),
+            },
+        ),
+        (
+            "reject",
+            CommandInfo {
+                name: "reject".into(),
+                desc: "Reject a contact request".into(),
+                usage: "reject <user_id>".into(),
+            },
+        ),
+        (
+            "block",
+            CommandInfo {
+                name: "block".into(),
+                desc: "Block a user, it can't message you nor add you to groups".into(),
+                usage: "block <user_id>".into(),
+            },
+        ),
+        (
+            "unblock",
+            CommandInfo {
+                name: "unblock".into(),
+                desc: "Unblock a user".into(),
+                usage: "unblock <user_id>".into(),
+            },
+        ),
+        (
+            "blocked",
+            CommandInfo {
+                name: "blocked".into(),
+                desc: "List the users you blocked".into(),
+                usage: "blocked".into(),
+            },
+        ),
+        (
+            "ignore",
+            CommandInfo {
+                name: "ignore".into(),
+                desc: "Hide the messages of a member in groups, on this device".into(),
+                usage: "ignore <user_id>".into(),
+            },
+        ),
+        (
+            "unignore",
+            CommandInfo {
+                name: "unignore".into(),
+                desc: "Show the messages of an ignored member again".into(),
+                usage: "unignore <user_id>".into(),
+            },
+        ),
         (
             "my-id",
             CommandInfo {
@@ -112,15 +369,22 @@ pub async fn process_command(cmd: &str, rd: StreamReader, wt: StreamWriter) {
             }
             match new_session(parts[1], rd.clone(), wt.clone()).await {
                 Some(session) => {
-                    let mut s_list = data::SESSIONS.lock().await;
+                    {
+                        let mut s_list = data::SESSIONS.lock().await;
 
-                    let key = session.id.clone();
-                    s_list.entry(key.clone()).or_insert(session.clone());
+                        let key = session.id.clone();
+                        s_list.entry(key.clone()).or_insert(session.clone());
+                    }
+                    flush_pending_messages(&session).await;
+                    if matches!(session.mode, ChatMode::Group(_) | ChatMode::Channel(_)) {
+                        load_group_members(&session.id, rd.clone(), wt.clone()).await;
+                    }
 
                     let mut session_lock = data::ACTIVE_SESSION.lock().await;
                     *session_lock = Some(session.clone());
 
                     update_session(session.clone());
+                    mark_session_read(&session.id).await;
                     let _ = LogMessage::log(
                         LogLevel::INFO,
                         format!("New session created successfully: {}", &session.id[..8]),
@@ -147,13 +411,18 @@ pub async fn process_command(cmd: &str, rd: StreamReader, wt: StreamWriter) {
             }
             match create_new_group(parts[1], rd.clone(), wt.clone()).await {
                 Some(session) => {
-                    let mut s_list = data::SESSIONS.lock().await;
-                    s_list.insert(session.id.clone(), session.clone());
+                    data::SESSIONS
+                        .lock()
+                        .await
+                        .insert(session.id.clone(), session.clone());
+                    flush_pending_messages(&session).await;
+                    load_group_members(&session.id, rd.clone(), wt.clone()).await;
 
                     let mut session_lock = data::ACTIVE_SESSION.lock().await;
                     *session_lock = Some(session.clone());
 
                     update_session(session.clone());
+                    mark_session_read(&session.id).await;
                     let _ = LogMessage::log(
                         LogLevel::INFO,
                         format!("New group created successfully: {}", &session.id[..8]),
@@ -188,6 +457,362 @@ pub async fn process_command(cmd: &str, rd: StreamReader, wt: StreamWriter) {
             }
             add_group_member(parts[1], rd.clone(), wt.clone()).await;
         }
+        "rmgpm" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("rmgpm").unwrap().name,
+                        commands.get("rmgpm").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            remove_group_member(parts[1], rd.clone(), wt.clone()).await;
+        }
+        "leave" => leave_group(rd.clone(), wt.clone()).await,
+        "rngp" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("rngp").unwrap().name,
+                        commands.get("rngp").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            rename_group(&parts[1..].join(" "), rd.clone(), wt.clone()).await;
+        }
+        "rmgp" => delete_group(rd.clone(), wt.clone()).await,
+        "promote" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("promote").unwrap().name,
+                        commands.get("promote").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            set_group_admin(parts[1], true, rd.clone(), wt.clone()).await;
+        }
+        "demote" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("demote").unwrap().name,
+                        commands.get("demote").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            set_group_admin(parts[1], false, rd.clone(), wt.clone()).await;
+        }
+        "transfer" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("transfer").unwrap().name,
+                        commands.get("transfer").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            transfer_group(parts[1], rd.clone(), wt.clone()).await;
+        }
+        "role" => {
+            if parts.len() < 3 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("role").unwrap().name,
+                        commands.get("role").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            set_group_role(parts[1], parts[2], rd.clone(), wt.clone()).await;
+        }
+        "members" => list_group_members(rd.clone(), wt.clone()).await,
+        "invite" => create_invite(&parts[1..], rd.clone(), wt.clone()).await,
+        "join" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("join").unwrap().name,
+                        commands.get("join").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            if let Some(session) = join_group(parts[1], rd.clone(), wt.clone()).await {
+                open_session(session, "Joined", rd.clone(), wt.clone()).await;
+            }
+        }
+        "mkch" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("mkch").unwrap().name,
+                        commands.get("mkch").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            let name = parts[1..].join(" ");
+            if let Some(session) = create_channel(&name, rd.clone(), wt.clone()).await {
+                open_session(session, "Created channel", rd.clone(), wt.clone()).await;
+            }
+        }
+        "sub" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("sub").unwrap().name,
+                        commands.get("sub").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            if let Some(session) = subscribe_channel(parts[1], rd.clone(), wt.clone()).await {
+                open_session(session, "Subscribed to", rd.clone(), wt.clone()).await;
+            }
+        }
+        "unsub" => unsubscribe_channel(rd.clone(), wt.clone()).await,
+        "rekey" => rekey_group(rd.clone(), wt.clone()).await,
+        "history" => {
+            let session = data::ACTIVE_SESSION.lock().await.clone();
+            match session {
+                Some(session) => load_history(&session, rd.clone(), wt.clone()).await,
+                None => {
+                    LogMessage::log(LogLevel::ERROR, "No Active Session Found!".into(), 5).await;
+                }
+            }
+        }
+        "devices" => list_devices(rd.clone(), wt.clone()).await,
+        "revoke" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("revoke").unwrap().name,
+                        commands.get("revoke").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            revoke_device(parts[1], rd.clone(), wt.clone()).await;
+        }
+        "status" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("status").unwrap().name,
+                        commands.get("status").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            set_status(parts[1], &parts[2..].join(" "), rd.clone(), wt.clone()).await;
+        }
+        "receipts" => show_receipts().await,
+        "handle" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("handle").unwrap().name,
+                        commands.get("handle").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            set_handle(parts[1], rd.clone(), wt.clone()).await;
+        }
+        "whois" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("whois").unwrap().name,
+                        commands.get("whois").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            whois(parts[1], rd.clone(), wt.clone()).await;
+        }
+        "dm" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("dm").unwrap().name,
+                        commands.get("dm").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            if let Some(session) = dm_handle(parts[1], rd.clone(), wt.clone()).await {
+                open_session(session, "Opened DM with", rd.clone(), wt.clone()).await;
+            }
+        }
+        "requests" => list_contact_requests(rd.clone(), wt.clone()).await,
+        "accept" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("accept").unwrap().name,
+                        commands.get("accept").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            if let Some(session) = accept_contact(parts[1], rd.clone(), wt.clone()).await {
+                open_session(
+                    session,
+                    "Accepted contact request from",
+                    rd.clone(),
+                    wt.clone(),
+                )
+                .await;
+            }
+        }
+        "reject" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("reject").unwrap().name,
+                        commands.get("reject").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            reject_contact(parts[1], rd.clone(), wt.clone()).await;
+        }
+        "block" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("block").unwrap().name,
+                        commands.get("block").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            block_user(parts[1], true, rd.clone(), wt.clone()).await;
+        }
+        "unblock" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("unblock").unwrap().name,
+                        commands.get("unblock").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            block_user(parts[1], false, rd.clone(), wt.clone()).await;
+        }
+        "blocked" => list_blocked(rd.clone(), wt.clone()).await,
+        "ignore" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("ignore").unwrap().name,
+                        commands.get("ignore").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            ignore_user(parts[1], true).await;
+        }
+        "unignore" => {
+            if parts.len() < 2 {
+                let _ = LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "{}: {}",
+                        commands.get("unignore").unwrap().name,
+                        commands.get("unignore").unwrap().desc
+                    ),
+                    0,
+                )
+                .await;
+                return;
+            }
+            ignore_user(parts[1], false).await;
+        }
         "my-id" => {
             let config = data::CLIENT_CONFIG.lock().await;
             match config.as_ref() {
@@ -210,3 +835,33 @@ pub async fn process_command(cmd: &str, rd: StreamReader, wt: StreamWriter) {
         }
     }
 }
+
+/// Lists a session we just entered and makes it the active one
+/// The members of groups and channels are loaded along with their roles
+async fn open_session(session: Session, done: &str, rd: StreamReader, wt: StreamWriter) {
+    data::SESSIONS
+        .lock()
+        .await
+        .insert(session.id.clone(), session.clone());
+    flush_pending_messages(&session).await;
+    if matches!(session.mode, ChatMode::Group(_) | ChatMode::Channel(_)) {
+        load_group_members(&session.id, rd, wt).await;
+    }
+
+    *data::ACTIVE_SESSION.lock().await = Some(session.clone());
+    update_session(session.clone());
+    mark_session_read(&session.id).await;
+    let awaiting = data::APP_STATE
+        .lock()
+        .unwrap()
+        .awaiting
+        .contains(&session.id);
+    let message = match awaiting {
+        true => format!(
+            "Contact request sent to {}, you can message once they accept it",
+            session.name
+        ),
+        false => format!("{} {} ({})", done, session.name, &session.id[..8]),
+    };
+    LogMessage::log(LogLevel::INFO, message, 5).await;
+}
@@ -1,6 +1,10 @@
 use crate::{
-    data::SESSIONS,
-    types::{LogLevel, LogMessage, Session},
+    data::{ACTIVE_SESSION, APP_STATE, MESSAGES, SESSIONS},
+    handlers::{
+        load_group_members, mark_session_read,
+        task::{flush_pending_messages, read_response},
+    },
+    types::{LogLevel, LogMessage, Session, app::set_awaiting},
 };
 use common::{
     net::{ChatMessageKind, Packet, StreamReader, StreamWriter},
@@ -41,10 +45,26 @@ pub async fn new_session(input: &str, rd: StreamReader, wt: StreamWriter) -> Opt
         mode: session.mode.clone(),
         algo: session.encryption.algo.clone(),
     };
+    let new_session = request_session(&new_session_payload, rd, wt).await?;
+
+    session.id = new_session.id;
+    session.encryption.encryption_key = Some(new_session.session_key);
+
+    Some(session)
+}
 
+/// ### Sends the `new` command to the server.
+///
+/// Returns the id and key of the session on success,
+/// errors are reported through [`LogMessage`].
+pub(crate) async fn request_session(
+    new_session_payload: &NewSessionPayload,
+    rd: StreamReader,
+    wt: StreamWriter,
+) -> Option<NewSessionResponse> {
     let packet = Packet {
         kind: ChatMessageKind::Command("new".to_string()),
-        payload: match bincode::encode_to_vec(&new_session_payload, bincode::config::standard()) {
+        payload: match bincode::encode_to_vec(new_session_payload, bincode::config::standard()) {
             Ok(vec) => vec,
             Err(e) => {
                 let _ = LogMessage::log(LogLevel::ERROR, format!("Something went wrong: {}", e), 5)
@@ -64,7 +84,7 @@ pub async fn new_session(input: &str, rd: StreamReader, wt: StreamWriter) -> Opt
         return None;
     }
 
-    let response: ServerResponse = match netutils::read_packet(rd.clone()).await {
+    let response: ServerResponse = match read_response(rd.clone()).await {
         Ok(packet) => packet,
         Err(err) => {
             let _ = LogMessage::log(
@@ -110,11 +130,72 @@ pub async fn new_session(input: &str, rd: StreamReader, wt: StreamWriter) -> Opt
                 return None;
             }
         };
+    set_awaiting(&new_session.id, new_session.pending);
 
-    session.id = new_session.id;
-    session.encryption.encryption_key = Some(new_session.session_key);
+    Some(new_session)
+}
 
-    Some(session)
+/// ### Re-joins every known session after a reconnect.
+///
+/// The `new` command is sent again for each session in [`SESSIONS`],
+/// and the session key is updated in case the server issued a new one.
+pub async fn restore_sessions(rd: StreamReader, wt: StreamWriter) {
+    let sessions: Vec<Session> = SESSIONS.lock().await.values().cloned().collect();
+    if sessions.is_empty() {
+        return;
+    }
+
+    let mut restored = 0;
+    for mut session in sessions {
+        let payload = NewSessionPayload {
+            id: session.target_id.clone(),
+            mode: session.mode.clone(),
+            algo: session.encryption.algo.clone(),
+        };
+        let Some(response) = request_session(&payload, rd.clone(), wt.clone()).await else {
+            continue;
+        };
+
+        // A DM that was dropped by the server can come back with a new id
+        let old_id = session.id.clone();
+        session.id = response.id;
+        session.encryption.encryption_key = Some(response.session_key);
+        {
+            let mut sessions = SESSIONS.lock().await;
+            sessions.remove(&old_id);
+            sessions.insert(session.id.clone(), session.clone());
+        }
+        if old_id != session.id {
+            let mut messages = MESSAGES.lock().await;
+            if let Some(list) = messages.remove(&old_id) {
+                messages.insert(session.id.clone(), list);
+            }
+        }
+        flush_pending_messages(&session).await;
+        if matches!(session.mode, ChatMode::Group(_) | ChatMode::Channel(_)) {
+            load_group_members(&session.id, rd.clone(), wt.clone()).await;
+        }
+
+        let mut active_session = ACTIVE_SESSION.lock().await;
+        if active_session.as_ref().is_some_and(|s| s.id == old_id) {
+            *active_session = Some(session.clone());
+            APP_STATE.lock().unwrap().active_session = Some(session.id.clone());
+            mark_session_read(&session.id).await;
+        }
+        restored += 1;
+    }
+
+    let total = SESSIONS.lock().await.len();
+    let level = match restored == total {
+        true => LogLevel::INFO,
+        false => LogLevel::ERROR,
+    };
+    LogMessage::log(
+        level,
+        format!("Restored {}/{} sessions", restored, total),
+        5,
+    )
+    .await;
 }
 
 pub async fn get_session(key: &str) -> Option<Session> {
@@ -168,6 +249,7 @@ async fn parse_connection_file(path: &PathBuf) -> Option<Session> {
         Some(t) => match t.as_str() {
             "dm" => ChatMode::Dm(name.clone()),
             "group" => ChatMode::Group(name.clone()),
+            "channel" => ChatMode::Channel(name.clone()),
             _ => {
                 let _ =
                     LogMessage::log(LogLevel::ERROR, format!("Unknown connection type"), 5).await;
@@ -204,6 +286,7 @@ async fn parse_connection_file(path: &PathBuf) -> Option<Session> {
     };
 
     let new_session = Session {
+        target_id: id.clone(),
         id,
         mode,
         name,
@@ -0,0 +1,233 @@
+use crate::{
+    data,
+    handlers::{drop_session, open_dm, task::send_command},
+    types::{LogLevel, LogMessage, Session, app::set_awaiting},
+};
+use common::{
+    net::{StreamReader, StreamWriter},
+    types::{ContactEvent, ContactEventKind, ContactPayload, UserListResponse},
+};
+
+/// ### Handles a contact request made, accepted or rejected by another user.
+///
+/// Requests are kept until we accept or reject them,
+/// a rejected DM is dropped from the session list.
+pub async fn process_contact_event(event: ContactEvent) {
+    let name = event
+        .username
+        .clone()
+        .unwrap_or_else(|| short_id(&event.user_id));
+
+    let message = match event.kind {
+        ContactEventKind::Requested => {
+            data::APP_STATE
+                .lock()
+                .unwrap()
+                .contact_requests
+                .insert(event.user_id.clone(), event.username);
+            format!(
+                "{} wants to message you: accept {} or reject {}",
+                name,
+                short_id(&event.user_id),
+                short_id(&event.user_id)
+            )
+        }
+        ContactEventKind::Accepted => {
+            set_awaiting(&event.dm_id, false);
+            format!("{} accepted your contact request", name)
+        }
+        ContactEventKind::Rejected => {
+            set_awaiting(&event.dm_id, false);
+            drop_session(&event.dm_id).await;
+            format!("{} rejected your contact request", name)
+        }
+    };
+    LogMessage::log(LogLevel::INFO, message, 10).await;
+}
+
+/// ### Lists the users waiting for us to accept their contact request.
+pub async fn list_contact_requests(rd: StreamReader, wt: StreamWriter) {
+    let Some(list) = user_list("requests", rd, wt).await else {
+        return;
+    };
+
+    data::APP_STATE.lock().unwrap().contact_requests = list
+        .users
+        .iter()
+        .map(|user| (user.user_id.clone(), user.username.clone()))
+        .collect();
+
+    let message = match list.users.is_empty() {
+        true => "No contact requests".to_string(),
+        false => format!("Contact requests: {}", describe(&list)),
+    };
+    LogMessage::log(LogLevel::INFO, message, 0).await;
+}
+
+/// ### Accepts the contact request of a user by opening the DM session.
+pub async fn accept_contact(user: &str, rd: StreamReader, wt: StreamWriter) -> Option<Session> {
+    let user_id = resolve_user(user);
+    let name = data::APP_STATE
+        .lock()
+        .unwrap()
+        .contact_requests
+        .remove(&user_id)
+        .flatten()
+        .unwrap_or_else(|| short_id(&user_id));
+
+    open_dm(&user_id, &name, rd, wt).await
+}
+
+/// ### Rejects the contact request of a user, it can ask again later.
+pub async fn reject_contact(user: &str, rd: StreamReader, wt: StreamWriter) {
+    let user_id = resolve_user(user);
+    if contact_command("reject", &user_id, rd, wt).await {
+        data::APP_STATE
+            .lock()
+            .unwrap()
+            .contact_requests
+            .remove(&user_id);
+        LogMessage::log(LogLevel::INFO, "Contact request rejected".into(), 5).await;
+    }
+}
+
+/// ### Blocks or unblocks a user.
+///
+/// A blocked user can't message us nor add us to groups,
+/// enforced by the server on every device.
+pub async fn block_user(user: &str, block: bool, rd: StreamReader, wt: StreamWriter) {
+    let user_id = resolve_user(user);
+    let cmd = match block {
+        true => "block",
+        false => "unblock",
+    };
+    if contact_command(cmd, &user_id, rd, wt).await {
+        let done = match block {
+            true => "blocked",
+            false => "unblocked",
+        };
+        LogMessage::log(
+            LogLevel::INFO,
+            format!("{} {}", short_id(&user_id), done),
+            5,
+        )
+        .await;
+    }
+}
+
+/// ### Lists the users we blocked.
+pub async fn list_blocked(rd: StreamReader, wt: StreamWriter) {
+    let Some(list) = user_list("blocked", rd, wt).await else {
+        return;
+    };
+    let message = match list.users.is_empty() {
+        true => "No blocked users".to_string(),
+        false => format!("Blocked: {}", describe(&list)),
+    };
+    LogMessage::log(LogLevel::INFO, message, 0).await;
+}
+
+/// ### Hides or shows again the messages of a member in groups.
+///
+/// Only applies to this device, the member isn't told.
+pub async fn ignore_user(user: &str, ignore: bool) {
+    let user_id = resolve_user(user);
+    {
+        let mut app = data::APP_STATE.lock().unwrap();
+        match ignore {
+            true => app.ignored.insert(user_id.clone()),
+            false => app.ignored.remove(&user_id),
+        };
+    }
+
+    let message = match ignore {
+        true => format!("Messages of {} are hidden in groups", short_id(&user_id)),
+        false => format!("Messages of {} are shown again", short_id(&user_id)),
+    };
+    LogMessage::log(LogLevel::INFO, message, 5).await;
+}
+
+/// Sends a command targeting a user, errors are logged
+async fn contact_command(cmd: &str, user_id: &str, rd: StreamReader, wt: StreamWriter) -> bool {
+    let request = ContactPayload {
+        user_id: user_id.to_string(),
+    };
+    let payload = match bincode::encode_to_vec(&request, bincode::config::standard()) {
+        Ok(vec) => vec,
+        Err(e) => {
+            LogMessage::log(LogLevel::ERROR, format!("Something went wrong: {}", e), 5).await;
+            return false;
+        }
+    };
+
+    let Some(response) = send_command(cmd, payload, rd, wt).await else {
+        return false;
+    };
+    if !response.success {
+        LogMessage::log(
+            LogLevel::ERROR,
+            format!("{} failed: {}", cmd, response.error.unwrap_or_default()),
+            5,
+        )
+        .await;
+    }
+    response.success
+}
+
+/// Sends a command answered with a list of users
+async fn user_list(cmd: &str, rd: StreamReader, wt: StreamWriter) -> Option<UserListResponse> {
+    let response = send_command(cmd, Vec::new(), rd, wt).await?;
+    if !response.success {
+        LogMessage::log(
+            LogLevel::ERROR,
+            format!("{} failed: {}", cmd, response.error.unwrap_or_default()),
+            5,
+        )
+        .await;
+        return None;
+    }
+
+    match response
+        .payload
+        .map(|payload| bincode::decode_from_slice(&payload, bincode::config::standard()))
+    {
+        Some(Ok((list, _))) => Some(list),
+        _ => {
+            LogMessage::log(LogLevel::ERROR, format!("Invalid {} response", cmd), 5).await;
+            None
+        }
+    }
+}
+
+/// Expands the start of a user ID to the pending request or known user it matches
+fn resolve_user(input: &str) -> String {
+    let app = data::APP_STATE.lock().unwrap();
+    let mut matches: Vec<&String> = app
+        .contact_requests
+        .keys()
+        .chain(app.presence.keys())
+        .filter(|user_id| user_id.starts_with(input))
+        .collect();
+    matches.sort();
+    matches.dedup();
+
+    match matches.as_slice() {
+        [user_id] => user_id.to_string(),
+        _ => input.to_string(),
+    }
+}
+
+fn describe(list: &UserListResponse) -> String {
+    list.users
+        .iter()
+        .map(|user| match &user.username {
+            Some(name) => format!("{} ({})", name, short_id(&user.user_id)),
+            None => short_id(&user.user_id),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+fn short_id(user_id: &str) -> String {
+    user_id.chars().take(8).collect()
+}
@@ -0,0 +1,97 @@
+use crate::{
+    handlers::task::send_command,
+    types::{LogLevel, LogMessage},
+};
+use chrono::DateTime;
+use common::{
+    net::{StreamReader, StreamWriter},
+    types::{DeviceListResponse, RevokeDevicePayload},
+};
+
+/// ### Lists the devices currently logged in with this identity.
+///
+/// Devices are logged in connection order, the current device is marked.
+pub async fn list_devices(rd: StreamReader, wt: StreamWriter) {
+    let Some(response) = send_command("devices", Vec::new(), rd, wt).await else {
+        return;
+    };
+    if !response.success {
+        LogMessage::log(
+            LogLevel::ERROR,
+            format!(
+                "Failed to list devices: {}",
+                response.error.unwrap_or_default()
+            ),
+            5,
+        )
+        .await;
+        return;
+    }
+
+    let list: DeviceListResponse = match response
+        .payload
+        .map(|payload| bincode::decode_from_slice(&payload, bincode::config::standard()))
+    {
+        Some(Ok((list, _))) => list,
+        _ => {
+            LogMessage::log(LogLevel::ERROR, "Invalid devices response".into(), 5).await;
+            return;
+        }
+    };
+
+    let devices: Vec<String> = list
+        .devices
+        .iter()
+        .map(|device| {
+            let since = DateTime::from_timestamp(device.connected_at as i64, 0)
+                .map(|time| time.format("%d %b %-H:%M").to_string())
+                .unwrap_or_default();
+            match device.current {
+                true => format!("{} (this device, since {})", device.device_id, since),
+                false => format!("{} (since {})", device.device_id, since),
+            }
+        })
+        .collect();
+    LogMessage::log(
+        LogLevel::INFO,
+        format!("Devices: {}", devices.join(", ")),
+        0,
+    )
+    .await;
+}
+
+/// ### Revokes another device of this identity.
+///
+/// The device is disconnected and can't log in again with the same id.
+pub async fn revoke_device(device_id: &str, rd: StreamReader, wt: StreamWriter) {
+    let request = RevokeDevicePayload {
+        device_id: device_id.to_string(),
+    };
+    let payload = match bincode::encode_to_vec(&request, bincode::config::standard()) {
+        Ok(vec) => vec,
+        Err(e) => {
+            LogMessage::log(LogLevel::ERROR, format!("Something went wrong: {}", e), 5).await;
+            return;
+        }
+    };
+
+    let Some(response) = send_command("revoke", payload, rd, wt).await else {
+        return;
+    };
+    match response.success {
+        true => {
+            LogMessage::log(LogLevel::INFO, format!("Device {} revoked", device_id), 5).await;
+        }
+        false => {
+            LogMessage::log(
+                LogLevel::ERROR,
+                format!(
+                    "Failed to revoke device: {}",
+                    response.error.unwrap_or_default()
+                ),
+                5,
+            )
+            .await;
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use crate::{
+    handlers::{request_session, task::send_command},
+    types::{LogLevel, LogMessage, Session},
+};
+use common::{
+    net::{StreamReader, StreamWriter},
+    types::{
+        ChatMode, ClaimHandlePayload, EncryptionConfig, NewSessionPayload, SymmetricAlgo,
+        WhoisPayload, WhoisResponse,
+    },
+    utils::enc::{parse_public_key, public_key_to_user_id},
+};
+
+/// ### Claims a handle, or changes ours.
+///
+/// Other users can find us with `whois` and start a DM with `dm`.
+pub async fn set_handle(handle: &str, rd: StreamReader, wt: StreamWriter) {
+    let request = ClaimHandlePayload {
+        handle: handle.trim_start_matches('@').to_string(),
+    };
+    let payload = match bincode::encode_to_vec(&request, bincode::config::standard()) {
+        Ok(vec) => vec,
+        Err(e) => {
+            LogMessage::log(LogLevel::ERROR, format!("Something went wrong: {}", e), 5).await;
+            return;
+        }
+    };
+
+    let Some(response) = send_command("handle", payload, rd, wt).await else {
+        return;
+    };
+    match response.success {
+        true => {
+            LogMessage::log(
+                LogLevel::INFO,
+                format!("You are now @{}", request.handle.to_lowercase()),
+                5,
+            )
+            .await;
+        }
+        false => {
+            LogMessage::log(
+                LogLevel::ERROR,
+                format!(
+                    "Failed to claim handle: {}",
+                    response.error.unwrap_or_default()
+                ),
+                5,
+            )
+            .await;
+        }
+    }
+}
+
+/// ### Shows the user id and public key a handle is registered to.
+pub async fn whois(handle: &str, rd: StreamReader, wt: StreamWriter) {
+    if let Some(entry) = lookup_handle(handle, rd, wt).await {
+        LogMessage::log(
+            LogLevel::INFO,
+            format!(
+                "@{}: user id {}, key {}",
+                entry.handle, entry.user_id, entry.public_key
+            ),
+            0,
+        )
+        .await;
+    }
+}
+
+/// ### Opens a DM session with the user a handle is registered to.
+///
+/// Returns the session on success, errors are reported through [`LogMessage`].
+pub async fn dm_handle(handle: &str, rd: StreamReader, wt: StreamWriter) -> Option<Session> {
+    let entry = lookup_handle(handle, rd.clone(), wt.clone()).await?;
+    open_dm(&entry.user_id, &entry.handle, rd, wt).await
+}
+
+/// ### Opens a DM session with a user, shown under `name`.
+///
+/// The first DM with a user is a contact request until they accept it,
+/// opening a DM we were asked to join accepts it.
+pub async fn open_dm(
+    user_id: &str,
+    name: &str,
+    rd: StreamReader,
+    wt: StreamWriter,
+) -> Option<Session> {
+    let algo = SymmetricAlgo::AES256;
+    let payload = NewSessionPayload {
+        id: user_id.to_string(),
+        mode: ChatMode::Dm(name.to_string()),
+        algo: algo.clone(),
+    };
+    let response = request_session(&payload, rd, wt).await?;
+
+    Some(Session {
+        name: name.to_string(),
+        encryption: EncryptionConfig {
+            algo,
+            encryption_key: Some(response.session_key),
+        },
+        mode: ChatMode::Dm(name.to_string()),
+        id: response.id,
+        target_id: user_id.to_string(),
+    })
+}
+
+/// Asks the server who a handle is registered to, and checks that the key matches the user id
+async fn lookup_handle(handle: &str, rd: StreamReader, wt: StreamWriter) -> Option<WhoisResponse> {
+    let request = WhoisPayload {
+        handle: handle.trim_start_matches('@').to_string(),
+    };
+    let payload = match bincode::encode_to_vec(&request, bincode::config::standard()) {
+        Ok(vec) => vec,
+        Err(e) => {
+            LogMessage::log(LogLevel::ERROR, format!("Something went wrong: {}", e), 5).await;
+            return None;
+        }
+    };
+
+    let response = send_command("whois", payload, rd, wt).await?;
+    if !response.success {
+        LogMessage::log(
+            LogLevel::ERROR,
+            format!("whois failed: {}", response.error.unwrap_or_default()),
+            5,
+        )
+        .await;
+        return None;
+    }
+
+    let entry: WhoisResponse = match response
+        .payload
+        .map(|payload| bincode::decode_from_slice(&payload, bincode::config::standard()))
+    {
+        Some(Ok((entry, _))) => entry,
+        _ => {
+            LogMessage::log(LogLevel::ERROR, "Invalid whois response".into(), 5).await;
+            return None;
+        }
+    };
+
+    let matches = parse_public_key(&entry.public_key)
+        .is_ok_and(|key| public_key_to_user_id(&key) == entry.user_id);
+    if !matches {
+        LogMessage::log(
+            LogLevel::ERROR,
+            format!("The key of @{} doesn't match its user id", entry.handle),
+            5,
+        )
+        .await;
+        return None;
+    }
+    Some(entry)
+}
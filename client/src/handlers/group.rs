@@ -2,12 +2,18 @@ use std::path::PathBuf;
 
 use config::{Config, File};
 
-use crate::types::{LogLevel, LogMessage, Session};
+use crate::{
+    data,
+    handlers::task::{read_response, send_command},
+    types::{LogLevel, LogMessage, Session},
+};
 use common::{
     net::{ChatMessageKind, Packet, StreamReader, StreamWriter},
     types::{
-        ChatMode, EncryptionConfig, NewGroupPayload, NewGroupResponse, ServerResponse,
-        SymmetricAlgo,
+        AddGroupMemberPayload, ChatMode, CreateInvitePayload, EncryptionConfig, GroupEvent,
+        GroupEventKind, GroupMemberInfo, GroupMemberPayload, GroupMembersResponse, GroupPayload,
+        GroupRole, GroupRolePayload, InviteResponse, JoinGroupPayload, JoinGroupResponse,
+        NewGroupPayload, NewGroupResponse, RenameGroupPayload, ServerResponse, SymmetricAlgo,
     },
     utils::{file::resolve_path, net as netutils},
 };
@@ -65,7 +71,7 @@ pub async fn create_new_group(
         return None;
     }
 
-    let response: ServerResponse = match netutils::read_packet(rd.clone()).await {
+    let response: ServerResponse = match read_response(rd.clone()).await {
         Ok(resp) => resp,
         Err(e) => {
             let _ = LogMessage::log(
@@ -117,6 +123,7 @@ pub async fn create_new_group(
 
     Some(Session {
         name: payload.name.clone(),
+        target_id: group_info.group_id.clone(),
         id: group_info.group_id,
         mode: ChatMode::Group(payload.name.clone()),
         encryption: EncryptionConfig {
@@ -126,81 +133,727 @@ pub async fn create_new_group(
     })
 }
 
+/// ### Adds a member to the active group, moderators and above.
+///
+/// The member gets the group key pushed if it's connected, otherwise
+/// it joins with `new` like any other member.
 pub async fn add_group_member(member_id: &str, rd: StreamReader, wt: StreamWriter) {
-    let packet = Packet {
-        kind: ChatMessageKind::Command("addgpm".to_string()),
-        payload: match bincode::encode_to_vec(member_id, bincode::config::standard()) {
-            Ok(vec) => vec,
-            Err(e) => {
-                let _ = LogMessage::log(LogLevel::ERROR, format!("Something went wrong: {}", e), 5)
-                    .await;
+    let Some(group_id) = active_group_id().await else {
+        return;
+    };
+    let payload = AddGroupMemberPayload {
+        group_id,
+        member_id: member_id.to_string(),
+    };
+    if group_command("addgpm", payload, rd, wt).await {
+        LogMessage::log(LogLevel::INFO, format!("Added {}", short_id(member_id)), 5).await;
+    }
+}
+
+/// ### Creates an invite token to the active group, moderators and above.
+///
+/// `max_uses` and `expires_in` (e.g. `30m`, `12h`, `7d`) are optional,
+/// `-` leaves the uses unlimited. The token is logged so it can be shared.
+pub async fn create_invite(args: &[&str], rd: StreamReader, wt: StreamWriter) {
+    let max_uses = match args.first() {
+        None | Some(&"-") => None,
+        Some(uses) => match uses.parse::<u32>() {
+            Ok(uses) => Some(uses),
+            Err(_) => {
+                LogMessage::log(LogLevel::ERROR, format!("Invalid max uses: {}", uses), 5).await;
                 return;
             }
         },
     };
+    let expires_in = match args.get(1) {
+        None => None,
+        Some(expiry) => match parse_duration(expiry) {
+            Some(secs) => Some(secs),
+            None => {
+                LogMessage::log(LogLevel::ERROR, format!("Invalid expiry: {}", expiry), 5).await;
+                return;
+            }
+        },
+    };
+    let Some(group_id) = active_group_id().await else {
+        return;
+    };
 
-    if let Err(_) = netutils::write_packet(wt.clone(), packet).await {
-        let _ = LogMessage::log(
+    let request = CreateInvitePayload {
+        group_id,
+        max_uses,
+        expires_in,
+    };
+    let payload = match bincode::encode_to_vec(&request, bincode::config::standard()) {
+        Ok(vec) => vec,
+        Err(e) => {
+            LogMessage::log(LogLevel::ERROR, format!("Something went wrong: {}", e), 5).await;
+            return;
+        }
+    };
+    let Some(response) = send_command("invite", payload, rd, wt).await else {
+        return;
+    };
+    if !response.success {
+        LogMessage::log(
             LogLevel::ERROR,
-            format!("Something went wrong, pls check your network connection"),
+            format!(
+                "Failed to create invite: {}",
+                response.error.unwrap_or_default()
+            ),
             5,
         )
         .await;
+        return;
+    }
 
+    match response
+        .payload
+        .map(|payload| bincode::decode_from_slice(&payload, bincode::config::standard()))
+    {
+        Some(Ok((InviteResponse { token }, _))) => {
+            LogMessage::log(LogLevel::INFO, format!("Invite: {}", token), 0).await;
+        }
+        _ => LogMessage::log(LogLevel::ERROR, "Invalid invite response".into(), 5).await,
+    }
+}
+
+/// ### Joins a group with an invite token.
+///
+/// Returns the session of the group, the members are told we joined.
+pub async fn join_group(token: &str, rd: StreamReader, wt: StreamWriter) -> Option<Session> {
+    let request = JoinGroupPayload {
+        token: token.to_string(),
+    };
+    enter_group("join", request, rd, wt).await
+}
+
+/// ### Creates a broadcast channel we own.
+///
+/// Subscribers only read it, we can make some of them publishers with `role`.
+pub async fn create_channel(name: &str, rd: StreamReader, wt: StreamWriter) -> Option<Session> {
+    let request = NewGroupPayload {
+        name: name.to_string(),
+        group_id: None,
+        members: Vec::new(),
+    };
+    let payload = match bincode::encode_to_vec(&request, bincode::config::standard()) {
+        Ok(vec) => vec,
+        Err(e) => {
+            LogMessage::log(LogLevel::ERROR, format!("Something went wrong: {}", e), 5).await;
+            return None;
+        }
+    };
+    let response = send_command("mkch", payload, rd, wt).await?;
+    if !response.success {
+        LogMessage::log(
+            LogLevel::ERROR,
+            format!(
+                "Failed to create channel: {}",
+                response.error.unwrap_or_default()
+            ),
+            5,
+        )
+        .await;
+        return None;
+    }
+
+    let created: NewGroupResponse = match response
+        .payload
+        .map(|payload| bincode::decode_from_slice(&payload, bincode::config::standard()))
+    {
+        Some(Ok((created, _))) => created,
+        _ => {
+            LogMessage::log(LogLevel::ERROR, "Invalid channel response".into(), 5).await;
+            return None;
+        }
+    };
+    Some(group_session(
+        &created.group_id,
+        name,
+        &created.session_key,
+        true,
+    ))
+}
+
+/// ### Subscribes to a broadcast channel.
+///
+/// Returns the session of the channel, we can read it but not post.
+pub async fn subscribe_channel(
+    channel_id: &str,
+    rd: StreamReader,
+    wt: StreamWriter,
+) -> Option<Session> {
+    let request = GroupPayload {
+        group_id: channel_id.to_string(),
+    };
+    enter_group("sub", request, rd, wt).await
+}
+
+/// Unsubscribes from the active channel
+pub async fn unsubscribe_channel(rd: StreamReader, wt: StreamWriter) {
+    let Some(group_id) = active_group_id().await else {
         return;
+    };
+    let payload = GroupPayload {
+        group_id: group_id.clone(),
+    };
+    if group_command("unsub", payload, rd, wt).await {
+        drop_session(&group_id).await;
+        LogMessage::log(LogLevel::INFO, "Unsubscribed".into(), 5).await;
     }
+}
 
-    let response: ServerResponse = match netutils::read_packet(rd.clone()).await {
-        Ok(resp) => resp,
+/// Sends a command that makes us a member of a group, returns the session of the group
+async fn enter_group<T: bincode::Encode>(
+    cmd: &str,
+    request: T,
+    rd: StreamReader,
+    wt: StreamWriter,
+) -> Option<Session> {
+    let payload = match bincode::encode_to_vec(&request, bincode::config::standard()) {
+        Ok(vec) => vec,
         Err(e) => {
-            let _ = LogMessage::log(
-                LogLevel::ERROR,
-                format!("Failed to read response: {}", e),
-                5,
-            )
-            .await;
+            LogMessage::log(LogLevel::ERROR, format!("Something went wrong: {}", e), 5).await;
+            return None;
+        }
+    };
+    let response = send_command(cmd, payload, rd, wt).await?;
+    if !response.success {
+        LogMessage::log(
+            LogLevel::ERROR,
+            format!("{} failed: {}", cmd, response.error.unwrap_or_default()),
+            5,
+        )
+        .await;
+        return None;
+    }
+
+    let joined: JoinGroupResponse = match response
+        .payload
+        .map(|payload| bincode::decode_from_slice(&payload, bincode::config::standard()))
+    {
+        Some(Ok((joined, _))) => joined,
+        _ => {
+            LogMessage::log(LogLevel::ERROR, format!("Invalid {} response", cmd), 5).await;
+            return None;
+        }
+    };
+    Some(group_session(
+        &joined.group_id,
+        &joined.group_name,
+        &joined.session_key,
+        joined.channel,
+    ))
+}
+
+/// ### Removes a member from the active group.
+///
+/// Moderators and above can remove members with a lower role.
+pub async fn remove_group_member(member_id: &str, rd: StreamReader, wt: StreamWriter) {
+    let Some(group_id) = active_group_id().await else {
+        return;
+    };
+    let payload = GroupMemberPayload {
+        group_id,
+        member_id: member_id.to_string(),
+    };
+    if group_command("rmgpm", payload, rd, wt).await {
+        LogMessage::log(
+            LogLevel::INFO,
+            format!("Removed {}", short_id(member_id)),
+            5,
+        )
+        .await;
+    }
+}
+
+/// ### Leaves the active group.
+///
+/// The group is removed from the session list once the server accepted.
+pub async fn leave_group(rd: StreamReader, wt: StreamWriter) {
+    let Some(group_id) = active_group_id().await else {
+        return;
+    };
+    let payload = GroupPayload {
+        group_id: group_id.clone(),
+    };
+    if group_command("leave", payload, rd, wt).await {
+        drop_session(&group_id).await;
+        LogMessage::log(LogLevel::INFO, "You left the group".into(), 5).await;
+    }
+}
+
+/// Renames the active group, admins only
+pub async fn rename_group(name: &str, rd: StreamReader, wt: StreamWriter) {
+    let Some(group_id) = active_group_id().await else {
+        return;
+    };
+    let payload = RenameGroupPayload {
+        group_id,
+        name: name.to_string(),
+    };
+    if group_command("rngp", payload, rd, wt).await {
+        LogMessage::log(LogLevel::INFO, format!("Group renamed to {}", name), 5).await;
+    }
+}
+
+/// Deletes the active group along with its history, owner only
+pub async fn delete_group(rd: StreamReader, wt: StreamWriter) {
+    let Some(group_id) = active_group_id().await else {
+        return;
+    };
+    let payload = GroupPayload {
+        group_id: group_id.clone(),
+    };
+    if group_command("rmgp", payload, rd, wt).await {
+        drop_session(&group_id).await;
+        LogMessage::log(LogLevel::INFO, "Group deleted".into(), 5).await;
+    }
+}
+
+/// Promotes a member of the active group to admin, or makes an admin a plain member
+pub async fn set_group_admin(member_id: &str, admin: bool, rd: StreamReader, wt: StreamWriter) {
+    let Some(group_id) = active_group_id().await else {
+        return;
+    };
+    let payload = GroupMemberPayload {
+        group_id,
+        member_id: member_id.to_string(),
+    };
+    let (cmd, done) = match admin {
+        true => ("promote", "is now an admin"),
+        false => ("demote", "is no longer an admin"),
+    };
+    if group_command(cmd, payload, rd, wt).await {
+        LogMessage::log(
+            LogLevel::INFO,
+            format!("{} {}", short_id(member_id), done),
+            5,
+        )
+        .await;
+    }
+}
+
+/// ### Gives a member of the active group another role.
+///
+/// Only roles below ours can be given, to members below us.
+pub async fn set_group_role(member_id: &str, role: &str, rd: StreamReader, wt: StreamWriter) {
+    let role: GroupRole = match role.parse() {
+        Ok(role) => role,
+        Err(err) => {
+            LogMessage::log(LogLevel::ERROR, err, 5).await;
             return;
         }
     };
+    let Some(group_id) = active_group_id().await else {
+        return;
+    };
+    let payload = GroupRolePayload {
+        group_id,
+        member_id: member_id.to_string(),
+        role,
+    };
+    if group_command("role", payload, rd, wt).await {
+        LogMessage::log(
+            LogLevel::INFO,
+            format!("{} is now a {}", short_id(member_id), role),
+            5,
+        )
+        .await;
+    }
+}
+
+/// ### Replaces the session key of the active group, admins only.
+///
+/// The new key is pushed to every member, including us.
+pub async fn rekey_group(rd: StreamReader, wt: StreamWriter) {
+    let Some(group_id) = active_group_id().await else {
+        return;
+    };
+    if group_command("rekey", GroupPayload { group_id }, rd, wt).await {
+        LogMessage::log(LogLevel::INFO, "Group key replaced".into(), 5).await;
+    }
+}
+
+/// Lists the members of the active group with their roles
+pub async fn list_group_members(rd: StreamReader, wt: StreamWriter) {
+    let Some(group_id) = active_group_id().await else {
+        return;
+    };
+    let Some(members) = load_group_members(&group_id, rd, wt).await else {
+        return;
+    };
+
+    let user_id = data::CLIENT_CONFIG
+        .lock()
+        .await
+        .as_ref()
+        .map(|config| config.user_id.clone())
+        .unwrap_or_default();
+    let members: Vec<String> = members
+        .iter()
+        .map(|member| {
+            format!(
+                "{} ({})",
+                display_name(&member.user_id, &user_id),
+                member.role
+            )
+        })
+        .collect();
+    LogMessage::log(
+        LogLevel::INFO,
+        format!("Members: {}", members.join(", ")),
+        0,
+    )
+    .await;
+}
 
+/// ### Fetches the members of a group and remembers their roles.
+///
+/// Called when a group session is opened so the members list and
+/// the input know our role, returns the members highest role first.
+pub async fn load_group_members(
+    group_id: &str,
+    rd: StreamReader,
+    wt: StreamWriter,
+) -> Option<Vec<GroupMemberInfo>> {
+    let payload = GroupPayload {
+        group_id: group_id.to_string(),
+    };
+    let payload = bincode::encode_to_vec(&payload, bincode::config::standard()).ok()?;
+    let response = send_command("members", payload, rd, wt).await?;
     if !response.success {
-        let msg = response.error.clone();
-        let _ = LogMessage::log(
+        LogMessage::log(
             LogLevel::ERROR,
             format!(
-                "Failed to add group member: {}",
-                msg.unwrap_or_else(|| "".to_string())
+                "Failed to list members: {}",
+                response.error.unwrap_or_default()
             ),
             5,
         )
         .await;
-        return;
-    } else {
-        if let Some(payload) = response.payload {
-            let (msg, _): (String, usize) =
-                match bincode::decode_from_slice(&payload, bincode::config::standard()) {
-                    Ok(data) => data,
-                    Err(err) => {
-                        let _ = LogMessage::log(
-                            LogLevel::ERROR,
-                            format!("Something went wrong: {}", err),
-                            5,
-                        )
-                        .await;
-                        return;
-                    }
-                };
+        return None;
+    }
 
-            let _ = LogMessage::log(LogLevel::INFO, format!("{}", msg), 5).await;
-            return;
+    let list: GroupMembersResponse = match response
+        .payload
+        .map(|payload| bincode::decode_from_slice(&payload, bincode::config::standard()))
+    {
+        Some(Ok((list, _))) => list,
+        _ => {
+            LogMessage::log(LogLevel::ERROR, "Invalid members response".into(), 5).await;
+            return None;
         }
-        let _ = LogMessage::log(
+    };
+
+    let roles = list
+        .members
+        .iter()
+        .map(|member| (member.user_id.clone(), member.role))
+        .collect();
+    data::APP_STATE
+        .lock()
+        .unwrap()
+        .roles
+        .insert(group_id.to_string(), roles);
+    Some(list.members)
+}
+
+/// Makes another member the owner of the active group, we stay an admin
+pub async fn transfer_group(member_id: &str, rd: StreamReader, wt: StreamWriter) {
+    let Some(group_id) = active_group_id().await else {
+        return;
+    };
+    let payload = GroupMemberPayload {
+        group_id,
+        member_id: member_id.to_string(),
+    };
+    if group_command("transfer", payload, rd, wt).await {
+        LogMessage::log(
             LogLevel::INFO,
-            format!("Group member added successfully"),
+            format!("{} now owns the group", short_id(member_id)),
+            5,
+        )
+        .await;
+    }
+}
+
+/// ### Shows a change made to one of our groups.
+///
+/// The group is dropped from the session list once we are removed
+/// or it is deleted, renames and member changes are applied.
+pub async fn process_group_event(event: GroupEvent) {
+    let user_id = data::CLIENT_CONFIG
+        .lock()
+        .await
+        .as_ref()
+        .map(|config| config.user_id.clone())
+        .unwrap_or_default();
+    let actor = display_name(&event.actor_id, &user_id);
+    let group = &event.group_name;
+
+    let message = match &event.kind {
+        GroupEventKind::MemberAdded(member_id) => {
+            set_group_member(&event.group_id, member_id, true);
+            set_member_role(&event.group_id, member_id, Some(GroupRole::Member));
+            match *member_id == event.actor_id {
+                true => format!("{} joined {} with an invite", actor, group),
+                false => format!(
+                    "{} added {} to {}",
+                    actor,
+                    display_name(member_id, &user_id),
+                    group
+                ),
+            }
+        }
+        GroupEventKind::MemberRemoved(member_id) => {
+            if *member_id == user_id {
+                drop_session(&event.group_id).await;
+            }
+            set_group_member(&event.group_id, member_id, false);
+            set_member_role(&event.group_id, member_id, None);
+            format!(
+                "{} removed {} from {}",
+                actor,
+                display_name(member_id, &user_id),
+                group
+            )
+        }
+        GroupEventKind::MemberLeft => {
+            set_group_member(&event.group_id, &event.actor_id, false);
+            set_member_role(&event.group_id, &event.actor_id, None);
+            format!("{} left {}", actor, group)
+        }
+        GroupEventKind::Renamed => {
+            rename_group_session(&event.group_id, group).await;
+            format!("{} renamed the group to {}", actor, group)
+        }
+        GroupEventKind::Deleted => {
+            drop_session(&event.group_id).await;
+            format!("{} deleted {}", actor, group)
+        }
+        GroupEventKind::RoleChanged(member_id, role) => {
+            set_member_role(&event.group_id, member_id, Some(*role));
+            format!(
+                "{} made {} a {} of {}",
+                actor,
+                display_name(member_id, &user_id),
+                role,
+                group
+            )
+        }
+        GroupEventKind::OwnerChanged(member_id) => {
+            set_member_role(&event.group_id, member_id, Some(GroupRole::Owner));
+            set_member_role(&event.group_id, &event.actor_id, Some(GroupRole::Admin));
+            format!(
+                "{} made {} the owner of {}",
+                actor,
+                display_name(member_id, &user_id),
+                group
+            )
+        }
+        GroupEventKind::Joined {
+            session_key,
+            channel,
+        } => {
+            let session = group_session(&event.group_id, group, session_key, *channel);
+            data::SESSIONS
+                .lock()
+                .await
+                .insert(session.id.clone(), session);
+            format!("{} added you to {}", actor, group)
+        }
+        GroupEventKind::Rekeyed(session_key) => {
+            set_group_key(&event.group_id, session_key).await;
+            format!("{} replaced the key of {}", actor, group)
+        }
+    };
+
+    // Our own changes are already reported by the command
+    if event.actor_id != user_id {
+        LogMessage::log(LogLevel::INFO, message, 10).await;
+    }
+}
+
+/// Id of the active session if it's a group or channel, logs an error otherwise
+async fn active_group_id() -> Option<String> {
+    match data::ACTIVE_SESSION.lock().await.as_ref() {
+        Some(session) if matches!(session.mode, ChatMode::Group(_) | ChatMode::Channel(_)) => {
+            Some(session.id.clone())
+        }
+        _ => {
+            LogMessage::log(
+                LogLevel::ERROR,
+                "The active session isn't a group".into(),
+                5,
+            )
+            .await;
+            None
+        }
+    }
+}
+
+/// Sends a group command, logs the error and returns `false` if it failed
+async fn group_command<T: bincode::Encode>(
+    cmd: &str,
+    payload: T,
+    rd: StreamReader,
+    wt: StreamWriter,
+) -> bool {
+    let payload = match bincode::encode_to_vec(&payload, bincode::config::standard()) {
+        Ok(vec) => vec,
+        Err(e) => {
+            LogMessage::log(LogLevel::ERROR, format!("Something went wrong: {}", e), 5).await;
+            return false;
+        }
+    };
+
+    let Some(response) = send_command(cmd, payload, rd, wt).await else {
+        return false;
+    };
+    if !response.success {
+        LogMessage::log(
+            LogLevel::ERROR,
+            format!("{} failed: {}", cmd, response.error.unwrap_or_default()),
             5,
         )
         .await;
     }
+    response.success
+}
+
+/// Removes a group or DM we are no longer part of from the session list
+pub(crate) async fn drop_session(session_id: &str) {
+    data::SESSIONS.lock().await.remove(session_id);
+    data::MESSAGES.lock().await.remove(session_id);
+    {
+        let mut active = data::ACTIVE_SESSION.lock().await;
+        if active
+            .as_ref()
+            .is_some_and(|session| session.id == session_id)
+        {
+            *active = None;
+        }
+    }
+
+    let mut app = data::APP_STATE.lock().unwrap();
+    if app.active_session.as_deref() == Some(session_id) {
+        app.reset_session();
+    }
+    app.members.remove(session_id);
+    app.roles.remove(session_id);
+    app.unread.remove(session_id);
+}
+
+/// Shows a group under its new name
+async fn rename_group_session(group_id: &str, name: &str) {
+    let renamed = |session: &mut Session| {
+        session.name = name.to_string();
+        session.mode = group_mode(name, matches!(session.mode, ChatMode::Channel(_)));
+    };
+    if let Some(session) = data::SESSIONS.lock().await.get_mut(group_id) {
+        renamed(session);
+    }
+    if let Some(session) = data::ACTIVE_SESSION
+        .lock()
+        .await
+        .as_mut()
+        .filter(|session| session.id == group_id)
+    {
+        renamed(session);
+    }
+}
+
+/// Session of a group or channel we take part in
+fn group_session(group_id: &str, name: &str, session_key: &[u8], channel: bool) -> Session {
+    Session {
+        name: name.to_string(),
+        target_id: group_id.to_string(),
+        id: group_id.to_string(),
+        mode: group_mode(name, channel),
+        encryption: EncryptionConfig {
+            algo: SymmetricAlgo::AES256,
+            encryption_key: Some(session_key.to_vec()),
+        },
+    }
+}
+
+fn group_mode(name: &str, channel: bool) -> ChatMode {
+    match channel {
+        true => ChatMode::Channel(name.to_string()),
+        false => ChatMode::Group(name.to_string()),
+    }
+}
+
+/// Seconds in a duration like `90`, `30m`, `12h` or `7d`
+fn parse_duration(input: &str) -> Option<u64> {
+    let (value, unit) = match input.find(|c: char| !c.is_ascii_digit()) {
+        Some(index) => input.split_at(index),
+        None => (input, "s"),
+    };
+    let scale = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 24 * 3600,
+        _ => return None,
+    };
+    value.parse::<u64>().ok()?.checked_mul(scale)
+}
+
+/// Uses the new session key of a group for the messages we send and receive
+async fn set_group_key(group_id: &str, session_key: &[u8]) {
+    if let Some(session) = data::SESSIONS.lock().await.get_mut(group_id) {
+        session.encryption.encryption_key = Some(session_key.to_vec());
+    }
+    if let Some(session) = data::ACTIVE_SESSION
+        .lock()
+        .await
+        .as_mut()
+        .filter(|session| session.id == group_id)
+    {
+        session.encryption.encryption_key = Some(session_key.to_vec());
+    }
+}
+
+/// Sets the role of a member of a group, `None` once it left
+fn set_member_role(group_id: &str, member_id: &str, role: Option<GroupRole>) {
+    let mut app = data::APP_STATE.lock().unwrap();
+    let Some(roles) = app.roles.get_mut(group_id) else {
+        return;
+    };
+    match role {
+        Some(role) => roles.insert(member_id.to_string(), role),
+        None => roles.remove(member_id),
+    };
+}
+
+/// Adds or removes a member from the members shown for a group
+fn set_group_member(group_id: &str, member_id: &str, member: bool) {
+    let mut app = data::APP_STATE.lock().unwrap();
+    if let Some(members) = app.members.get_mut(group_id) {
+        members.retain(|id| id != member_id);
+        if member {
+            members.push(member_id.to_string());
+        }
+    }
+}
+
+/// Name shown for a user in group notifications
+fn display_name(user_id: &str, own_id: &str) -> String {
+    if user_id == own_id {
+        return "You".to_string();
+    }
+    let app = data::APP_STATE.lock().unwrap();
+    app.presence
+        .get(user_id)
+        .and_then(|update| update.username.clone())
+        .unwrap_or_else(|| short_id(user_id))
+}
+
+fn short_id(user_id: &str) -> String {
+    user_id.chars().take(8).collect()
 }
 
 pub fn parse_group_file(path: &PathBuf) -> Option<NewGroupPayload> {
@@ -0,0 +1,171 @@
+use crate::{
+    data::{APP_STATE, HISTORY, MESSAGES},
+    handlers::task::{is_ignored, read_response},
+    types::{LogLevel, LogMessage, Session},
+};
+use common::{
+    net::{ChatMessageKind, Packet, StreamReader, StreamWriter},
+    types::{HistoryCursor, HistoryRequest, HistoryResponse, Message},
+    utils::{enc::decrypt_message, net as netutils},
+};
+use std::sync::Arc;
+use tokio::sync::Mutex as AsyncMutex;
+
+/// Number of messages requested per page.
+const HISTORY_PAGE_SIZE: u32 = 50;
+
+/// ### Loads the messages preceding the oldest known message of a session.
+///
+/// The page is requested with the `history` command, decrypted with the
+/// session key and prepended to the message list. Once the server reports
+/// that no older messages are left, further calls only log it.
+pub async fn load_history(session: &Session, rd: StreamReader, wt: StreamWriter) {
+    let state = HISTORY
+        .lock()
+        .await
+        .get(&session.id)
+        .cloned()
+        .unwrap_or_default();
+    if state.complete {
+        LogMessage::log(LogLevel::INFO, "No older messages".into(), 2).await;
+        return;
+    }
+
+    // Continue from the last page, or from the oldest message received live
+    let before = match state.oldest_id {
+        Some(id) => Some(HistoryCursor::Id(id)),
+        None => oldest_timestamp(&session.id)
+            .await
+            .map(HistoryCursor::Timestamp),
+    };
+    let request = HistoryRequest {
+        session_id: session.id.clone(),
+        before,
+        limit: HISTORY_PAGE_SIZE,
+    };
+
+    let packet = Packet {
+        kind: ChatMessageKind::Command("history".to_string()),
+        payload: match bincode::encode_to_vec(&request, bincode::config::standard()) {
+            Ok(vec) => vec,
+            Err(e) => {
+                LogMessage::log(LogLevel::ERROR, format!("Something went wrong: {}", e), 5).await;
+                return;
+            }
+        },
+    };
+    if netutils::write_packet(wt.clone(), packet).await.is_err() {
+        LogMessage::log(
+            LogLevel::ERROR,
+            "Something went wrong, pls check your network connection".into(),
+            5,
+        )
+        .await;
+        return;
+    }
+
+    let response = match read_response(rd.clone()).await {
+        Ok(response) => response,
+        Err(e) => {
+            LogMessage::log(
+                LogLevel::ERROR,
+                format!("Failed to read response: {}", e),
+                5,
+            )
+            .await;
+            return;
+        }
+    };
+    if !response.success {
+        LogMessage::log(
+            LogLevel::ERROR,
+            format!(
+                "Failed to load history: {}",
+                response.error.unwrap_or_default()
+            ),
+            5,
+        )
+        .await;
+        return;
+    }
+    let page: HistoryResponse = match response
+        .payload
+        .map(|payload| bincode::decode_from_slice(&payload, bincode::config::standard()))
+    {
+        Some(Ok((page, _))) => page,
+        _ => {
+            LogMessage::log(LogLevel::ERROR, "Failed to decode history".into(), 5).await;
+            return;
+        }
+    };
+
+    {
+        let mut history = HISTORY.lock().await;
+        let state = history.entry(session.id.clone()).or_default();
+        if let Some(first) = page.messages.first() {
+            state.oldest_id = Some(first.id);
+        }
+        state.complete = !page.more;
+    }
+
+    let older: Vec<Message> = page
+        .messages
+        .into_iter()
+        .filter(|entry| !is_ignored(session, &entry.message.sender_id))
+        .filter_map(|entry| {
+            let mut msg = entry.message;
+            let content = decrypt_message(&msg.content, session.encryption.clone()).ok()?;
+            msg.content = content.into_bytes();
+            Some(msg)
+        })
+        .collect();
+    let added = prepend_messages(&session.id, older).await;
+
+    // Keep the selected message in place
+    {
+        let mut app = APP_STATE.lock().unwrap();
+        if app.active_session.as_deref() == Some(session.id.as_str())
+            && !app.msg_auto_scroll
+            && let Some(selected) = app.message_state.selected()
+        {
+            app.message_state.select(Some(selected + added));
+        }
+    }
+
+    let msg = match added {
+        0 => "No older messages".to_string(),
+        n => format!("Loaded {} older messages", n),
+    };
+    LogMessage::log(LogLevel::INFO, msg, 2).await;
+}
+
+/// Timestamp of the oldest message of a session
+async fn oldest_timestamp(session_id: &str) -> Option<u128> {
+    let list = MESSAGES.lock().await.get(session_id).cloned()?;
+    let messages = list.lock().await;
+    messages.iter().map(|msg| msg.timestamps).min()
+}
+
+/// Prepends messages that aren't in the list yet, returns how many were added
+async fn prepend_messages(session_id: &str, older: Vec<Message>) -> usize {
+    let list = MESSAGES
+        .lock()
+        .await
+        .entry(session_id.to_string())
+        .or_insert_with(|| Arc::new(AsyncMutex::new(Vec::new())))
+        .clone();
+    let mut messages = list.lock().await;
+
+    let older: Vec<Message> = older
+        .into_iter()
+        .filter(|msg| {
+            !messages
+                .iter()
+                .any(|m| m.sender_id == msg.sender_id && m.timestamps == msg.timestamps)
+        })
+        .collect();
+    let added = older.len();
+    messages.splice(0..0, older);
+
+    added
+}
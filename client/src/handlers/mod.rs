@@ -1,10 +1,22 @@
 pub mod client;
 pub mod connection;
+pub mod contact;
+pub mod device;
+pub mod directory;
 pub mod group;
+pub mod history;
+pub mod presence;
+pub mod receipt;
 pub mod task;
 pub mod cmd;
 
 pub use client::*;
 pub use connection::*;
+pub use contact::*;
+pub use device::*;
+pub use directory::*;
 pub use group::*;
+pub use history::*;
+pub use presence::*;
+pub use receipt::*;
 pub use cmd::*;
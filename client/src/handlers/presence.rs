@@ -0,0 +1,69 @@
+use crate::{
+    handlers::task::send_command,
+    types::{LogLevel, LogMessage},
+};
+use common::{
+    net::{StreamReader, StreamWriter},
+    types::{Presence, SetStatusPayload},
+};
+
+/// ### Sets our presence and status line.
+///
+/// `presence` is one of `online`, `away` or `dnd`, an empty `status`
+/// clears the status line. Users sharing a session with us are told
+/// about the change by the server.
+pub async fn set_status(presence: &str, status: &str, rd: StreamReader, wt: StreamWriter) {
+    let presence = match presence {
+        "online" => Presence::Online,
+        "away" => Presence::Away,
+        "dnd" => Presence::DoNotDisturb,
+        other => {
+            LogMessage::log(
+                LogLevel::ERROR,
+                format!("Unknown presence: {}, use online, away or dnd", other),
+                5,
+            )
+            .await;
+            return;
+        }
+    };
+    let request = SetStatusPayload {
+        presence,
+        status: match status.trim() {
+            "" => None,
+            status => Some(status.to_string()),
+        },
+    };
+    let payload = match bincode::encode_to_vec(&request, bincode::config::standard()) {
+        Ok(vec) => vec,
+        Err(e) => {
+            LogMessage::log(LogLevel::ERROR, format!("Something went wrong: {}", e), 5).await;
+            return;
+        }
+    };
+
+    let Some(response) = send_command("status", payload, rd, wt).await else {
+        return;
+    };
+    match response.success {
+        true => {
+            LogMessage::log(
+                LogLevel::INFO,
+                format!("Status set to {:?}", request.presence),
+                5,
+            )
+            .await;
+        }
+        false => {
+            LogMessage::log(
+                LogLevel::ERROR,
+                format!(
+                    "Failed to set status: {}",
+                    response.error.unwrap_or_default()
+                ),
+                5,
+            )
+            .await;
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{
+    data,
+    types::{AppConfig, LogLevel, LogMessage, PendingReceipt},
+};
+
+/// ### Tells the authors of the unread messages of a session that we read them.
+///
+/// Called when the session is opened, the receipts are dropped if the
+/// writer task isn't draining the queue.
+pub async fn mark_session_read(session_id: &str) {
+    let receipts = data::APP_STATE.lock().unwrap().take_unread(session_id);
+    queue_receipts(receipts).await;
+}
+
+/// Hands receipts to the writer task without waiting for it
+pub async fn queue_receipts(receipts: Vec<PendingReceipt>) {
+    if receipts.is_empty() {
+        return;
+    }
+    let receipt_tx = data::CHANNELS.lock().await.receipt_tx.clone();
+    let receipt_tx = receipt_tx.lock().await;
+    for receipt in receipts {
+        let _ = receipt_tx.try_send(receipt);
+    }
+}
+
+/// ### Shows which members received and read one of our messages.
+///
+/// Uses the selected message of the active session if it's ours,
+/// otherwise the last message we sent in it.
+pub async fn show_receipts() {
+    let summary = receipt_summary(&data::APP_STATE.lock().unwrap());
+    match summary {
+        Ok(summary) => LogMessage::log(LogLevel::INFO, summary, 0).await,
+        Err(err) => LogMessage::log(LogLevel::ERROR, err.into(), 5).await,
+    }
+}
+
+/// Lists the members of the active session by how far our message got to them
+fn receipt_summary(app: &AppConfig) -> Result<String, &'static str> {
+    let session_id = app
+        .active_session
+        .as_ref()
+        .ok_or("No Active Session Found!")?;
+
+    let own = |index: &usize| {
+        app.messages
+            .get(*index)
+            .is_some_and(|msg| msg.sender_id == app.user_id)
+    };
+    let receipt = app
+        .message_state
+        .selected()
+        .filter(own)
+        .or_else(|| (0..app.messages.len()).rev().find(own))
+        .and_then(|index| {
+            app.receipts
+                .get(&(session_id.clone(), app.messages[index].timestamps))
+        })
+        .ok_or("No receipts for your messages in this session")?;
+
+    let mut read = Vec::new();
+    let mut delivered = Vec::new();
+    let mut pending = Vec::new();
+    for member in app.members.get(session_id).into_iter().flatten() {
+        let name = app
+            .presence
+            .get(member)
+            .and_then(|update| update.username.clone())
+            .unwrap_or_else(|| member[..8].to_string());
+        if receipt.read.contains(member) {
+            read.push(name);
+        } else if receipt.delivered.contains(member) {
+            delivered.push(name);
+        } else {
+            pending.push(name);
+        }
+    }
+
+    let parts: Vec<String> = [
+        ("Read by", read),
+        ("Delivered to", delivered),
+        ("Not delivered to", pending),
+    ]
+    .into_iter()
+    .filter(|(_, names)| !names.is_empty())
+    .map(|(label, names)| format!("{} {}", label, names.join(", ")))
+    .collect();
+    match parts.is_empty() {
+        true => Ok(format!("Message {:?}", receipt.state)),
+        false => Ok(parts.join(" · ")),
+    }
+}
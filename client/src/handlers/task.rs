@@ -1,11 +1,20 @@
 use crate::{
     data,
-    handlers::process_command,
-    types::{LogLevel, LogMessage},
+    handlers::{process_command, process_contact_event, process_group_event},
+    types::{
+        DeliveryState, LogLevel, LogMessage, PendingReceipt, Session,
+        app::{
+            clear_typing, mark_unread, record_receipt, set_delivery_state, set_typing,
+            update_presence,
+        },
+    },
 };
 use common::{
     net::{ChatMessageKind, Packet, StreamReader, StreamWriter},
-    types::{ChatMode, Message},
+    types::{
+        ChatMode, GroupPermission, Message, RateLimitEvent, RateLimitKind, Receipt, ReceiptKind,
+        ServerEvent, ServerResponse,
+    },
     utils::{
         enc::{decrypt_message, encrypt_message},
         net::{read_packet, write_packet},
@@ -45,23 +54,89 @@ pub async fn start_reader_task(rd: StreamReader) -> JoinHandle<()> {
                 Err(_) => break,
             };
 
-            match packet.kind {
-                ChatMessageKind::DirectMessage(id) => {
-                    process_message(id.clone(), packet.payload.clone()).await;
-                }
-                ChatMessageKind::GroupMessage(id) => {
-                    process_message(id.clone(), packet.payload.clone()).await;
-                }
-                _ => (),
-            }
+            handle_packet(packet).await;
         }
     })
 }
 
+/// ### Reads packets until the response to the pending command arrives.
+///
+/// Messages and events received in the meantime are processed as
+/// the reader task would, so nothing is lost while a command runs.
+///
+/// # Errors
+///
+/// Returns an error if the stream can't be read or the response can't be decoded.
+pub async fn read_response(
+    rd: StreamReader,
+) -> Result<ServerResponse, Box<dyn std::error::Error + Send + Sync>> {
+    loop {
+        let packet = read_packet::<Packet>(rd.clone()).await?;
+        match packet.kind {
+            ChatMessageKind::Command(_) => {
+                let (response, _): (ServerResponse, usize) =
+                    bincode::decode_from_slice(&packet.payload, bincode::config::standard())?;
+                return Ok(response);
+            }
+            _ => handle_packet(packet).await,
+        }
+    }
+}
+
+/// ### Sends a command and waits for its response.
+///
+/// Errors are logged, `None` is returned if the command couldn't be sent
+/// or no response was read.
+pub async fn send_command(
+    cmd: &str,
+    payload: Vec<u8>,
+    rd: StreamReader,
+    wt: StreamWriter,
+) -> Option<ServerResponse> {
+    let packet = Packet {
+        kind: ChatMessageKind::Command(cmd.to_string()),
+        payload,
+    };
+    if write_packet(wt.clone(), packet).await.is_err() {
+        LogMessage::log(
+            LogLevel::ERROR,
+            "Something went wrong, pls check your network connection".into(),
+            5,
+        )
+        .await;
+        return None;
+    }
+
+    match read_response(rd.clone()).await {
+        Ok(response) => Some(response),
+        Err(e) => {
+            LogMessage::log(
+                LogLevel::ERROR,
+                format!("Failed to read response: {}", e),
+                5,
+            )
+            .await;
+            None
+        }
+    }
+}
+
+/// Dispatches a packet pushed by the server.
+async fn handle_packet(packet: Packet) {
+    match packet.kind {
+        ChatMessageKind::DirectMessage(id) | ChatMessageKind::GroupMessage(id) => {
+            process_message(id, packet.payload).await;
+        }
+        ChatMessageKind::Event => process_event(packet.payload).await,
+        ChatMessageKind::Receipt(id) => process_receipt(id, packet.payload).await,
+        ChatMessageKind::Command(_) | ChatMessageKind::Typing(_) => (),
+    }
+}
+
 /// ### Spawns a background task that continuously writes packets to the stream.
 ///
 /// This function acquires a lock on the given [`StreamWriter`] and runs
-/// an asynchronous loop to send outgoing messages to the remote peer.
+/// an asynchronous loop to send outgoing messages, typing signals and receipts to the remote peer.
 ///
 /// # Parameters
 ///
@@ -80,16 +155,30 @@ pub async fn start_reader_task(rd: StreamReader) -> JoinHandle<()> {
 /// [`StreamWriter`]: common::net::StreamWriter
 pub async fn start_writer_task(wt: StreamWriter) -> JoinHandle<()> {
     tokio::spawn(async move {
-        let msg_rx = {
+        let (msg_rx, typing_rx, receipt_rx) = {
             let channels = data::CHANNELS.lock().await;
-            channels.msg_rx.clone()
+            (
+                channels.msg_rx.clone(),
+                channels.typing_rx.clone(),
+                channels.receipt_rx.clone(),
+            )
         };
 
         loop {
-            match msg_rx.lock().await.recv().await {
-                Some(msg) => send_message(wt.clone(), &msg).await,
-                None => break,
-            };
+            tokio::select! {
+                msg = async { msg_rx.lock().await.recv().await } => match msg {
+                    Some(msg) => send_message(wt.clone(), &msg).await,
+                    None => break,
+                },
+                session_id = async { typing_rx.lock().await.recv().await } => match session_id {
+                    Some(session_id) => send_typing(wt.clone(), session_id).await,
+                    None => break,
+                },
+                receipt = async { receipt_rx.lock().await.recv().await } => match receipt {
+                    Some(receipt) => send_receipt(wt.clone(), receipt).await,
+                    None => break,
+                },
+            }
         }
     })
 }
@@ -110,7 +199,8 @@ pub async fn start_writer_task(wt: StreamWriter) -> JoinHandle<()> {
 /// # Returns
 ///
 /// A [`JoinHandle`] to the spawned task. The task runs until the command
-/// loop is terminated or the underlying connection fails.
+/// loop is terminated or the reader task stops because the underlying
+/// connection failed.
 ///
 /// # Notes
 ///
@@ -131,12 +221,16 @@ pub async fn start_command_task(
         };
 
         loop {
-            let command = match cmd_rx.lock().await.recv().await {
-                Some(cmd) => cmd,
-                None => {
-                    rd_task.abort();
-                    break;
-                }
+            let command = tokio::select! {
+                cmd = async { cmd_rx.lock().await.recv().await } => match cmd {
+                    Some(cmd) => cmd,
+                    None => {
+                        rd_task.abort();
+                        break;
+                    }
+                },
+                // The reader task only stops on its own when the connection is lost
+                _ = &mut rd_task => break,
             };
 
             rd_task.abort();
@@ -146,6 +240,85 @@ pub async fn start_command_task(
     })
 }
 
+/// Tells the members of a session that we are typing, unless disabled in the config
+async fn send_typing(wt: StreamWriter, session_id: String) {
+    let enabled = data::CLIENT_CONFIG
+        .lock()
+        .await
+        .as_ref()
+        .is_some_and(|config| config.typing);
+    if !enabled {
+        return;
+    }
+
+    let packet = Packet {
+        kind: ChatMessageKind::Typing(session_id),
+        payload: Vec::new(),
+    };
+    let _ = write_packet::<Packet>(wt, packet).await;
+}
+
+/// ### Tells the author of some messages that we received or read them.
+///
+/// Read receipts carry the message ids encrypted with the session key,
+/// they aren't sent when disabled in the config.
+async fn send_receipt(wt: StreamWriter, pending: PendingReceipt) {
+    let read_receipts = data::CLIENT_CONFIG
+        .lock()
+        .await
+        .as_ref()
+        .is_some_and(|config| config.read_receipts);
+    if pending.read && !read_receipts {
+        return;
+    }
+
+    let kind = match pending.read {
+        true => {
+            let Some(session) = data::SESSIONS
+                .lock()
+                .await
+                .get(&pending.session_id)
+                .cloned()
+            else {
+                return;
+            };
+            match encrypt_message(&pending.message_ids.join(","), session.encryption) {
+                Ok(ids) => ReceiptKind::Read(ids),
+                Err(_) => return,
+            }
+        }
+        false => ReceiptKind::Delivered(pending.message_ids),
+    };
+    let receipt = Receipt {
+        reader_id: String::new(),
+        author_id: pending.author_id,
+        kind,
+    };
+    let Ok(payload) = bincode::encode_to_vec(&receipt, bincode::config::standard()) else {
+        return;
+    };
+    let packet = Packet {
+        kind: ChatMessageKind::Receipt(pending.session_id),
+        payload,
+    };
+    let _ = write_packet::<Packet>(wt, packet).await;
+}
+
+/// Whether the messages of a member are hidden in a session, DMs are never hidden
+pub(crate) fn is_ignored(session: &Session, user_id: &str) -> bool {
+    !matches!(session.mode, ChatMode::Dm(_))
+        && data::APP_STATE.lock().unwrap().ignored.contains(user_id)
+}
+
+/// Whether our role allows posting in a session, roles of unknown groups aren't checked
+fn can_post(session_id: &str, user_id: &str) -> bool {
+    let app = data::APP_STATE.lock().unwrap();
+    app.roles
+        .get(session_id)
+        .and_then(|roles| roles.get(user_id))
+        .is_none_or(|role| role.can(GroupPermission::Post))
+}
+
 async fn send_message(wt: StreamWriter, input: &str) {
     let session = match data::ACTIVE_SESSION.lock().await.as_ref() {
         Some(session) => session.to_owned(),
@@ -162,9 +335,30 @@ async fn send_message(wt: StreamWriter, input: &str) {
         }
     };
 
+    if !can_post(&session.id, &client_config.user_id) {
+        LogMessage::log(LogLevel::ERROR, "You can't post in this group".into(), 5).await;
+        return;
+    }
+    let awaiting = data::APP_STATE
+        .lock()
+        .unwrap()
+        .awaiting
+        .contains(&session.id);
+    if awaiting {
+        LogMessage::log(
+            LogLevel::ERROR,
+            format!("{} hasn't accepted your contact request yet", session.name),
+            5,
+        )
+        .await;
+        return;
+    }
+
     let kind = match session.mode.clone() {
         ChatMode::Dm(_) => ChatMessageKind::DirectMessage(session.id.clone()),
-        ChatMode::Group(_) => ChatMessageKind::GroupMessage(session.id.clone()),
+        ChatMode::Group(_) | ChatMode::Channel(_) => {
+            ChatMessageKind::GroupMessage(session.id.clone())
+        }
     };
 
     let mut msg_data = Message {
@@ -176,9 +370,16 @@ async fn send_message(wt: StreamWriter, input: &str) {
             .duration_since(UNIX_EPOCH)
             .unwrap()
             .as_millis(),
+        message_id: None,
     };
 
     // Add the message into message list
+    set_delivery_state(
+        &session.id,
+        msg_data.timestamps,
+        DeliveryState::Sending,
+        None,
+    );
     update_msg_list(session.id.clone(), msg_data.clone()).await;
 
     match encrypt_message(input, session.encryption.clone()) {
@@ -190,6 +391,12 @@ async fn send_message(wt: StreamWriter, input: &str) {
             let payload = match bincode::encode_to_vec(&msg_data, bincode::config::standard()) {
                 Ok(payload) => payload,
                 Err(err) => {
+                    set_delivery_state(
+                        &session.id,
+                        msg_data.timestamps,
+                        DeliveryState::Failed,
+                        None,
+                    );
                     LogMessage::log(
                         LogLevel::ERROR,
                         format!("Failed to encode message: {}", err),
@@ -200,11 +407,22 @@ async fn send_message(wt: StreamWriter, input: &str) {
                 }
             };
             let packet = Packet { kind, payload };
-            let _ = write_packet::<Packet>(wt.clone(), packet).await;
-
-            {}
+            if write_packet::<Packet>(wt.clone(), packet).await.is_err() {
+                set_delivery_state(
+                    &session.id,
+                    msg_data.timestamps,
+                    DeliveryState::Failed,
+                    None,
+                );
+            }
         }
         Err(err) => {
+            set_delivery_state(
+                &session.id,
+                msg_data.timestamps,
+                DeliveryState::Failed,
+                None,
+            );
             LogMessage::log(
                 LogLevel::ERROR,
                 format!("Failed to encrypt message: {}", err),
@@ -216,7 +434,7 @@ async fn send_message(wt: StreamWriter, input: &str) {
 }
 
 async fn process_message(id: String, payload: Vec<u8>) {
-    let (mut msg, _): (Message, usize) =
+    let (msg, _): (Message, usize) =
         match bincode::decode_from_slice(&payload, bincode::config::standard()) {
             Ok(decoded) => decoded,
             Err(err) => {
@@ -232,9 +450,43 @@ async fn process_message(id: String, payload: Vec<u8>) {
 
     let session = match data::SESSIONS.lock().await.get(&id) {
         Some(session) => session.to_owned(),
-        None => return,
+        None => {
+            // Kept until the session is opened, e.g. a DM started while we were offline
+            let mut pending = data::PENDING_MESSAGES.lock().await;
+            let messages = pending.entry(id.clone()).or_default();
+            if messages.is_empty() {
+                let sender = match &msg.username {
+                    Some(name) => format!("{} ({})", name, msg.sender_id),
+                    None => msg.sender_id.clone(),
+                };
+                LogMessage::log(LogLevel::INFO, format!("New message from {}", sender), 10).await;
+            }
+            messages.push(msg);
+            return;
+        }
     };
 
+    clear_typing(&session.id, &msg.sender_id);
+    show_message(&session, msg).await;
+}
+
+/// ### Shows the messages received before the session was opened.
+///
+/// Called once the session is in [`SESSIONS`](crate::data::SESSIONS).
+pub async fn flush_pending_messages(session: &Session) {
+    let messages = data::PENDING_MESSAGES.lock().await.remove(&session.id);
+    for msg in messages.unwrap_or_default() {
+        show_message(session, msg).await;
+    }
+}
+
+/// Decrypts a received message and adds it to the message list of its session
+/// Messages of ignored members are dropped in groups
+async fn show_message(session: &Session, mut msg: Message) {
+    if is_ignored(session, &msg.sender_id) {
+        return;
+    }
+
     let decrypted_msg = match decrypt_message(&msg.content, session.encryption.clone()) {
         Ok(msg) => msg,
         Err(err) => {
@@ -251,7 +503,154 @@ async fn process_message(id: String, payload: Vec<u8>) {
     msg.content = decrypted_msg.into_bytes();
 
     // Update message list
-    update_msg_list(id.clone(), msg.clone()).await;
+    update_msg_list(session.id.clone(), msg.clone()).await;
+    acknowledge_message(session, &msg).await;
+}
+
+/// ### Acknowledges a message received from another member.
+///
+/// The author is told the message was delivered, and read if its session
+/// is being viewed. Otherwise it's read once the session is opened.
+async fn acknowledge_message(session: &Session, msg: &Message) {
+    let Some(message_id) = msg.message_id.clone() else {
+        return;
+    };
+    let own = data::CLIENT_CONFIG
+        .lock()
+        .await
+        .as_ref()
+        .is_some_and(|config| config.user_id == msg.sender_id);
+    if own {
+        return;
+    }
+
+    let viewed = data::ACTIVE_SESSION
+        .lock()
+        .await
+        .as_ref()
+        .is_some_and(|active| active.id == session.id);
+    if !viewed {
+        mark_unread(&session.id, &msg.sender_id, &message_id);
+    }
+
+    let receipt_tx = data::CHANNELS.lock().await.receipt_tx.clone();
+    let receipt_tx = receipt_tx.lock().await;
+    let mut receipt = PendingReceipt {
+        session_id: session.id.clone(),
+        author_id: msg.sender_id.clone(),
+        read: false,
+        message_ids: vec![message_id],
+    };
+    let _ = receipt_tx.send(receipt.clone()).await;
+    if viewed {
+        receipt.read = true;
+        let _ = receipt_tx.send(receipt).await;
+    }
+}
+
+/// ### Records a receipt for messages we sent.
+///
+/// Read receipts are decrypted with the key of the session they were sent in.
+async fn process_receipt(session_id: String, payload: Vec<u8>) {
+    let (receipt, _): (Receipt, usize) =
+        match bincode::decode_from_slice(&payload, bincode::config::standard()) {
+            Ok(decoded) => decoded,
+            Err(_) => return,
+        };
+
+    match receipt.kind {
+        ReceiptKind::Delivered(ids) => record_receipt(&receipt.reader_id, &ids, false),
+        ReceiptKind::Read(ids) => {
+            let Some(session) = data::SESSIONS.lock().await.get(&session_id).cloned() else {
+                return;
+            };
+            let Ok(ids) = decrypt_message(&ids, session.encryption) else {
+                return;
+            };
+            let ids: Vec<String> = ids.split(',').map(String::from).collect();
+            record_receipt(&receipt.reader_id, &ids, true);
+        }
+    }
+}
+
+/// Handles an event pushed by the server:
+/// records what the server did with a message we sent, a presence update, a typing signal,
+/// a change made to one of our groups, a contact request or a rate limit warning
+async fn process_event(payload: Vec<u8>) {
+    let (event, _): (ServerEvent, usize) =
+        match bincode::decode_from_slice(&payload, bincode::config::standard()) {
+            Ok(decoded) => decoded,
+            Err(_) => return,
+        };
+
+    match event {
+        ServerEvent::MessageStatus(status) => {
+            let state = match status.delivered + status.queued {
+                0 if status.dropped + status.refused > 0 => DeliveryState::Failed,
+                _ => DeliveryState::Sent,
+            };
+            set_delivery_state(
+                &status.session_id,
+                status.timestamps,
+                state,
+                Some(status.message_id.clone()),
+            );
+
+            if status.refused > 0 {
+                LogMessage::log(
+                    LogLevel::ERROR,
+                    "Message refused, your contact request wasn't accepted".into(),
+                    5,
+                )
+                .await;
+            } else if status.dropped > 0 {
+                LogMessage::log(
+                    LogLevel::ERROR,
+                    format!(
+                        "Message not delivered to {} recipient(s), their queue is full",
+                        status.dropped
+                    ),
+                    5,
+                )
+                .await;
+            } else if status.queued > 0 {
+                LogMessage::log(
+                    LogLevel::INFO,
+                    format!(
+                        "Message delivered to {}, queued for {} offline recipient(s)",
+                        status.delivered, status.queued
+                    ),
+                    5,
+                )
+                .await;
+            }
+        }
+        ServerEvent::Presence(update) => update_presence(update),
+        ServerEvent::Typing(event) => set_typing(event),
+        ServerEvent::Group(event) => process_group_event(event).await,
+        ServerEvent::Contact(event) => process_contact_event(event).await,
+        ServerEvent::RateLimit(event) => warn_rate_limit(event).await,
+    }
+}
+
+/// Tells the user the server is slowing us down, and how close it is to disconnecting us
+async fn warn_rate_limit(event: RateLimitEvent) {
+    let what = match event.limit {
+        RateLimitKind::Messages => "messages",
+        RateLimitKind::Commands => "commands",
+        RateLimitKind::Bytes => "data",
+    };
+    let message = match event.throttled {
+        true => format!(
+            "Too many {}, the server is slowing you down ({} warnings left before disconnecting)",
+            what, event.remaining
+        ),
+        false => format!(
+            "Sending {} too fast, slow down ({} warnings left before disconnecting)",
+            what, event.remaining
+        ),
+    };
+    LogMessage::log(LogLevel::ERROR, message, 5).await;
 }
 
 async fn update_msg_list(id: String, msg: Message) {
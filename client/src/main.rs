@@ -1,12 +1,11 @@
 use null_talk_client::{
     data,
     handlers::handle_client,
-    types::{LogLevel, LogMessage},
+    types::{ConnectionStatus, LogLevel, LogMessage, set_connection_status},
     ui::run_terminal,
-    utils::{configure_client, try_tls_handshake},
+    utils::{Backoff, configure_client, connect_to_server},
 };
 use std::env;
-use tokio::net::TcpStream;
 
 #[tokio::main]
 async fn main() {
@@ -17,67 +16,39 @@ async fn main() {
 
     // Create TCP connection thread
     let tcp = tokio::spawn(async move {
-        let (addr, host_name) = {
-            let config_lock = data::CLIENT_CONFIG.lock().await;
-            let config = match config_lock.as_ref() {
-                Some(cfg) => cfg,
-                None => {
-                    LogMessage::log(LogLevel::ERROR, "Configuration not found!".into(), 0).await;
-                    return;
-                }
-            };
-
-            (
-                format!("{}:{}", &config.hostname, &config.port),
-                config.hostname.clone(),
-            )
+        let config = match data::CLIENT_CONFIG.lock().await.clone() {
+            Some(cfg) => cfg,
+            None => {
+                LogMessage::log(LogLevel::ERROR, "Configuration not found!".into(), 0).await;
+                return;
+            }
         };
+        let addr = config.address();
 
-        match TcpStream::connect(addr.clone()).await {
-            Ok(stream) => {
-                LogMessage::log(
-                    LogLevel::INFO,
-                    format!("Establishing a secure TLS connection..."),
-                    0,
-                )
-                .await;
-                let result = try_tls_handshake(host_name, stream).await;
-                match result {
-                    Some(tls_stream) => {
-                        LogMessage::log(
-                            LogLevel::INFO,
-                            format!("Successfully connected to {}", addr),
-                            5,
-                        )
-                        .await;
-                        handle_client(tls_stream).await;
-                    }
-                    None => match TcpStream::connect(addr.clone()).await {
-                        Ok(plain_stream) => {
-                            LogMessage::log(
-                                LogLevel::INFO,
-                                format!("Successfully connected to {}, TLS not enabled", addr),
-                                5,
-                            )
-                            .await;
-                            handle_client(Box::new(plain_stream)).await;
-                        }
-                        Err(_) => {
-                            LogMessage::log(
-                                LogLevel::ERROR,
-                                format!("Failed to connect to {}", addr),
-                                0,
-                            )
-                            .await;
-                            return;
-                        }
-                    },
-                };
-            }
-            Err(_) => {
-                LogMessage::log(LogLevel::ERROR, format!("Failed to connect to {}", addr), 0).await;
-                return;
+        // Reconnect loop, the delay grows exponentially between failed attempts
+        let mut backoff = Backoff::default();
+        loop {
+            set_connection_status(ConnectionStatus::Connecting);
+
+            if let Some((stream, secure)) = connect_to_server(&config).await {
+                set_connection_status(ConnectionStatus::Connected(addr.clone()));
+                if handle_client(stream, secure).await {
+                    backoff.reset();
+                }
             }
+
+            let delay = backoff.next_delay();
+            set_connection_status(ConnectionStatus::Reconnecting {
+                attempt: backoff.attempt(),
+                retry_in: delay.as_secs(),
+            });
+            LogMessage::log(
+                LogLevel::ERROR,
+                format!("Connection lost, reconnecting in {}s...", delay.as_secs()),
+                delay.as_secs(),
+            )
+            .await;
+            tokio::time::sleep(delay).await;
         }
     });
 
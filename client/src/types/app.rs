@@ -1,12 +1,18 @@
 use crate::{
     data,
-    types::{LogMessage, Session},
+    types::{LogMessage, PendingReceipt, Session},
 };
-use common::types::Message;
+use common::types::{GroupRole, Message, PresenceUpdate, TypingEvent};
 use ratatui::widgets::{ListState, ScrollbarState};
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    time::{Duration, Instant},
+};
 use tui_textarea::TextArea;
 
+/// How long a typing signal is shown without a newer one.
+const TYPING_TIMEOUT: Duration = Duration::from_secs(6);
+
 /// ### Represents the different modes of the text editor.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EditorMode {
@@ -22,6 +28,17 @@ pub enum Panels {
     SideBar,
 }
 
+/// ### Represents the state of the connection to the server.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    /// A connection attempt is in progress.
+    Connecting,
+    /// Connected to the server at the given address.
+    Connected(String),
+    /// The connection was lost, the next attempt starts in `retry_in` seconds.
+    Reconnecting { attempt: u32, retry_in: u64 },
+}
+
 /// ### Represents the application configuration.
 pub struct AppConfig {
     /// The current mode of the editor.
@@ -63,6 +80,74 @@ pub struct AppConfig {
     pub input: TextArea<'static>,
     /// Log message for the user.
     pub log: Option<LogMessage>,
+    /// State of the connection to the server.
+    pub connection: ConnectionStatus,
+
+    /// Presence of the users sharing a session with the current user, keyed by user ID.
+    pub presence: HashMap<String, PresenceUpdate>,
+    /// Members of each session other than the current user, keyed by session ID.
+    pub members: HashMap<String, Vec<String>>,
+    /// Roles of the members of each group, including ours, keyed by group ID.
+    pub roles: HashMap<String, HashMap<String, GroupRole>>,
+
+    /// Members currently typing in any session.
+    pub typing: Vec<Typist>,
+    /// When we last told the members of the active session that we are typing.
+    pub typing_sent_at: Option<Instant>,
+
+    /// Delivery state of the messages we sent, keyed by session ID and timestamp.
+    pub receipts: HashMap<(String, u128), MessageReceipt>,
+    /// Received messages not reported as read yet, as (author ID, message ID) per session ID.
+    pub unread: HashMap<String, Vec<(String, String)>>,
+
+    /// Users waiting for us to accept their contact request, with their name, keyed by user ID.
+    pub contact_requests: HashMap<String, Option<String>>,
+    /// DMs we opened whose contact request wasn't accepted yet, by session ID.
+    pub awaiting: HashSet<String>,
+    /// Members whose messages are hidden in groups, by user ID.
+    pub ignored: HashSet<String>,
+}
+
+/// ### A member typing in a session.
+#[derive(Debug, Clone)]
+pub struct Typist {
+    /// The session the member is typing in.
+    pub session_id: String,
+    /// The user ID of the member.
+    pub user_id: String,
+    /// The name shown for the member.
+    pub name: String,
+    /// When the member stops being shown as typing.
+    pub expires_at: Instant,
+}
+
+/// ### Represents how far a message we sent got.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DeliveryState {
+    /// The server couldn't relay the message.
+    Failed,
+    /// Written to the connection, not acknowledged by the server yet.
+    #[default]
+    Sending,
+    /// Relayed or queued by the server.
+    Sent,
+    /// Received by a device of every member.
+    Delivered,
+    /// Read by every member.
+    Read,
+}
+
+/// ### What we know about the delivery of a message we sent.
+#[derive(Debug, Clone, Default)]
+pub struct MessageReceipt {
+    /// The id assigned to the message by the server.
+    pub message_id: Option<String>,
+    /// How far the message got.
+    pub state: DeliveryState,
+    /// Members with a device that received the message.
+    pub delivered: HashSet<String>,
+    /// Members that read the message.
+    pub read: HashSet<String>,
 }
 
 impl AppConfig {
@@ -91,6 +176,21 @@ impl AppConfig {
 
             input: TextArea::default(),
             log: None,
+            connection: ConnectionStatus::Connecting,
+
+            presence: HashMap::new(),
+            members: HashMap::new(),
+            roles: HashMap::new(),
+
+            typing: Vec::new(),
+            typing_sent_at: None,
+
+            receipts: HashMap::new(),
+            unread: HashMap::new(),
+
+            contact_requests: HashMap::new(),
+            awaiting: HashSet::new(),
+            ignored: HashSet::new(),
         }
     }
 
@@ -111,6 +211,33 @@ impl AppConfig {
         self.active_session = None;
     }
 
+    /// Returns the names of the members typing in a session.
+    pub fn typing_in(&self, session_id: &str) -> Vec<String> {
+        let now = Instant::now();
+        self.typing
+            .iter()
+            .filter(|typist| typist.session_id == session_id && typist.expires_at > now)
+            .map(|typist| typist.name.clone())
+            .collect()
+    }
+
+    /// Takes the unread messages of a session as read receipts, one per author.
+    pub fn take_unread(&mut self, session_id: &str) -> Vec<PendingReceipt> {
+        let mut receipts: Vec<PendingReceipt> = Vec::new();
+        for (author_id, message_id) in self.unread.remove(session_id).unwrap_or_default() {
+            match receipts.iter_mut().find(|r| r.author_id == author_id) {
+                Some(receipt) => receipt.message_ids.push(message_id),
+                None => receipts.push(PendingReceipt {
+                    session_id: session_id.to_string(),
+                    author_id,
+                    read: true,
+                    message_ids: vec![message_id],
+                }),
+            }
+        }
+        receipts
+    }
+
     /// Returns the current session.
     pub fn current_session(&mut self) -> Option<&Session> {
         self.active_session
@@ -119,6 +246,15 @@ impl AppConfig {
     }
 }
 
+/// Records whether a DM we opened still waits for its contact request to be accepted
+pub fn set_awaiting(session_id: &str, pending: bool) {
+    let mut app = data::APP_STATE.lock().unwrap();
+    match pending {
+        true => app.awaiting.insert(session_id.to_string()),
+        false => app.awaiting.remove(session_id),
+    };
+}
+
 /// Updates the given session
 pub fn update_session(session: Session) {
     let mut app = data::APP_STATE.lock().unwrap();
@@ -127,3 +263,117 @@ pub fn update_session(session: Session) {
     app.sessions.entry(key.clone()).or_insert(session.clone());
     app.active_session = Some(key.clone());
 }
+
+/// Records the presence of a user and the sessions it shares with us
+pub fn update_presence(update: PresenceUpdate) {
+    let mut app = data::APP_STATE.lock().unwrap();
+    for chat_id in &update.chats {
+        let members = app.members.entry(chat_id.clone()).or_default();
+        if !members.contains(&update.user_id) {
+            members.push(update.user_id.clone());
+        }
+    }
+
+    // Keep the last known username while the user is offline
+    let username = update.username.clone().or_else(|| {
+        app.presence
+            .get(&update.user_id)
+            .and_then(|known| known.username.clone())
+    });
+    app.presence.insert(
+        update.user_id.clone(),
+        PresenceUpdate { username, ..update },
+    );
+}
+
+/// Shows a member as typing in a session until [`TYPING_TIMEOUT`] passes
+pub fn set_typing(event: TypingEvent) {
+    let mut app = data::APP_STATE.lock().unwrap();
+    let now = Instant::now();
+    app.typing.retain(|typist| {
+        typist.expires_at > now
+            && !(typist.session_id == event.session_id && typist.user_id == event.user_id)
+    });
+    app.typing.push(Typist {
+        name: event
+            .username
+            .unwrap_or_else(|| event.user_id[..8].to_string()),
+        session_id: event.session_id,
+        user_id: event.user_id,
+        expires_at: now + TYPING_TIMEOUT,
+    });
+}
+
+/// Stops showing a member as typing, e.g. once its message arrived
+pub fn clear_typing(session_id: &str, user_id: &str) {
+    let mut app = data::APP_STATE.lock().unwrap();
+    app.typing
+        .retain(|typist| !(typist.session_id == session_id && typist.user_id == user_id));
+}
+
+/// Records how far a message we sent got, a failure or the first server answer wins
+pub fn set_delivery_state(
+    session_id: &str,
+    timestamps: u128,
+    state: DeliveryState,
+    message_id: Option<String>,
+) {
+    let mut app = data::APP_STATE.lock().unwrap();
+    let receipt = app
+        .receipts
+        .entry((session_id.to_string(), timestamps))
+        .or_default();
+    if message_id.is_some() {
+        receipt.message_id = message_id;
+    }
+    if state == DeliveryState::Failed || receipt.state < state {
+        receipt.state = state;
+    }
+}
+
+/// Records that a member received or read some of our messages
+pub fn record_receipt(reader_id: &str, message_ids: &[String], read: bool) {
+    let mut app = data::APP_STATE.lock().unwrap();
+    let app = &mut *app;
+    for ((session_id, _), receipt) in app.receipts.iter_mut() {
+        let Some(message_id) = &receipt.message_id else {
+            continue;
+        };
+        if !message_ids.contains(message_id) {
+            continue;
+        }
+
+        receipt.delivered.insert(reader_id.to_string());
+        if read {
+            receipt.read.insert(reader_id.to_string());
+        }
+
+        // Members are only known once their presence arrived, count at least the reader
+        let members = app.members.get(session_id).map_or(0, Vec::len).max(1);
+        let state = if receipt.read.len() >= members {
+            DeliveryState::Read
+        } else if receipt.delivered.len() >= members {
+            DeliveryState::Delivered
+        } else {
+            DeliveryState::Sent
+        };
+        if receipt.state < state {
+            receipt.state = state;
+        }
+    }
+}
+
+/// Remembers a received message until its session is viewed
+pub fn mark_unread(session_id: &str, author_id: &str, message_id: &str) {
+    let mut app = data::APP_STATE.lock().unwrap();
+    app.unread
+        .entry(session_id.to_string())
+        .or_default()
+        .push((author_id.to_string(), message_id.to_string()));
+}
+
+/// Updates the connection status shown in the UI
+pub fn set_connection_status(status: ConnectionStatus) {
+    let mut app = data::APP_STATE.lock().unwrap();
+    app.connection = status;
+}
@@ -1,4 +1,4 @@
-use crate::types::LogMessage;
+use crate::types::{LogMessage, PendingReceipt};
 use std::sync::Arc;
 use tokio::sync::{
     Mutex as AsyncMutex,
@@ -8,7 +8,7 @@ use tokio::sync::{
 /// ### A centralized container for application communication channels.
 ///
 /// [`AppChannels`] groups together asynchronous message-passing channels
-/// for logs, commands, and messages. Each channel is created with a bounded
+/// for logs, commands, messages, typing signals and receipts. Each channel is created with a bounded
 /// capacity of 10 and is wrapped in [`Arc`] + [`tokio::sync::Mutex`] for
 /// thread-safe, shared access across tasks.
 ///
@@ -23,6 +23,12 @@ use tokio::sync::{
 /// - **Messages**
 ///   - `msg_tx`: Sender for application messages (`String`).
 ///   - `msg_rx`: Receiver for application messages.
+/// - **Typing**
+///   - `typing_tx`: Sender for typing signals (session ID).
+///   - `typing_rx`: Receiver for typing signals.
+/// - **Receipts**
+///   - `receipt_tx`: Sender for delivery and read receipts ([`PendingReceipt`]).
+///   - `receipt_rx`: Receiver for receipts.
 ///
 /// # Examples
 ///
@@ -62,6 +68,16 @@ pub struct AppChannels {
     pub msg_tx: Arc<AsyncMutex<Sender<String>>>,
     /// Receiver for application messages (`String`).
     pub msg_rx: Arc<AsyncMutex<Receiver<String>>>,
+
+    /// Sender for typing signals (session ID).
+    pub typing_tx: Arc<AsyncMutex<Sender<String>>>,
+    /// Receiver for typing signals (session ID).
+    pub typing_rx: Arc<AsyncMutex<Receiver<String>>>,
+
+    /// Sender for delivery and read receipts.
+    pub receipt_tx: Arc<AsyncMutex<Sender<PendingReceipt>>>,
+    /// Receiver for delivery and read receipts.
+    pub receipt_rx: Arc<AsyncMutex<Receiver<PendingReceipt>>>,
 }
 
 impl AppChannels {
@@ -72,6 +88,8 @@ impl AppChannels {
         let (log_tx, log_rx) = channel::<LogMessage>(10);
         let (cmd_tx, cmd_rx) = channel::<String>(10);
         let (msg_tx, msg_rx) = channel::<String>(10);
+        let (typing_tx, typing_rx) = channel::<String>(10);
+        let (receipt_tx, receipt_rx) = channel::<PendingReceipt>(10);
 
         AppChannels {
             log_tx: Arc::new(AsyncMutex::new(log_tx)),
@@ -80,6 +98,10 @@ impl AppChannels {
             cmd_rx: Arc::new(AsyncMutex::new(cmd_rx)),
             msg_tx: Arc::new(AsyncMutex::new(msg_tx)),
             msg_rx: Arc::new(AsyncMutex::new(msg_rx)),
+            typing_tx: Arc::new(AsyncMutex::new(typing_tx)),
+            typing_rx: Arc::new(AsyncMutex::new(typing_rx)),
+            receipt_tx: Arc::new(AsyncMutex::new(receipt_tx)),
+            receipt_rx: Arc::new(AsyncMutex::new(receipt_rx)),
         }
     }
 }
@@ -1,8 +1,43 @@
+use common::types::CompressionAlgo;
 use rsa::{RsaPrivateKey, RsaPublicKey};
 use serde::Deserialize;
 
+/// ### The transport used to reach the server.
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+pub enum Transport {
+    /// TCP, upgraded to TLS when the server supports it.
+    Tcp,
+    /// WebSocket to a `ws://` or `wss://` URL.
+    WebSocket(String),
+    /// QUIC, always encrypted with TLS 1.3.
+    Quic,
+    /// Unix domain socket at the given path, for servers on the same host.
+    Unix(String),
+}
+
+/// ### The protocol spoken with a proxy.
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+pub enum ProxyKind {
+    /// SOCKS5, the server hostname is resolved locally.
+    Socks5,
+    /// SOCKS5, the server hostname is resolved by the proxy.
+    Socks5h,
+    /// HTTP `CONNECT` tunnel.
+    Http,
+}
+
+/// ### A proxy the connection to the server is tunneled through.
+#[derive(Debug, Deserialize, Clone)]
+pub struct ProxyConfig {
+    pub kind: ProxyKind,
+    pub host: String,
+    pub port: u16,
+    /// Username and password, if the proxy requires authentication.
+    pub auth: Option<(String, String)>,
+}
+
 /// ### Represents the configuration for a connection.
-/// 
+///
 /// This struct holds the necessary details for connecting to a server,
 #[derive(Debug, Deserialize, Clone)]
 pub struct ConnectionConfig {
@@ -10,7 +45,33 @@ pub struct ConnectionConfig {
     pub port: String,
     pub name: String,
     pub user_id: String,
+    /// Identifies this device among the devices logged in with the same key.
+    pub device_id: String,
 
     pub public_key: RsaPublicKey,
     pub private_key: RsaPrivateKey,
+
+    /// Frame compression algorithms offered to the server, in order of preference.
+    pub compression: Vec<CompressionAlgo>,
+    /// How the server is reached.
+    pub transport: Transport,
+    /// PEM file with extra trusted root certificates, e.g. a self-signed server certificate.
+    pub ca_cert: Option<String>,
+    /// Proxy used to reach the server over TCP or WebSocket.
+    pub proxy: Option<ProxyConfig>,
+    /// Whether the members of a session are told that we are typing.
+    pub typing: bool,
+    /// Whether the authors of the messages we view are told that we read them.
+    pub read_receipts: bool,
+}
+
+impl ConnectionConfig {
+    /// Returns the address of the server as shown to the user.
+    pub fn address(&self) -> String {
+        match &self.transport {
+            Transport::Tcp | Transport::Quic => format!("{}:{}", self.hostname, self.port),
+            Transport::WebSocket(url) => url.clone(),
+            Transport::Unix(path) => format!("unix:{}", path),
+        }
+    }
 }
@@ -17,6 +17,9 @@ pub struct Session {
     pub mode: ChatMode,
     /// A unique identifier for the session.
     pub id: String,
+    /// The id the session was opened with (peer `user_id` for DMs, `group_id` for groups).
+    /// Used to re-join the session after a reconnect.
+    pub target_id: String,
 }
 
 /// ### A shared reference to the currently active [`Session`].
@@ -43,3 +46,36 @@ pub type Sessions = Arc<AsyncMutex<HashMap<String, Session>>>;
 /// and [`tokio::sync::Mutex`] to allow concurrent producers
 /// and consumers to push and read messages safely.
 pub type Messages = Arc<AsyncMutex<Vec<Message>>>;
+
+/// ### Messages received for sessions that haven't been opened yet.
+///
+/// The key is the session's unique ID, the messages are still encrypted
+/// since the session key is only known once the session is opened.
+pub type PendingMessages = Arc<AsyncMutex<HashMap<String, Vec<Message>>>>;
+
+/// ### Paging state of the server-side history of a session.
+#[derive(Clone, Debug, Default)]
+pub struct HistoryState {
+    /// Id of the oldest history entry loaded so far.
+    pub oldest_id: Option<u64>,
+    /// Whether the server has no older messages.
+    pub complete: bool,
+}
+
+/// ### A shared map of the history paging state of every session.
+///
+/// The key is the session's unique ID.
+pub type HistoryStates = Arc<AsyncMutex<HashMap<String, HistoryState>>>;
+
+/// ### A receipt waiting to be sent by the writer task.
+#[derive(Clone, Debug)]
+pub struct PendingReceipt {
+    /// The DM or group the messages were sent to.
+    pub session_id: String,
+    /// The user ID of the author of the messages.
+    pub author_id: String,
+    /// Whether the messages were read, or only received.
+    pub read: bool,
+    /// The ids of the messages.
+    pub message_ids: Vec<String>,
+}
@@ -1,4 +1,7 @@
-use std::sync::MutexGuard;
+use std::{
+    sync::MutexGuard,
+    time::{Duration, Instant},
+};
 
 use ratatui::{
     crossterm::event::{KeyCode, KeyEvent, KeyModifiers},
@@ -8,11 +11,15 @@ use tui_textarea::{CursorMove, TextArea};
 
 use crate::{
     data,
-    types::{AppConfig, EditorMode, Panels},
+    handlers::queue_receipts,
+    types::{AppConfig, ConnectionStatus, EditorMode, LogLevel, LogMessage, Panels},
 };
 
+/// Shortest interval between two typing signals sent for the active session.
+const TYPING_THROTTLE: Duration = Duration::from_secs(3);
+
 /// ### Handles user input events for the application.
-/// 
+///
 /// This function will process key events and update the application state accordingly.
 pub async fn handle_events(key: KeyEvent) -> Option<String> {
     let code = key.code;
@@ -29,7 +36,10 @@ pub async fn handle_events(key: KeyEvent) -> Option<String> {
     match app.mode {
         EditorMode::NORMAL => handle_normal_mode(code, modifier, app).await,
         EditorMode::INSERT | EditorMode::COMMAND => {
-            handle_insert_cmd_mode(code, modifier, app).await
+            let before = app.input.lines().to_vec();
+            let result = handle_insert_cmd_mode(code, modifier, app).await;
+            notify_typing(before).await;
+            result
         }
     }
 }
@@ -71,13 +81,23 @@ async fn handle_normal_mode(
                 return None;
             }
             KeyCode::Up | KeyCode::Char('k') => {
+                let mut load_history = false;
                 match app.active_panel {
                     Panels::Main => {
                         app.msg_auto_scroll = false;
                         app.message_state.select_previous();
+
+                        // Lazily load older messages once the top is reached
+                        load_history =
+                            app.messages.is_empty() || app.message_state.selected() == Some(0);
                     }
                     Panels::SideBar => app.session_state.select_previous(),
                 }
+                if load_history {
+                    drop(app);
+                    let cmd_tx = data::CHANNELS.lock().await.cmd_tx.clone();
+                    let _ = cmd_tx.lock().await.try_send("history".into());
+                }
                 return None;
             }
             KeyCode::End | KeyCode::Char('g') => {
@@ -92,6 +112,7 @@ async fn handle_normal_mode(
             }
             KeyCode::Enter => {
                 if app.active_panel == Panels::SideBar {
+                    let mut receipts = Vec::new();
                     if let Some(selected) = app.session_state.selected() {
                         let sessions = app.sessions.keys().collect::<Vec<&String>>();
 
@@ -103,6 +124,7 @@ async fn handle_normal_mode(
                                         let mut session_lock = data::ACTIVE_SESSION.lock().await;
                                         *session_lock = Some(session.clone());
 
+                                        receipts = app.take_unread(&session_id);
                                         app.active_session = Some(session_id);
                                     }
                                     None => (),
@@ -113,6 +135,9 @@ async fn handle_normal_mode(
                     }
 
                     app.switch_panel(Panels::Main);
+                    // The app state isn't held while the receipts are queued
+                    drop(app);
+                    queue_receipts(receipts).await;
                 }
                 return None;
             }
@@ -126,6 +151,31 @@ async fn handle_normal_mode(
     }
 }
 
+/// ### Tells the members of the active session that we are typing.
+///
+/// Only sent in insert mode when the input changed, at most once every [`TYPING_THROTTLE`].
+async fn notify_typing(before: Vec<String>) {
+    let session_id = {
+        let app = data::APP_STATE.lock().unwrap();
+        if app.mode != EditorMode::INSERT
+            || app.input.lines() == before
+            || app
+                .typing_sent_at
+                .is_some_and(|sent_at| sent_at.elapsed() < TYPING_THROTTLE)
+        {
+            return;
+        }
+        match app.active_session.clone() {
+            Some(session_id) => session_id,
+            None => return,
+        }
+    };
+
+    let channels = data::CHANNELS.lock().await;
+    if channels.typing_tx.lock().await.try_send(session_id).is_ok() {
+        data::APP_STATE.lock().unwrap().typing_sent_at = Some(Instant::now());
+    }
+}
 
 /// ### Handles key events in insert and cmd mode.
 async fn handle_insert_cmd_mode(
@@ -179,23 +229,44 @@ async fn handle_insert_cmd_mode(
                     return None;
                 }
 
-                let tx = match app.mode {
-                    EditorMode::COMMAND => {
-                        if input == "q" {
-                            return Some("quit".into());
-                        }
-                        let channels = data::CHANNELS.lock().await;
-                        channels.cmd_tx.clone()
-                    }
-                    EditorMode::INSERT => {
-                        let channels = data::CHANNELS.lock().await;
-                        channels.msg_tx.clone()
+                let mode = app.mode;
+                if mode == EditorMode::COMMAND && input == "q" {
+                    return Some("quit".into());
+                }
+                let connected = matches!(app.connection, ConnectionStatus::Connected(_));
+                // The app state isn't held while the input is handed over, it is locked again after
+                drop(app);
+
+                if !connected {
+                    LogMessage::log(
+                        LogLevel::ERROR,
+                        "Not connected to the server, try again later".into(),
+                        5,
+                    )
+                    .await;
+                    return None;
+                }
+                let tx = {
+                    let channels = data::CHANNELS.lock().await;
+                    match mode {
+                        EditorMode::COMMAND => channels.cmd_tx.clone(),
+                        EditorMode::INSERT => channels.msg_tx.clone(),
+                        _ => unreachable!(),
                     }
-                    _ => unreachable!(),
                 };
-
-                let _ = tx.lock().await.send(input).await;
+                // Don't block the UI while the connection task is busy with earlier inputs
+                if tx.lock().await.try_send(input).is_err() {
+                    LogMessage::log(
+                        LogLevel::ERROR,
+                        "Command queue full, try again later".into(),
+                        5,
+                    )
+                    .await;
+                    return None;
+                }
+                let mut app = data::APP_STATE.lock().unwrap();
                 app.input = TextArea::default();
+                app.typing_sent_at = None;
                 app.switch_mode(EditorMode::NORMAL);
                 return None;
             }
@@ -1,6 +1,6 @@
 use crate::{
     data,
-    types::{LogLevel, LogMessage, Panels},
+    types::{DeliveryState, LogLevel, LogMessage, Panels},
 };
 use chrono::DateTime;
 use common::types::Message;
@@ -13,7 +13,7 @@ use ratatui::{
 };
 
 /// ### Renders the main panel.
-/// 
+///
 /// This function will render the main panel of the application, including the header, body, footer, and any log messages.
 pub fn render_main_panel(frame: &mut Frame, area: Rect) {
     let active_panel = {
@@ -30,12 +30,23 @@ pub fn render_main_panel(frame: &mut Frame, area: Rect) {
         .borders(Borders::ALL)
         .border_style(border_color);
 
+    // Members typing in the active session are shown above the footer
+    let typing = {
+        let app = data::APP_STATE.lock().unwrap();
+        match &app.active_session {
+            Some(session_id) => app.typing_in(session_id),
+            None => Vec::new(),
+        }
+    };
+
     // Split Main Panel
-    let (header_area, body_area, footer_area, log_area) = split_main_panel(area);
+    let (header_area, body_area, typing_area, footer_area, log_area) =
+        split_main_panel(area, !typing.is_empty());
 
     // Render header, body, footer inside main panel
     render_main_header(frame, header_area, border_color);
     render_messages(frame, body_area);
+    render_typing(frame, typing_area, typing);
     render_main_footer(frame, footer_area);
 
     {
@@ -48,8 +59,10 @@ pub fn render_main_panel(frame: &mut Frame, area: Rect) {
     frame.render_widget(&main_block, area);
 }
 
-/// ### Splits the main panel into header, body, footer, and log areas.
-fn split_main_panel(area: Rect) -> (Rect, Rect, Rect, Rect) {
+/// ### Splits the main panel into header, body, typing, footer, and log areas.
+///
+/// The typing area is only given a line when someone is typing.
+fn split_main_panel(area: Rect, typing: bool) -> (Rect, Rect, Rect, Rect, Rect) {
     let inner_main_area = area.inner(Margin {
         vertical: 0,
         horizontal: 1,
@@ -63,6 +76,7 @@ fn split_main_panel(area: Rect) -> (Rect, Rect, Rect, Rect) {
     let main_split = Layout::vertical([
         Constraint::Length(4),
         Constraint::Fill(1),
+        Constraint::Length(typing as u16),
         Constraint::Length(required_height_for_input as u16 + 1),
         Constraint::Length(2),
     ])
@@ -70,10 +84,17 @@ fn split_main_panel(area: Rect) -> (Rect, Rect, Rect, Rect) {
 
     let header_area = main_split[0];
     let body_area = main_split[1];
-    let footer_area = main_split[2];
-    let message_area = main_split[3];
-
-    (header_area, body_area, footer_area, message_area)
+    let typing_area = main_split[2];
+    let footer_area = main_split[3];
+    let message_area = main_split[4];
+
+    (
+        header_area,
+        body_area,
+        typing_area,
+        footer_area,
+        message_area,
+    )
 }
 
 /// ### Renders the main header
@@ -105,6 +126,23 @@ fn render_main_header(frame: &mut Frame, header_area: Rect, border_color: Color)
     );
 }
 
+/// ### Renders the members typing in the active session
+fn render_typing(frame: &mut Frame, typing_area: Rect, typing: Vec<String>) {
+    let text = match typing.len() {
+        0 => return,
+        1 => format!("{} is typing…", typing[0]),
+        _ => format!("{} are typing…", typing.join(", ")),
+    };
+
+    frame.render_widget(
+        Paragraph::new(text).style(Style::default().fg(Color::DarkGray)),
+        typing_area.inner(Margin {
+            vertical: 0,
+            horizontal: 1,
+        }),
+    );
+}
+
 /// ### Renders the main footer
 fn render_main_footer(frame: &mut Frame, footer_area: Rect) {
     let inner_footer_area = footer_area.inner(Margin {
@@ -157,7 +195,7 @@ fn render_log_message(frame: &mut Frame, log_area: Rect, log: &LogMessage) {
 }
 
 /// ### Renders the messages in the main panel
-/// 
+///
 /// This function will render the messages in the main panel, including any user messages and system messages.
 fn render_messages(frame: &mut Frame, area: Rect) {
     let message_area = area.inner(Margin {
@@ -165,9 +203,20 @@ fn render_messages(frame: &mut Frame, area: Rect) {
         vertical: 0,
     });
 
-    let (messages, user_id) = {
+    let (messages, user_id, markers) = {
         let app = data::APP_STATE.lock().unwrap();
-        (app.messages.clone(), app.user_id.clone())
+        let session_id = app.active_session.clone().unwrap_or_default();
+        let markers: Vec<Option<DeliveryState>> = app
+            .messages
+            .iter()
+            .map(|msg| {
+                app.receipts
+                    .get(&(session_id.clone(), msg.timestamps))
+                    .filter(|_| msg.sender_id == app.user_id)
+                    .map(|receipt| receipt.state)
+            })
+            .collect();
+        (app.messages.clone(), app.user_id.clone(), markers)
     };
 
     let no_msg_line = Line::from("No Messages Yet!").alignment(Alignment::Center);
@@ -178,7 +227,14 @@ fn render_messages(frame: &mut Frame, area: Rect) {
         messages
             .iter()
             .enumerate()
-            .map(|(_, message)| format_message(message.clone(), user_id.clone(), message_area))
+            .map(|(index, message)| {
+                format_message(
+                    message.clone(),
+                    user_id.clone(),
+                    markers[index],
+                    message_area,
+                )
+            })
             .collect()
     };
 
@@ -210,7 +266,12 @@ fn render_messages(frame: &mut Frame, area: Rect) {
 }
 
 /// ### Formats a message for display in the main panel.
-fn format_message(message: Message, user_id: String, message_area: Rect) -> ListItem<'static> {
+fn format_message(
+    message: Message,
+    user_id: String,
+    state: Option<DeliveryState>,
+    message_area: Rect,
+) -> ListItem<'static> {
     let msg = message.clone();
 
     let date_time_string = format_date_time(msg.timestamps);
@@ -222,16 +283,19 @@ fn format_message(message: Message, user_id: String, message_area: Rect) -> List
         }
     };
 
-    // First line with username and timestamp
-    let line1 = Line::from(Span::styled(
+    // First line with username, timestamp and delivery marker of our own messages
+    let mut spans = vec![Span::styled(
         format!(
             "{} | {}",
             msg.username.unwrap_or_else(|| msg.sender_id[..8].into()),
             date_time_string,
         ),
         prompt_style,
-    ))
-    .alignment(Alignment::Left);
+    )];
+    if let Some(state) = state {
+        spans.push(delivery_marker(state));
+    }
+    let line1 = Line::from(spans).alignment(Alignment::Left);
 
     // Second line message content
     let wrapped_lines = wrap_text_to_width(
@@ -246,8 +310,20 @@ fn format_message(message: Message, user_id: String, message_area: Rect) -> List
     ListItem::new(lines.clone())
 }
 
+/// Marker shown next to a message we sent
+fn delivery_marker(state: DeliveryState) -> Span<'static> {
+    let (marker, color) = match state {
+        DeliveryState::Sending => (" …", Color::DarkGray),
+        DeliveryState::Sent => (" ✓", Color::Gray),
+        DeliveryState::Delivered => (" ✓✓", Color::Gray),
+        DeliveryState::Read => (" ✓✓", Color::LightBlue),
+        DeliveryState::Failed => (" ✗ failed", Color::Red),
+    };
+    Span::styled(marker, Style::default().fg(color))
+}
+
 /// ### Formats a date and time for display in the main panel.
-/// 
+///
 /// # Example
 /// ```no_run
 /// let formatted = format_date_time(1633036800000);
@@ -1,4 +1,8 @@
-use crate::{data, types::Panels};
+use crate::{
+    data,
+    types::{ConnectionStatus, Panels},
+};
+use common::types::{ChatMode, GroupRole, Presence, PresenceUpdate};
 use ratatui::{
     Frame,
     layout::{Alignment, Constraint, Layout, Margin, Rect},
@@ -6,6 +10,7 @@ use ratatui::{
     text::{Line, Span},
     widgets::{Block, Borders, HighlightSpacing, List, ListItem, Paragraph},
 };
+use std::collections::HashMap;
 
 /// ### Renders the side panel.
 ///
@@ -28,11 +33,19 @@ pub fn render_side_panel(frame: &mut Frame, area: Rect) {
     frame.render_widget(main_block, area);
 
     // Split side panel
-    let (header_area, session_area, _) = split_side_panel(area);
+    let (header_area, session_area, footer_area) = split_side_panel(area);
+
+    // Members of the active group are listed below the sessions
+    let members = active_group_members();
+    let (session_area, members_area) = split_members(session_area, members.len());
 
     // Render header, body, footer inside main panel
     render_header(frame, header_area, border_color);
     render_sessions(frame, session_area);
+    if let Some(members_area) = members_area {
+        render_members(frame, members_area, border_color, members);
+    }
+    render_footer(frame, footer_area, border_color);
 }
 
 /// ### Splits the side panel into header, body, and footer areas.
@@ -55,6 +68,20 @@ fn split_side_panel(area: Rect) -> (Rect, Rect, Rect) {
     (header_area, body_area, footer_area)
 }
 
+/// ### Splits the body of the side panel into sessions and group members.
+///
+/// The members take at most half of the body, no area is returned for them
+/// if there are none to show.
+fn split_members(area: Rect, members: usize) -> (Rect, Option<Rect>) {
+    if members == 0 {
+        return (area, None);
+    }
+
+    let height = (members as u16 + 1).min(area.height / 2);
+    let split = Layout::vertical([Constraint::Fill(1), Constraint::Length(height)]).split(area);
+    (split[0], Some(split[1]))
+}
+
 /// ### Renders the header of the side panel.
 fn render_header(frame: &mut Frame, header_area: Rect, border_color: Color) {
     let header_inner_area = header_area.inner(Margin {
@@ -74,6 +101,39 @@ fn render_header(frame: &mut Frame, header_area: Rect, border_color: Color) {
     frame.render_widget(title, header_inner_area);
 }
 
+/// ### Renders the connection status in the footer of the side panel.
+fn render_footer(frame: &mut Frame, footer_area: Rect, border_color: Color) {
+    let footer_inner_area = footer_area.inner(Margin {
+        vertical: 1,
+        horizontal: 0,
+    });
+
+    let connection = {
+        let app = data::APP_STATE.lock().unwrap();
+        app.connection.clone()
+    };
+
+    let (status, color) = match connection {
+        ConnectionStatus::Connecting => ("● Connecting...".to_string(), Color::Yellow),
+        ConnectionStatus::Connected(addr) => (format!("● Connected: {}", addr), Color::Green),
+        ConnectionStatus::Reconnecting { attempt, retry_in } => (
+            format!("● Reconnecting in {}s (#{})", retry_in, attempt),
+            Color::Red,
+        ),
+    };
+
+    let footer = Paragraph::new(status)
+        .style(Style::default().fg(color))
+        .alignment(Alignment::Center)
+        .block(
+            Block::new()
+                .borders(Borders::TOP)
+                .border_style(border_color),
+        );
+
+    frame.render_widget(footer, footer_inner_area);
+}
+
 /// ### Renders the sessions in the side panel.
 fn render_sessions(frame: &mut Frame, area: Rect) {
     let sidebar_area = area.inner(Margin {
@@ -81,9 +141,14 @@ fn render_sessions(frame: &mut Frame, area: Rect) {
         vertical: 0,
     });
 
-    let (sessions, active_session) = {
+    let (sessions, active_session, presence, members) = {
         let app = data::APP_STATE.lock().unwrap();
-        (app.sessions.clone(), app.active_session.clone())
+        (
+            app.sessions.clone(),
+            app.active_session.clone(),
+            app.presence.clone(),
+            app.members.clone(),
+        )
     };
 
     let items: Vec<ListItem> = sessions
@@ -92,13 +157,25 @@ fn render_sessions(frame: &mut Frame, area: Rect) {
         .map(|(_, s)| {
             let chat_mode = format!("{:?}", s.1.mode);
             let chat_id = format!("{}", &s.0[..8]);
+            // Channels don't share presence, they get a marker of their own
+            let (dot, mode_style) = match s.1.mode {
+                ChatMode::Channel(_) => (
+                    Span::styled("» ", Style::default().fg(Color::LightCyan)),
+                    Style::default().fg(Color::LightCyan).italic(),
+                ),
+                _ => (
+                    presence_dot(session_presence(&presence, &members, s.0)),
+                    Style::default(),
+                ),
+            };
 
             // calculate available width
             let total_width = sidebar_area.width as usize;
-            let spacing = total_width.saturating_sub(chat_mode.len() + chat_id.len() + 2); // 2 for safety
+            let spacing = total_width.saturating_sub(chat_mode.len() + chat_id.len() + 4); // 2 for the dot, 2 for safety
 
             let line = Line::from(vec![
-                Span::raw(format!("{:?}", s.1.mode)),
+                dot,
+                Span::styled(chat_mode, mode_style),
                 Span::raw(" ".repeat(spacing)), // dynamic padding
                 Span::raw(format!("{}", &s.0[..8])),
             ]);
@@ -137,3 +214,120 @@ fn render_sessions(frame: &mut Frame, area: Rect) {
         frame.render_stateful_widget(list_widget, sidebar_area, &mut app.session_state);
     }
 }
+
+/// ### Returns the presence of the members of the active group.
+fn active_group_members() -> Vec<(PresenceUpdate, Option<GroupRole>)> {
+    let mut app = data::APP_STATE.lock().unwrap();
+    let session_id = match app.current_session() {
+        Some(session) if matches!(session.mode, ChatMode::Group(_)) => session.id.clone(),
+        _ => return Vec::new(),
+    };
+
+    let roles = app.roles.get(&session_id);
+    let mut members: Vec<(PresenceUpdate, Option<GroupRole>)> = app
+        .members
+        .get(&session_id)
+        .into_iter()
+        .flatten()
+        .filter_map(|user_id| {
+            let role = roles.and_then(|roles| roles.get(user_id).copied());
+            app.presence
+                .get(user_id)
+                .cloned()
+                .map(|member| (member, role))
+        })
+        .collect();
+    members.sort_by_key(|(member, role)| {
+        (
+            member.presence == Presence::Offline,
+            std::cmp::Reverse(*role),
+            member.user_id.clone(),
+        )
+    });
+    members
+}
+
+/// ### Renders the members of the active group with their presence and role.
+fn render_members(
+    frame: &mut Frame,
+    area: Rect,
+    border_color: Color,
+    members: Vec<(PresenceUpdate, Option<GroupRole>)>,
+) {
+    let items: Vec<ListItem> = members
+        .into_iter()
+        .map(|(member, role)| {
+            let name = member
+                .username
+                .unwrap_or_else(|| member.user_id[..8].to_string());
+            let mut spans = vec![presence_dot(Some(member.presence)), Span::raw(name)];
+            if let Some(tag) = role.and_then(role_tag) {
+                spans.push(tag);
+            }
+            if let Some(status) = member.status {
+                spans.push(Span::styled(
+                    format!(" {}", status),
+                    Style::default().fg(Color::DarkGray),
+                ));
+            }
+            ListItem::new(Line::from(spans))
+        })
+        .collect();
+
+    let list_widget = List::new(items).block(
+        Block::new()
+            .title("MEMBERS")
+            .title_style(Style::default().fg(Color::Magenta))
+            .title_alignment(Alignment::Center)
+            .borders(Borders::TOP)
+            .border_style(border_color),
+    );
+    frame.render_widget(list_widget, area);
+}
+
+/// Tag shown after the name of a member, plain members have none
+fn role_tag(role: GroupRole) -> Option<Span<'static>> {
+    let color = match role {
+        GroupRole::Owner => Color::LightYellow,
+        GroupRole::Admin => Color::LightMagenta,
+        GroupRole::Moderator => Color::LightBlue,
+        GroupRole::ReadOnly => Color::DarkGray,
+        GroupRole::Member => return None,
+    };
+    Some(Span::styled(
+        format!(" [{}]", role),
+        Style::default().fg(color),
+    ))
+}
+
+/// ### Returns the most available presence among the members of a session.
+///
+/// `None` if the presence of no member is known yet.
+fn session_presence(
+    presence: &HashMap<String, PresenceUpdate>,
+    members: &HashMap<String, Vec<String>>,
+    session_id: &str,
+) -> Option<Presence> {
+    members
+        .get(session_id)?
+        .iter()
+        .filter_map(|user_id| presence.get(user_id))
+        .map(|member| member.presence)
+        .min_by_key(|presence| match presence {
+            Presence::Online => 0,
+            Presence::Away => 1,
+            Presence::DoNotDisturb => 2,
+            Presence::Offline => 3,
+        })
+}
+
+/// ### Returns a dot colored after a presence.
+fn presence_dot(presence: Option<Presence>) -> Span<'static> {
+    let color = match presence {
+        Some(Presence::Online) => Color::Green,
+        Some(Presence::Away) => Color::Yellow,
+        Some(Presence::DoNotDisturb) => Color::Red,
+        Some(Presence::Offline) | None => Color::DarkGray,
+    };
+    Span::styled("● ", Style::default().fg(color))
+}
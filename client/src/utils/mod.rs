@@ -1,6 +1,7 @@
 pub mod config;
 pub mod file;
 pub mod net;
+pub mod proxy;
 
 pub use config::*;
 pub use file::*;
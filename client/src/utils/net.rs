@@ -1,16 +1,30 @@
 use crate::{
-    data::CLIENT_CONFIG,
-    types::{LogLevel, LogMessage},
+    data::{CLIENT_CONFIG, RESUMPTION_TICKET},
+    types::{ConnectionConfig, LogLevel, LogMessage, Transport},
+    utils::proxy::connect_via_proxy,
 };
 use common::{
     net::{AsyncStream, HandshakePacket, StreamReader, StreamWriter},
-    utils::{enc as encutils, net as netutils},
+    quic::{QUIC_ALPN, QuicStream},
+    types::{Compression, CompressionAlgo},
+    utils::{compression::DEFAULT_COMPRESSION_THRESHOLD, enc as encutils, net as netutils},
+    ws::WsStream,
 };
+use quinn::{Endpoint, crypto::rustls::QuicClientConfig};
 use rsa::RsaPrivateKey;
-use rustls::{ClientConfig, pki_types::ServerName};
-use std::sync::Arc;
+use rustls::{
+    ClientConfig,
+    pki_types::{CertificateDer, ServerName, pem::PemObject},
+};
+use std::{
+    net::{Ipv4Addr, Ipv6Addr, SocketAddr},
+    sync::Arc,
+};
+#[cfg(unix)]
+use tokio::net::UnixStream;
 use tokio::{
-    net::TcpStream,
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpStream, lookup_host},
     time::{Duration, timeout},
 };
 use tokio_rustls::TlsConnector;
@@ -21,13 +35,23 @@ use tokio_rustls::TlsConnector;
 /// sending the initial handshake packet, receiving the server's
 /// response, and completing the handshake by establishing a
 /// secure session key.
+///
+/// The resumption ticket of the previous connection is presented
+/// to the server, if the server accepts it the nonce signing steps
+/// are skipped. The ticket is only presented over `secure` transports,
+/// anyone reading it on a plain connection could resume the session.
+/// Frame compression is enabled if the server picked one of the
+/// offered algorithms.
 pub async fn perform_handshake(
     rd: StreamReader,
     wt: StreamWriter,
+    secure: bool,
 ) -> Result<Vec<u8>, Box<dyn std::error::Error + Send + Sync>> {
     let username: String;
     let public_key: String;
     let private_key: RsaPrivateKey;
+    let compression: Vec<CompressionAlgo>;
+    let device_id: Option<String>;
 
     // Get user credentials from config
     {
@@ -46,10 +70,16 @@ pub async fn perform_handshake(
             Some(cfg) => cfg.private_key.clone(),
             None => return Err("❗️Failed to get private key from config".into()),
         };
+        compression = match config.as_ref() {
+            Some(cfg) => cfg.compression.clone(),
+            None => Vec::new(),
+        };
+        device_id = config.as_ref().map(|cfg| cfg.device_id.clone());
     }
 
     // Step: 0
-    // Send handshake packet with username and public_key
+    // Send handshake packet with username, public_key, device and the ticket of the last connection
+    let ticket = RESUMPTION_TICKET.lock().await.take().filter(|_| secure);
     let packet = HandshakePacket {
         step: 0,
         username: Some(username),
@@ -57,70 +87,399 @@ pub async fn perform_handshake(
         nonce: None,
         signature: None,
         session_key: None,
+        ticket,
+        compression: Some(compression),
+        device_id,
     };
     netutils::write_packet(wt.clone(), packet).await?;
 
-    // Step: 1
-    // Receive handshake packet from server with nonce
-    // Sign the nonce with private_key
-    let packet: HandshakePacket = netutils::read_packet(rd.clone()).await?;
-    assert!(packet.step == 1);
-    let nonce = match packet.nonce {
-        Some(nonce) => nonce.clone(),
-        None => return Err("❗️Failed to get nonce from handshake packet".into()),
-    };
-    let signature = encutils::sign_nonce(&private_key, &nonce);
+    // The server answers with step 3 directly when the ticket was accepted
+    let mut packet: HandshakePacket = netutils::read_packet(rd.clone()).await?;
+    if packet.step == 1 {
+        // Step: 1
+        // Receive handshake packet from server with nonce
+        // Sign the nonce with private_key
+        let nonce = match packet.nonce {
+            Some(nonce) => nonce.clone(),
+            None => return Err("❗️Failed to get nonce from handshake packet".into()),
+        };
+        let signature = encutils::sign_nonce(&private_key, &nonce);
 
-    // Step: 2
-    // Send handshake packet with signature
-    let packet = HandshakePacket {
-        step: 2,
-        username: None,
-        public_key: None,
-        nonce: None,
-        signature: Some(signature),
-        session_key: None,
-    };
-    netutils::write_packet(wt.clone(), packet).await?;
+        // Step: 2
+        // Send handshake packet with signature
+        let packet2 = HandshakePacket {
+            step: 2,
+            username: None,
+            public_key: None,
+            nonce: None,
+            signature: Some(signature),
+            session_key: None,
+            ticket: None,
+            compression: None,
+            device_id: None,
+        };
+        netutils::write_packet(wt.clone(), packet2).await?;
+
+        packet = netutils::read_packet(rd.clone()).await?;
+    } else {
+        LogMessage::log(LogLevel::INFO, "Connection resumed".to_string(), 5).await;
+    }
 
     // Step: 3
-    // Receive handshake packet with session_key
-    let packet: HandshakePacket = netutils::read_packet(rd.clone()).await?;
-    assert!(packet.step == 3);
+    // Receive handshake packet with session_key and a new ticket
+    if packet.step != 3 {
+        return Err("❗️Invalid handshake step".into());
+    }
     let session_key = packet
         .session_key
         .ok_or("❗️Failed to get session_key from handshake packet")?;
+    *RESUMPTION_TICKET.lock().await = packet.ticket;
+
+    // Every frame after the handshake may be compressed
+    if let Some(algo) = packet.compression.and_then(|algos| algos.first().copied()) {
+        wt.lock().await.compression = Some(Compression {
+            algo,
+            threshold: DEFAULT_COMPRESSION_THRESHOLD,
+        });
+        rd.lock().await.compression = Some(algo);
+    }
 
     Ok(session_key)
 }
 
-pub async fn create_tls_connector() -> Result<TlsConnector, Box<dyn std::error::Error + Send + Sync>>
-{
+/// ### Exponential backoff used between reconnect attempts.
+///
+/// Every call to [`Backoff::next_delay`] doubles the delay, starting at
+/// [`Backoff::INITIAL_DELAY`] and capped at [`Backoff::MAX_DELAY`].
+/// Call [`Backoff::reset`] once a connection has been established.
+pub struct Backoff {
+    attempt: u32,
+    delay: Duration,
+}
+
+impl Backoff {
+    /// Delay before the first reconnect attempt.
+    pub const INITIAL_DELAY: Duration = Duration::from_secs(1);
+    /// Upper bound for the delay between attempts.
+    pub const MAX_DELAY: Duration = Duration::from_secs(60);
+
+    /// Returns the delay to wait before the next attempt and advances the backoff.
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = self.delay;
+        self.attempt += 1;
+        self.delay = (self.delay * 2).min(Self::MAX_DELAY);
+        delay
+    }
+
+    /// Returns the number of attempts made since the last reset.
+    pub fn attempt(&self) -> u32 {
+        self.attempt
+    }
+
+    /// Resets the backoff after a successful connection.
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            attempt: 0,
+            delay: Self::INITIAL_DELAY,
+        }
+    }
+}
+
+/// ### Opens a connection to the server.
+///
+/// The connection is made with the transport selected in the configuration.
+/// Returns the stream and whether it is encrypted or local,
+/// or `None` if the server could not be reached.
+pub async fn connect_to_server(config: &ConnectionConfig) -> Option<(Box<dyn AsyncStream>, bool)> {
+    match &config.transport {
+        Transport::Tcp => connect_tcp(config).await,
+        Transport::WebSocket(url) => connect_websocket(url, config)
+            .await
+            .map(|stream| (stream, url.starts_with("wss://"))),
+        Transport::Quic => connect_quic(config).await.map(|stream| (stream, true)),
+        Transport::Unix(path) => connect_unix(path).await.map(|stream| (stream, true)),
+    }
+}
+
+/// ### Opens a Unix domain socket connection to the server.
+///
+/// The socket is local, so no TLS is attempted.
+#[cfg(unix)]
+async fn connect_unix(path: &str) -> Option<Box<dyn AsyncStream>> {
+    match UnixStream::connect(path).await {
+        Ok(stream) => {
+            LogMessage::log(
+                LogLevel::INFO,
+                format!("Successfully connected to unix:{}", path),
+                5,
+            )
+            .await;
+            Some(Box::new(stream))
+        }
+        Err(_) => {
+            LogMessage::log(
+                LogLevel::ERROR,
+                format!("Failed to connect to unix:{}", path),
+                0,
+            )
+            .await;
+            None
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn connect_unix(_path: &str) -> Option<Box<dyn AsyncStream>> {
+    LogMessage::log(
+        LogLevel::ERROR,
+        "Unix domain sockets are not supported on this platform".to_string(),
+        0,
+    )
+    .await;
+    None
+}
+
+/// ### Opens a TCP stream to the server.
+///
+/// The stream is tunneled through the proxy if one is configured,
+/// TLS is negotiated on top of the tunnel by the caller.
+async fn open_tcp_stream(
+    config: &ConnectionConfig,
+) -> Result<TcpStream, Box<dyn std::error::Error + Send + Sync>> {
+    match &config.proxy {
+        Some(proxy) => {
+            let port: u16 = config.port.parse()?;
+            connect_via_proxy(proxy, &config.hostname, port).await
+        }
+        None => Ok(TcpStream::connect(format!("{}:{}", config.hostname, config.port)).await?),
+    }
+}
+
+/// ### Opens a TCP connection to the server.
+///
+/// A TLS connection is tried first, if the TLS handshake fails
+/// the client falls back to a plain TCP connection.
+/// Returns the stream and whether TLS is enabled.
+async fn connect_tcp(config: &ConnectionConfig) -> Option<(Box<dyn AsyncStream>, bool)> {
+    let addr = &config.address();
+    let stream = match open_tcp_stream(config).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            LogMessage::log(
+                LogLevel::ERROR,
+                format!("Failed to connect to {}: {}", addr, e),
+                0,
+            )
+            .await;
+            return None;
+        }
+    };
+
+    LogMessage::log(
+        LogLevel::INFO,
+        "Establishing a secure TLS connection...".to_string(),
+        0,
+    )
+    .await;
+    if let Some(tls_stream) = try_tls_handshake(config, stream).await {
+        LogMessage::log(
+            LogLevel::INFO,
+            format!("Successfully connected to {}", addr),
+            5,
+        )
+        .await;
+        return Some((tls_stream, true));
+    }
+
+    match open_tcp_stream(config).await {
+        Ok(plain_stream) => {
+            LogMessage::log(
+                LogLevel::INFO,
+                format!("Successfully connected to {}, TLS not enabled", addr),
+                5,
+            )
+            .await;
+            Some((Box::new(plain_stream), false))
+        }
+        Err(_) => {
+            LogMessage::log(LogLevel::ERROR, format!("Failed to connect to {}", addr), 0).await;
+            None
+        }
+    }
+}
+
+/// ### Opens a WebSocket connection to the server.
+///
+/// `wss://` URLs require a successful TLS handshake, there is no fallback.
+async fn connect_websocket(url: &str, config: &ConnectionConfig) -> Option<Box<dyn AsyncStream>> {
+    let addr = format!("{}:{}", config.hostname, config.port);
+    let stream: Box<dyn AsyncStream> = match open_tcp_stream(config).await {
+        Ok(stream) => Box::new(stream),
+        Err(e) => {
+            LogMessage::log(
+                LogLevel::ERROR,
+                format!("Failed to connect to {}: {}", addr, e),
+                0,
+            )
+            .await;
+            return None;
+        }
+    };
+
+    let stream = match url.starts_with("wss://") {
+        true => match try_tls_handshake(config, stream).await {
+            Some(tls_stream) => tls_stream,
+            None => {
+                LogMessage::log(
+                    LogLevel::ERROR,
+                    format!("TLS handshake with {} failed", addr),
+                    0,
+                )
+                .await;
+                return None;
+            }
+        },
+        false => stream,
+    };
+
+    match tokio_tungstenite::client_async(url, stream).await {
+        Ok((ws, _)) => {
+            LogMessage::log(
+                LogLevel::INFO,
+                format!("Successfully connected to {}", url),
+                5,
+            )
+            .await;
+            Some(Box::new(WsStream::new(ws)))
+        }
+        Err(e) => {
+            LogMessage::log(
+                LogLevel::ERROR,
+                format!("WebSocket handshake failed: {}", e),
+                0,
+            )
+            .await;
+            None
+        }
+    }
+}
+
+/// ### Opens a QUIC connection to the server.
+///
+/// QUIC is always encrypted, there is no plain fallback.
+/// The session is carried by a single bidirectional stream.
+async fn connect_quic(config: &ConnectionConfig) -> Option<Box<dyn AsyncStream>> {
+    let addr = config.address();
+    let stream = match timeout(Duration::from_secs(10), open_quic_stream(config)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => {
+            LogMessage::log(
+                LogLevel::ERROR,
+                format!("Failed to connect to {} over QUIC: {}", addr, e),
+                0,
+            )
+            .await;
+            return None;
+        }
+        Err(_) => {
+            LogMessage::log(
+                LogLevel::ERROR,
+                format!("QUIC connection to {} timed out", addr),
+                0,
+            )
+            .await;
+            return None;
+        }
+    };
+
+    LogMessage::log(
+        LogLevel::INFO,
+        format!("Successfully connected to {} over QUIC", addr),
+        5,
+    )
+    .await;
+    Some(Box::new(stream))
+}
+
+async fn open_quic_stream(
+    config: &ConnectionConfig,
+) -> Result<QuicStream, Box<dyn std::error::Error + Send + Sync>> {
+    let remote = lookup_host(config.address())
+        .await?
+        .next()
+        .ok_or("No address found")?;
+    let local: SocketAddr = match remote.is_ipv6() {
+        true => (Ipv6Addr::UNSPECIFIED, 0).into(),
+        false => (Ipv4Addr::UNSPECIFIED, 0).into(),
+    };
+
+    let mut tls_config = create_tls_config(config).await?;
+    tls_config.alpn_protocols = vec![QUIC_ALPN.to_vec()];
+    let crypto = QuicClientConfig::try_from(tls_config)?;
+
+    let mut endpoint = Endpoint::client(local)?;
+    endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(crypto)));
+
+    let connection = endpoint.connect(remote, &config.hostname)?.await?;
+    let (send, recv) = connection.open_bi().await?;
+
+    Ok(QuicStream::new(connection, send, recv).with_endpoint(endpoint))
+}
+
+/// ### Builds the TLS client configuration.
+///
+/// Trusts the webpki roots and, if configured, the certificates in `ca_cert`.
+async fn create_tls_config(
+    config: &ConnectionConfig,
+) -> Result<ClientConfig, Box<dyn std::error::Error + Send + Sync>> {
     // Load the root certificates from the webpki-roots crate
     let mut root_store = rustls::RootCertStore::empty();
     root_store.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
 
+    if let Some(path) = &config.ca_cert {
+        for cert in CertificateDer::pem_file_iter(path)? {
+            root_store.add(cert?)?;
+        }
+    }
+
     // Create the client configuration
     let config = ClientConfig::builder()
         .with_root_certificates(root_store)
         .with_no_client_auth();
 
+    Ok(config)
+}
+
+pub async fn create_tls_connector(
+    config: &ConnectionConfig,
+) -> Result<TlsConnector, Box<dyn std::error::Error + Send + Sync>> {
+    let config = create_tls_config(config).await?;
+
     // Create the async TLS connector
     let connector = TlsConnector::from(Arc::new(config));
     Ok(connector)
 }
 
-pub async fn try_tls_handshake(
-    host_name: String,
-    stream: TcpStream,
-) -> Option<Box<dyn AsyncStream>> {
+pub async fn try_tls_handshake<S>(
+    config: &ConnectionConfig,
+    stream: S,
+) -> Option<Box<dyn AsyncStream>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
     // Convert the hostname string to a ServerName
-    let domain = match ServerName::try_from(host_name) {
+    let domain = match ServerName::try_from(config.hostname.clone()) {
         Ok(name) => name,
         Err(_) => return None,
     };
 
-    let tls_connector = match create_tls_connector().await {
+    let tls_connector = match create_tls_connector(config).await {
         Ok(connector) => connector,
         Err(e) => {
             LogMessage::log(
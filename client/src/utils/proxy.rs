@@ -0,0 +1,187 @@
+use crate::types::{ProxyConfig, ProxyKind};
+use base64::{Engine, engine::general_purpose::STANDARD};
+use std::net::IpAddr;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpStream, lookup_host},
+};
+
+type ProxyResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Upper bound for the response headers of an HTTP proxy.
+const MAX_HTTP_RESPONSE_LEN: usize = 8 * 1024;
+
+/// ### Opens a TCP tunnel to `host:port` through the proxy.
+///
+/// The returned stream is connected to the target, TLS or the
+/// WebSocket upgrade can be done on top of it as on a direct connection.
+pub async fn connect_via_proxy(
+    proxy: &ProxyConfig,
+    host: &str,
+    port: u16,
+) -> ProxyResult<TcpStream> {
+    let mut stream = TcpStream::connect((proxy.host.as_str(), proxy.port))
+        .await
+        .map_err(|e| {
+            format!(
+                "Failed to connect to proxy {}:{}: {}",
+                proxy.host, proxy.port, e
+            )
+        })?;
+
+    match proxy.kind {
+        ProxyKind::Socks5 | ProxyKind::Socks5h => {
+            socks5_connect(&mut stream, proxy, host, port).await?
+        }
+        ProxyKind::Http => http_connect(&mut stream, proxy, host, port).await?,
+    }
+
+    Ok(stream)
+}
+
+/// ### Performs the SOCKS5 handshake (RFC 1928) and the `CONNECT` request.
+///
+/// Username/password authentication (RFC 1929) is offered when credentials are configured.
+async fn socks5_connect(
+    stream: &mut TcpStream,
+    proxy: &ProxyConfig,
+    host: &str,
+    port: u16,
+) -> ProxyResult<()> {
+    // Greeting with the supported authentication methods
+    let greeting: &[u8] = match proxy.auth {
+        Some(_) => &[0x05, 0x02, 0x00, 0x02],
+        None => &[0x05, 0x01, 0x00],
+    };
+    stream.write_all(greeting).await?;
+
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).await?;
+    if reply[0] != 0x05 {
+        return Err("Proxy is not a SOCKS5 server".into());
+    }
+    match (reply[1], &proxy.auth) {
+        (0x00, _) => {}
+        (0x02, Some((username, password))) => {
+            if username.len() > 255 || password.len() > 255 {
+                return Err("Proxy username and password must be at most 255 bytes".into());
+            }
+            let mut request = vec![0x01, username.len() as u8];
+            request.extend_from_slice(username.as_bytes());
+            request.push(password.len() as u8);
+            request.extend_from_slice(password.as_bytes());
+            stream.write_all(&request).await?;
+
+            stream.read_exact(&mut reply).await?;
+            if reply[1] != 0x00 {
+                return Err("Proxy authentication failed".into());
+            }
+        }
+        _ => return Err("Proxy requires an unsupported authentication method".into()),
+    }
+
+    // Connect request, the hostname is only sent as is with socks5h
+    let mut request = vec![0x05, 0x01, 0x00];
+    let ip = match host.trim_matches(['[', ']']).parse::<IpAddr>() {
+        Ok(ip) => Some(ip),
+        Err(_) if proxy.kind == ProxyKind::Socks5 => {
+            let addr = lookup_host((host, port))
+                .await?
+                .next()
+                .ok_or_else(|| format!("Failed to resolve {}", host))?;
+            Some(addr.ip())
+        }
+        Err(_) => None,
+    };
+    match ip {
+        Some(IpAddr::V4(ip)) => {
+            request.push(0x01);
+            request.extend_from_slice(&ip.octets());
+        }
+        Some(IpAddr::V6(ip)) => {
+            request.push(0x04);
+            request.extend_from_slice(&ip.octets());
+        }
+        None => {
+            if host.len() > 255 {
+                return Err("Hostname is too long for SOCKS5".into());
+            }
+            request.push(0x03);
+            request.push(host.len() as u8);
+            request.extend_from_slice(host.as_bytes());
+        }
+    }
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    // Reply: version, status, reserved, then the bound address which is skipped
+    let mut reply = [0u8; 4];
+    stream.read_exact(&mut reply).await?;
+    if reply[1] != 0x00 {
+        return Err(format!("Proxy refused the connection: {}", socks5_error(reply[1])).into());
+    }
+    let addr_len = match reply[3] {
+        0x01 => 4,
+        0x04 => 16,
+        0x03 => stream.read_u8().await? as usize,
+        _ => return Err("Invalid SOCKS5 reply".into()),
+    };
+    let mut bound = vec![0u8; addr_len + 2];
+    stream.read_exact(&mut bound).await?;
+
+    Ok(())
+}
+
+/// Describes a SOCKS5 reply code
+fn socks5_error(code: u8) -> &'static str {
+    match code {
+        0x01 => "general failure",
+        0x02 => "connection not allowed by ruleset",
+        0x03 => "network unreachable",
+        0x04 => "host unreachable",
+        0x05 => "connection refused",
+        0x06 => "TTL expired",
+        0x07 => "command not supported",
+        0x08 => "address type not supported",
+        _ => "unknown error",
+    }
+}
+
+/// ### Opens an HTTP `CONNECT` tunnel.
+///
+/// Credentials are sent with basic `Proxy-Authorization`.
+async fn http_connect(
+    stream: &mut TcpStream,
+    proxy: &ProxyConfig,
+    host: &str,
+    port: u16,
+) -> ProxyResult<()> {
+    let target = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(_)) => format!("[{}]:{}", host, port),
+        _ => format!("{}:{}", host, port),
+    };
+
+    let mut request = format!("CONNECT {0} HTTP/1.1\r\nHost: {0}\r\n", target);
+    if let Some((username, password)) = &proxy.auth {
+        let credentials = STANDARD.encode(format!("{}:{}", username, password));
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", credentials));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes()).await?;
+
+    // Read the response headers byte by byte, so nothing of the tunnel is consumed
+    let mut response = Vec::new();
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_HTTP_RESPONSE_LEN {
+            return Err("Proxy response is too long".into());
+        }
+        response.push(stream.read_u8().await?);
+    }
+
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    match status_line.split_whitespace().nth(1) {
+        Some(status) if status.starts_with('2') => Ok(()),
+        _ => Err(format!("Proxy refused the connection: {}", status_line).into()),
+    }
+}
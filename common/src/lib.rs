@@ -1,3 +1,5 @@
 pub mod net;
+pub mod quic;
 pub mod types;
 pub mod utils;
+pub mod ws;
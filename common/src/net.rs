@@ -1,5 +1,6 @@
 use std::sync::Arc;
 
+use crate::types::{Compression, CompressionAlgo};
 use bincode::{Decode, Encode};
 use tokio::{
     io::{AsyncRead, AsyncWrite, ReadHalf, WriteHalf},
@@ -12,11 +13,21 @@ use tokio::{
 #[derive(Encode, Decode, PartialEq, Debug, Clone)]
 pub enum ChatMessageKind {
     /// Represents a command message
+    /// The server answers with the same kind and a [`ServerResponse`](crate::types::ServerResponse) payload
     Command(String),
     /// Represents a direct message
     DirectMessage(String),
     /// Represents a group message
     GroupMessage(String),
+    /// Represents a typing signal for a DM or group, the payload is empty
+    /// Relayed to the other members as a [`ServerEvent::Typing`](crate::types::ServerEvent::Typing), never stored
+    Typing(String),
+    /// Represents receipts for messages of a DM or group, the payload is a [`Receipt`](crate::types::Receipt)
+    /// Relayed to the author of the messages, never stored
+    Receipt(String),
+    /// Represents an event pushed by the server
+    /// The payload is a [`ServerEvent`](crate::types::ServerEvent)
+    Event,
 }
 
 /// Represents a chat network packet
@@ -28,6 +39,9 @@ pub struct Packet {
     pub payload: Vec<u8>,
 }
 
+/// Longest device id a client may send in the handshake
+pub const MAX_DEVICE_ID_LEN: usize = 64;
+
 /// Represents a handshake packet
 /// This is used to initiate a connection between clients
 #[derive(Encode, Decode, PartialEq, Debug)]
@@ -44,16 +58,59 @@ pub struct HandshakePacket {
     pub signature: Option<Vec<u8>>,
     /// The session key for the handshake
     pub session_key: Option<Vec<u8>>,
+    /// The resumption ticket, presented by the client in step 0
+    /// and issued by the server in step 3
+    pub ticket: Option<Vec<u8>>,
+    /// The compression algorithms offered by the client in step 0,
+    /// and the one picked by the server in step 3
+    pub compression: Option<Vec<CompressionAlgo>>,
+    /// The device the client connects from, sent in step 0
+    pub device_id: Option<String>,
 }
 
 /// Custom trait that bundles AsyncRead + AsyncWrite
 pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
 impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
 
+/// Read half of a stream along with the frame settings negotiated for it
+pub struct FrameReader {
+    /// The underlying read half
+    pub inner: ReadHalf<Box<dyn AsyncStream>>,
+    /// Algorithm compressed frames may use, compressed frames are refused until negotiated
+    pub compression: Option<CompressionAlgo>,
+}
+
+impl FrameReader {
+    /// Wraps a read half, compressed frames are refused
+    pub fn new(inner: ReadHalf<Box<dyn AsyncStream>>) -> Self {
+        FrameReader {
+            inner,
+            compression: None,
+        }
+    }
+}
+
 /// Represents a stream reader for a client
 // pub type StreamReader<S> = Arc<Mutex<OwnedReadHalf<S>>>;
-pub type StreamReader = Arc<Mutex<ReadHalf<Box<dyn AsyncStream>>>>;
+pub type StreamReader = Arc<Mutex<FrameReader>>;
+/// Write half of a stream along with the frame settings negotiated for it
+pub struct FrameWriter {
+    /// The underlying write half
+    pub inner: WriteHalf<Box<dyn AsyncStream>>,
+    /// Compression applied to outgoing frames, `None` until negotiated
+    pub compression: Option<Compression>,
+}
+
+impl FrameWriter {
+    /// Wraps a write half, frames are sent uncompressed
+    pub fn new(inner: WriteHalf<Box<dyn AsyncStream>>) -> Self {
+        FrameWriter {
+            inner,
+            compression: None,
+        }
+    }
+}
+
 /// Represents a stream writer for a client
 // pub type StreamWriter = Arc<Mutex<OwnedWriteHalf>>;
-pub type StreamWriter = Arc<Mutex<WriteHalf<Box<dyn AsyncStream>>>>;
-
+pub type StreamWriter = Arc<Mutex<FrameWriter>>;
@@ -0,0 +1,78 @@
+//! QUIC transport.
+//!
+//! [`QuicStream`] joins the two halves of a bidirectional QUIC stream,
+//! so a QUIC connection can be used everywhere an
+//! [`AsyncStream`](crate::net::AsyncStream) is expected.
+//! Each client connection carries a single bidirectional stream.
+
+use quinn::{Connection, Endpoint, RecvStream, SendStream};
+use std::{
+    io::Error,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// ALPN protocol identifier negotiated by both peers
+pub const QUIC_ALPN: &[u8] = b"null-talk";
+
+/// Byte stream over a bidirectional QUIC stream
+pub struct QuicStream {
+    send: SendStream,
+    recv: RecvStream,
+    /// Keeps the connection open as long as the stream lives
+    connection: Connection,
+    /// The client side owns its endpoint, the server shares one between all connections
+    _endpoint: Option<Endpoint>,
+}
+
+impl QuicStream {
+    /// Wraps an accepted or opened bidirectional stream
+    pub fn new(connection: Connection, send: SendStream, recv: RecvStream) -> Self {
+        QuicStream {
+            send,
+            recv,
+            connection,
+            _endpoint: None,
+        }
+    }
+
+    /// Keeps the given endpoint alive along with the stream
+    pub fn with_endpoint(mut self, endpoint: Endpoint) -> Self {
+        self._endpoint = Some(endpoint);
+        self
+    }
+
+    /// The underlying QUIC connection
+    pub fn connection(&self) -> &Connection {
+        &self.connection
+    }
+}
+
+impl AsyncRead for QuicStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.get_mut().recv).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for QuicStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, Error>> {
+        AsyncWrite::poll_write(Pin::new(&mut self.get_mut().send), cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.get_mut().send).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Error>> {
+        Pin::new(&mut self.get_mut().send).poll_shutdown(cx)
+    }
+}
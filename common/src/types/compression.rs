@@ -0,0 +1,41 @@
+use bincode::{Decode, Encode};
+use serde::Deserialize;
+
+/**
+ * Supported frame compression algorithms.
+ */
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Encode, Decode)]
+#[serde(rename_all = "PascalCase")]
+pub enum CompressionAlgo {
+    Zstd,
+    Deflate,
+}
+
+impl CompressionAlgo {
+    /// Byte written in front of a compressed frame body.
+    pub fn tag(&self) -> u8 {
+        match self {
+            CompressionAlgo::Zstd => 1,
+            CompressionAlgo::Deflate => 2,
+        }
+    }
+
+    /// Returns the algorithm for a frame tag, if known.
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            1 => Some(CompressionAlgo::Zstd),
+            2 => Some(CompressionAlgo::Deflate),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * Compression settings negotiated for one side of a connection.
+ */
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Compression {
+    pub algo: CompressionAlgo,
+    /// Frames smaller than this many bytes are sent raw.
+    pub threshold: usize,
+}
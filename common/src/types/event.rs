@@ -0,0 +1,146 @@
+use bincode::{Decode, Encode};
+
+use crate::types::GroupRole;
+
+/// Events pushed by the server, outside of any command exchange
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub enum ServerEvent {
+    /// Outcome of routing a message sent by this client
+    MessageStatus(MessageStatus),
+    /// A user sharing a DM or group with this client changed its presence or status
+    Presence(PresenceUpdate),
+    /// A member of a DM or group is typing
+    Typing(TypingEvent),
+    /// The members, name, roles or key of a group this client is part of changed
+    Group(GroupEvent),
+    /// A contact request to this client was made, accepted or rejected
+    Contact(ContactEvent),
+    /// This client sends faster than the server allows
+    RateLimit(RateLimitEvent),
+}
+
+/// How a message reached its recipients
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct MessageStatus {
+    /// DM or group the message was sent to
+    pub session_id: String,
+    /// Timestamp of the message, identifies it along with the session
+    pub timestamps: u128,
+    /// Id assigned to the message by the server
+    pub message_id: String,
+    /// Recipients the message was delivered to
+    pub delivered: u32,
+    /// Offline recipients the message was queued for
+    pub queued: u32,
+    /// Recipients whose queue was full, offline or on some of their devices
+    pub dropped: u32,
+    /// Recipients that didn't accept messages from this client yet, or blocked it
+    pub refused: u32,
+}
+
+/// Availability of a user
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone, Copy, Default)]
+pub enum Presence {
+    #[default]
+    Online,
+    Away,
+    DoNotDisturb,
+    /// None of the devices of the user is connected
+    Offline,
+}
+
+/// Presence and status line of a user
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct PresenceUpdate {
+    /// User the update is about
+    pub user_id: String,
+    /// Username of the user, unknown while it is offline
+    pub username: Option<String>,
+    pub presence: Presence,
+    /// Custom status line set by the user
+    pub status: Option<String>,
+    /// DMs and groups this client shares with the user
+    pub chats: Vec<String>,
+}
+
+/// A member of a DM or group is typing, it stops being shown after a few seconds
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct TypingEvent {
+    /// DM or group the user is typing in
+    pub session_id: String,
+    /// User typing
+    pub user_id: String,
+    /// Username of the user typing
+    pub username: Option<String>,
+}
+
+/// A change made to a group, pushed to its members
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct GroupEvent {
+    pub group_id: String,
+    /// Name of the group, the new one once renamed
+    pub group_name: String,
+    /// User that made the change
+    pub actor_id: String,
+    pub kind: GroupEventKind,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub enum GroupEventKind {
+    MemberAdded(String),
+    MemberRemoved(String),
+    /// The actor left the group
+    MemberLeft,
+    Renamed,
+    Deleted,
+    /// The given member has a new role
+    RoleChanged(String, GroupRole),
+    /// Ownership was transferred to the given member, the previous owner is now an admin
+    OwnerChanged(String),
+    /// New session key of the group, only sent to its members
+    Rekeyed(Vec<u8>),
+    /// We were added to the group, with its session key
+    Joined {
+        session_key: Vec<u8>,
+        channel: bool,
+    },
+}
+
+/// A contact request between this client and another user, pushed to the other side
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct ContactEvent {
+    /// DM session the request is for
+    pub dm_id: String,
+    /// User that made, accepted or rejected the request
+    pub user_id: String,
+    /// Handle or username of the user
+    pub username: Option<String>,
+    pub kind: ContactEventKind,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub enum ContactEventKind {
+    /// The user wants to start a DM, it can't message until the request is accepted
+    Requested,
+    Accepted,
+    /// The DM session was deleted
+    Rejected,
+}
+
+/// Sent when a client goes over a rate limit, the connection is closed once `remaining` is 0
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct RateLimitEvent {
+    /// Limit that was exceeded
+    pub limit: RateLimitKind,
+    /// Whether what the client sends is now delayed until it is within the limit
+    pub throttled: bool,
+    /// Violations left before the server closes the connection
+    pub remaining: u32,
+}
+
+#[derive(Encode, Decode, PartialEq, Eq, Debug, Clone, Copy)]
+pub enum RateLimitKind {
+    Messages,
+    Commands,
+    Bytes,
+}
@@ -0,0 +1,85 @@
+use bincode::{Decode, Encode};
+use std::{fmt, str::FromStr};
+
+/// Role of a member inside a group, ordered from the least to the most privileged
+#[derive(Encode, Decode, PartialEq, Eq, PartialOrd, Ord, Debug, Clone, Copy, Default)]
+pub enum GroupRole {
+    /// Can read the group but not post in it
+    ReadOnly,
+    #[default]
+    Member,
+    Moderator,
+    Admin,
+    /// Created the group or had it transferred, there is exactly one
+    Owner,
+}
+
+/// Actions restricted by the role of a member
+#[derive(PartialEq, Eq, Debug, Clone, Copy)]
+pub enum GroupPermission {
+    /// Send messages and typing signals to the group
+    Post,
+    /// Add members to the group
+    Invite,
+    /// Remove members with a lower role
+    Remove,
+    /// Give members with a lower role another role, lower than its own
+    SetRole,
+    /// Rename the group
+    Rename,
+    /// Replace the session key of the group
+    Rekey,
+}
+
+impl GroupRole {
+    /// Whether the role grants a permission
+    ///
+    /// |           | owner | admin | moderator | member | read-only |
+    /// |-----------|-------|-------|-----------|--------|-----------|
+    /// | post      | ✓     | ✓     | ✓         | ✓      |           |
+    /// | invite    | ✓     | ✓     | ✓         |        |           |
+    /// | remove    | ✓     | ✓     | ✓         |        |           |
+    /// | set role  | ✓     | ✓     | ✓         |        |           |
+    /// | rename    | ✓     | ✓     |           |        |           |
+    /// | rekey     | ✓     | ✓     |           |        |           |
+    pub fn can(self, permission: GroupPermission) -> bool {
+        match permission {
+            GroupPermission::Post => self >= GroupRole::Member,
+            GroupPermission::Invite | GroupPermission::Remove | GroupPermission::SetRole => {
+                self >= GroupRole::Moderator
+            }
+            GroupPermission::Rename | GroupPermission::Rekey => self >= GroupRole::Admin,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            GroupRole::ReadOnly => "read-only",
+            GroupRole::Member => "member",
+            GroupRole::Moderator => "moderator",
+            GroupRole::Admin => "admin",
+            GroupRole::Owner => "owner",
+        }
+    }
+}
+
+impl fmt::Display for GroupRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for GroupRole {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read-only" => Ok(GroupRole::ReadOnly),
+            "member" => Ok(GroupRole::Member),
+            "moderator" => Ok(GroupRole::Moderator),
+            "admin" => Ok(GroupRole::Admin),
+            "owner" => Ok(GroupRole::Owner),
+            other => Err(format!("Unknown role: {}", other)),
+        }
+    }
+}
@@ -1,5 +1,11 @@
+pub mod compression;
 pub mod enc;
+pub mod event;
+pub mod group;
 pub mod payload;
 
+pub use compression::*;
 pub use enc::*;
+pub use event::*;
+pub use group::*;
 pub use payload::*;
@@ -1,14 +1,16 @@
 use bincode::{Decode, Encode};
 
-use crate::types::SymmetricAlgo;
+use crate::types::{GroupRole, Presence, SymmetricAlgo};
 
 /**
- * Represents the current chat mode (none, direct message, or group).
+ * Represents the current chat mode (none, direct message, group or broadcast channel).
  */
 #[derive(Clone, Debug, Encode, Decode, PartialEq)]
 pub enum ChatMode {
     Dm(String),
     Group(String),
+    /// Read-only for subscribers, only publishers can post
+    Channel(String),
 }
 
 #[derive(Encode, Decode, PartialEq, Debug, serde::Deserialize)]
@@ -31,6 +33,70 @@ pub struct AddGroupMemberPayload {
     pub member_id: String,
 }
 
+/// Targets a member of a group, to remove, promote, demote or make it the owner
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct GroupMemberPayload {
+    pub group_id: String,
+    pub member_id: String,
+}
+
+/// Targets a group, to leave or delete it
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct GroupPayload {
+    pub group_id: String,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct GroupRolePayload {
+    pub group_id: String,
+    pub member_id: String,
+    pub role: GroupRole,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct GroupMembersResponse {
+    pub members: Vec<GroupMemberInfo>,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug, Clone)]
+pub struct GroupMemberInfo {
+    pub user_id: String,
+    pub role: GroupRole,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct RenameGroupPayload {
+    pub group_id: String,
+    pub name: String,
+}
+
+/// Asks for an invite token to a group, unlimited and never expiring if not set
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct CreateInvitePayload {
+    pub group_id: String,
+    pub max_uses: Option<u32>,
+    /// seconds the invite stays valid
+    pub expires_in: Option<u64>,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct InviteResponse {
+    pub token: String,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct JoinGroupPayload {
+    pub token: String,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct JoinGroupResponse {
+    pub group_id: String,
+    pub group_name: String,
+    pub session_key: Vec<u8>,
+    pub channel: bool,
+}
+
 #[derive(Encode, Decode, PartialEq, Debug)]
 pub struct NewGroupResponse {
     pub session_key: Vec<u8>,
@@ -41,6 +107,7 @@ pub struct NewGroupResponse {
 pub struct NewSessionResponse {
     pub id: String,
     pub session_key: Vec<u8>,
+    pub pending: bool, // Contact request the other member hasn't accepted yet
 }
 
 #[derive(Encode, Decode, PartialEq, Debug)]
@@ -52,9 +119,109 @@ pub struct ServerResponse {
 
 #[derive(Clone, Debug, Encode, Decode, PartialEq, Eq)]
 pub struct Message {
-    pub id: String,               // SessionId or GroupId
-    pub sender_id: String,        // UserId of sender
-    pub username: Option<String>, // UserName of sender
-    pub content: Vec<u8>,          // Message content
-    pub timestamps: u128,          // Timestamp
+    pub id: String,                 // SessionId or GroupId
+    pub sender_id: String,          // UserId of sender
+    pub username: Option<String>,   // UserName of sender
+    pub content: Vec<u8>,           // Message content
+    pub timestamps: u128,           // Timestamp
+    pub message_id: Option<String>, // Assigned by the server when relayed
+}
+
+/// Position in the history of a chat, entries before it are returned
+#[derive(Clone, Copy, Debug, Encode, Decode, PartialEq)]
+pub enum HistoryCursor {
+    Id(u64),         // History entry id
+    Timestamp(u128), // Message timestamp
+}
+
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct HistoryRequest {
+    pub session_id: String,
+    pub before: Option<HistoryCursor>, // Latest messages if None
+    pub limit: u32,
+}
+
+#[derive(Clone, Debug, Encode, Decode, PartialEq)]
+pub struct HistoryMessage {
+    pub id: u64,          // History entry id
+    pub message: Message, // Message as sent, content still encrypted
+}
+
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct HistoryResponse {
+    pub messages: Vec<HistoryMessage>, // Oldest first
+    pub more: bool,                    // Whether older messages are left
+}
+
+#[derive(Clone, Debug, Encode, Decode, PartialEq)]
+pub struct DeviceInfo {
+    pub device_id: String,
+    pub connected_at: u64, // Unix timestamp (seconds)
+    pub current: bool,     // Whether it's the device asking
+}
+
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct DeviceListResponse {
+    pub devices: Vec<DeviceInfo>,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct RevokeDevicePayload {
+    pub device_id: String,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct SetStatusPayload {
+    pub presence: Presence,     // Offline isn't accepted
+    pub status: Option<String>, // Custom status line, cleared if None
+}
+
+/// Acknowledgement of messages by one of their recipients
+#[derive(Clone, Debug, Encode, Decode, PartialEq)]
+pub struct Receipt {
+    pub reader_id: String, // UserId of the recipient, set by the server
+    pub author_id: String, // UserId of the author of the messages
+    pub kind: ReceiptKind,
+}
+
+#[derive(Clone, Debug, Encode, Decode, PartialEq)]
+pub enum ReceiptKind {
+    Delivered(Vec<String>), // Message ids received by a device of the recipient
+    Read(Vec<u8>),          // Comma separated message ids, encrypted with the session key
+}
+
+/// Claims a handle, replacing the previous handle of the user
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct ClaimHandlePayload {
+    pub handle: String,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct WhoisPayload {
+    pub handle: String,
+}
+
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct WhoisResponse {
+    pub handle: String,
+    pub user_id: String,
+    pub public_key: String, // OpenSSH format
+}
+
+/// Targets another user, to reject its contact request, block or unblock it
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct ContactPayload {
+    pub user_id: String,
+}
+
+#[derive(Clone, Debug, Encode, Decode, PartialEq)]
+pub struct UserInfo {
+    pub user_id: String,
+    pub username: Option<String>, // Handle of the user, if it has one
+}
+
+/// Pending contact requests or blocked users
+#[derive(Encode, Decode, PartialEq, Debug)]
+pub struct UserListResponse {
+    pub users: Vec<UserInfo>,
 }
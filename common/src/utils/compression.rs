@@ -0,0 +1,53 @@
+//! Frame compression utilities.
+//! Compression is applied to whole frames, i.e. outside of the end-to-end
+//! encrypted message content, so compressed sizes leak nothing about plaintext.
+
+use crate::types::CompressionAlgo;
+use flate2::{Compression as DeflateLevel, read::DeflateDecoder, write::DeflateEncoder};
+use std::io::{Error, ErrorKind, Read, Write};
+use zstd::stream::read::Decoder as ZstdDecoder;
+
+/// Frames are never decompressed to more than this many bytes.
+pub const MAX_DECOMPRESSED_LEN: usize = 16 * 1024 * 1024;
+
+/// Frames below this size are sent raw unless configured otherwise.
+pub const DEFAULT_COMPRESSION_THRESHOLD: usize = 512;
+
+/// Compresses a frame body with the given algorithm.
+pub fn compress(algo: CompressionAlgo, data: &[u8]) -> Result<Vec<u8>, Error> {
+    match algo {
+        CompressionAlgo::Zstd => zstd::bulk::compress(data, zstd::DEFAULT_COMPRESSION_LEVEL),
+        CompressionAlgo::Deflate => {
+            let mut encoder = DeflateEncoder::new(Vec::new(), DeflateLevel::default());
+            encoder.write_all(data)?;
+            encoder.finish()
+        }
+    }
+}
+
+/// Decompresses a frame body with the given algorithm.
+/// Returns an error if the output would exceed [`MAX_DECOMPRESSED_LEN`].
+pub fn decompress(algo: CompressionAlgo, data: &[u8]) -> Result<Vec<u8>, Error> {
+    decompress_limited(algo, data, MAX_DECOMPRESSED_LEN)
+}
+
+/// Decompresses a frame body with the given algorithm.
+/// Returns an error if the output would exceed `max_len` bytes.
+pub fn decompress_limited(
+    algo: CompressionAlgo,
+    data: &[u8],
+    max_len: usize,
+) -> Result<Vec<u8>, Error> {
+    // Decoded as a stream, the output only grows as far as the data goes
+    let decoder: Box<dyn Read> = match algo {
+        CompressionAlgo::Zstd => Box::new(ZstdDecoder::with_buffer(data)?),
+        CompressionAlgo::Deflate => Box::new(DeflateDecoder::new(data)),
+    };
+    let mut out = Vec::new();
+    decoder.take(max_len as u64 + 1).read_to_end(&mut out)?;
+
+    if out.len() > max_len {
+        return Err(Error::new(ErrorKind::InvalidData, "Frame too large"));
+    }
+    Ok(out)
+}
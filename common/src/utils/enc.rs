@@ -33,52 +33,7 @@ pub fn encrypt_message(
     message: &str,
     enc_config: EncryptionConfig,
 ) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
-    match enc_config.algo {
-        SymmetricAlgo::AES256 => {
-            let encryption_key = &enc_config.encryption_key.expect("❗️Missing encryption key");
-            let key = Key::<Aes256Gcm>::from_slice(&encryption_key); // Normally random
-            let cipher = Aes256Gcm::new(key);
-
-            // Generate a random 96-bit nonce (12 bytes)
-            let mut nonce_bytes = [0u8; 12];
-            OsRng.fill_bytes(&mut nonce_bytes);
-            let nonce = Nonce::from_slice(&nonce_bytes);
-
-            // Encrypt
-            let ciphertext = match cipher.encrypt(nonce, message.as_bytes()) {
-                Ok(ct) => ct,
-                Err(_) => return Err("❗️Encryption failed".into()),
-            };
-
-            // Return nonce + ciphertext as base64 so you can decrypt later
-            let mut combined = nonce_bytes.to_vec();
-            combined.extend(ciphertext);
-
-            Ok(combined)
-        }
-        SymmetricAlgo::ChaCha20 => {
-            let encryption_key = enc_config.encryption_key.expect("❗️Missing encryption key");
-            let key = ChaChaKey::from_slice(&encryption_key);
-            let cipher = ChaCha20Poly1305::new(key);
-
-            // Generate a random 96-bit nonce (12 bytes)
-            let mut nonce_bytes = [0u8; 12];
-            OsRng.fill_bytes(&mut nonce_bytes);
-            let nonce = ChaChaNonce::from_slice(&nonce_bytes);
-
-            // Encrypt
-            let ciphertext = match cipher.encrypt(nonce, message.as_bytes()) {
-                Ok(ct) => ct,
-                Err(_) => return Err("❗️Encryption failed".into()),
-            };
-
-            // Return nonce + ciphertext as base64 so you can decrypt later
-            let mut combined = nonce_bytes.to_vec();
-            combined.extend(ciphertext);
-
-            Ok(combined)
-        }
-    }
+    encrypt_bytes(message.as_bytes(), enc_config)
 }
 
 /// Decrypts a message using the specified encryption configuration.
@@ -88,44 +43,76 @@ pub fn decrypt_message(
     message: &[u8],
     enc_config: EncryptionConfig,
 ) -> Result<String, Box<dyn Error + Send + Sync>> {
-    match enc_config.algo {
+    let plaintext = decrypt_bytes(message, enc_config)?;
+    let msg = String::from_utf8_lossy(&plaintext);
+
+    Ok(msg.into_owned())
+}
+
+/// Encrypts raw bytes using the specified encryption configuration.
+/// Returns the 12 bytes nonce followed by the ciphertext.
+pub fn encrypt_bytes(
+    data: &[u8],
+    enc_config: EncryptionConfig,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let encryption_key = enc_config
+        .encryption_key
+        .ok_or("❗️Missing encryption key")?;
+
+    // Generate a random 96-bit nonce (12 bytes)
+    let mut nonce_bytes = [0u8; 12];
+    OsRng.fill_bytes(&mut nonce_bytes);
+
+    // Encrypt
+    let ciphertext = match enc_config.algo {
         SymmetricAlgo::AES256 => {
-            let encryption_key = &enc_config.encryption_key.expect("❗️Missing encryption key");
-            let key = Key::<Aes256Gcm>::from_slice(&encryption_key); // Normally random
-            let cipher = Aes256Gcm::new(key);
-
-            // Get 12 bytes nonce
-            let nonce_bytes = message[0..12].to_vec();
-            let nonce = Nonce::from_slice(&nonce_bytes);
-
-            // Decrypt
-            let ciphertext = match cipher.decrypt(nonce, &message[12..]) {
-                Ok(ct) => ct,
-                Err(_) => return Err("❗️Decryption failed".into()),
-            };
-            let msg = String::from_utf8_lossy(&ciphertext);
-
-            Ok(msg.into_owned())
+            let key = Key::<Aes256Gcm>::from_slice(&encryption_key);
+            Aes256Gcm::new(key).encrypt(Nonce::from_slice(&nonce_bytes), data)
         }
         SymmetricAlgo::ChaCha20 => {
-            let encryption_key = enc_config.encryption_key.expect("❗️Missing encryption key");
             let key = ChaChaKey::from_slice(&encryption_key);
-            let cipher = ChaCha20Poly1305::new(key);
+            ChaCha20Poly1305::new(key).encrypt(ChaChaNonce::from_slice(&nonce_bytes), data)
+        }
+    }
+    .map_err(|_| "❗️Encryption failed")?;
 
-            // Get 12 bytes nonce
-            let nonce_bytes = message[0..12].to_vec();
-            let nonce = ChaChaNonce::from_slice(&nonce_bytes);
+    // Return nonce + ciphertext so you can decrypt later
+    let mut combined = nonce_bytes.to_vec();
+    combined.extend(ciphertext);
 
-            // Encrypt
-            let ciphertext = match cipher.encrypt(nonce, &message[12..]) {
-                Ok(ct) => ct,
-                Err(_) => return Err("❗️Decryption failed".into()),
-            };
-            let msg = String::from_utf8_lossy(&ciphertext);
+    Ok(combined)
+}
 
-            Ok(msg.into_owned())
+/// Decrypts bytes produced by [`encrypt_bytes`].
+/// Returns error if the data is malformed or was tampered with.
+pub fn decrypt_bytes(
+    data: &[u8],
+    enc_config: EncryptionConfig,
+) -> Result<Vec<u8>, Box<dyn Error + Send + Sync>> {
+    let encryption_key = enc_config
+        .encryption_key
+        .ok_or("❗️Missing encryption key")?;
+    if data.len() < 12 {
+        return Err("❗️Decryption failed".into());
+    }
+
+    // Get 12 bytes nonce
+    let (nonce_bytes, ciphertext) = data.split_at(12);
+
+    // Decrypt
+    let plaintext = match enc_config.algo {
+        SymmetricAlgo::AES256 => {
+            let key = Key::<Aes256Gcm>::from_slice(&encryption_key);
+            Aes256Gcm::new(key).decrypt(Nonce::from_slice(nonce_bytes), ciphertext)
+        }
+        SymmetricAlgo::ChaCha20 => {
+            let key = ChaChaKey::from_slice(&encryption_key);
+            ChaCha20Poly1305::new(key).decrypt(ChaChaNonce::from_slice(nonce_bytes), ciphertext)
         }
     }
+    .map_err(|_| "❗️Decryption failed")?;
+
+    Ok(plaintext)
 }
 
 /// Attempts to parse an RSA private key from various formats.
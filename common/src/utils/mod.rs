@@ -1,3 +1,4 @@
+pub mod compression;
 pub mod enc;
 pub mod file;
 pub mod net;
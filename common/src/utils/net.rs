@@ -1,14 +1,26 @@
 //! Networking utilities for reading and writing packets.
 //! Provides functions to read and write packets over a TCP connection.
+//!
+//! Every frame starts with a `u32` length. If the highest bit of the length
+//! is set, the frame body is compressed: its first byte is the
+//! [`CompressionAlgo`] tag and the rest is the compressed packet.
+//! Compressed frames are only accepted once an algorithm was negotiated.
 
-use crate::net::{StreamReader, StreamWriter};
+use crate::{
+    net::{StreamReader, StreamWriter},
+    types::CompressionAlgo,
+    utils::compression::{MAX_DECOMPRESSED_LEN, compress, decompress_limited},
+};
 use tokio::io::{AsyncReadExt, AsyncWriteExt};
 
+/// Bit of the frame length marking a compressed frame.
+const COMPRESSED_FLAG: u32 = 1 << 31;
+
 /// Reads and decodes a packet from the provided stream.
 ///
-/// This function acquires a lock on the underlying [`OwnedReadHalf`] (wrapped in
-/// [`Arc`] + [`tokio::sync::Mutex`]), reads the next frame of data, and attempts to
-/// deserialize it into a type `P` using [`bincode`].
+/// This function acquires a lock on the underlying [`FrameReader`] (wrapped in
+/// [`Arc`] + [`tokio::sync::Mutex`]), reads the next frame of data, decompresses
+/// it if needed, and attempts to deserialize it into a type `P` using [`bincode`].
 ///
 /// # Type Parameters
 ///
@@ -18,15 +30,18 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 ///
 /// Returns an error if:
 /// - The stream cannot be read (e.g. due to I/O issues).
+/// - A compressed frame cannot be decompressed, or uses an algorithm
+///   that wasn't negotiated for the reader.
 /// - The bytes cannot be decoded into `P` using [`bincode`].
 ///
 /// # Examples
 ///
 /// ```no_run
+/// use common::net::{AsyncStream, FrameReader, StreamReader};
+/// use common::utils::net::read_packet;
 /// use tokio::net::TcpStream;
 /// use tokio::sync::Mutex;
 /// use std::sync::Arc;
-/// use my_crate::read_packet;
 ///
 /// #[derive(bincode::Decode)]
 /// struct MyPacket {
@@ -36,9 +51,9 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 ///
 /// #[tokio::main]
 /// async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-///     let stream = TcpStream::connect("127.0.0.1:8080").await?;
-///     let (rd, _) = stream.into_split();
-///     let reader = Arc::new(Mutex::new(rd));
+///     let stream: Box<dyn AsyncStream> = Box::new(TcpStream::connect("127.0.0.1:8080").await?);
+///     let (rd, _) = tokio::io::split(stream);
+///     let reader: StreamReader = Arc::new(Mutex::new(FrameReader::new(rd)));
 ///
 ///     let packet: MyPacket = read_packet(reader).await?;
 ///     println!("Got packet: id={}, payload={}", packet.id, packet.payload);
@@ -47,18 +62,51 @@ use tokio::io::{AsyncReadExt, AsyncWriteExt};
 /// }
 /// ```
 ///
-/// [`OwnedReadHalf`]: tokio::net::tcp::OwnedReadHalf
+/// [`FrameReader`]: crate::net::FrameReader
 /// [`Arc`]: std::sync::Arc
 /// [`tokio::sync::Mutex`]: tokio::sync::Mutex
 pub async fn read_packet<P>(rd: StreamReader) -> Result<P, Box<dyn std::error::Error + Send + Sync>>
+where
+    P: bincode::Decode<()>,
+{
+    read_packet_limited(rd, !COMPRESSED_FLAG as usize).await
+}
+
+/// Reads and decodes a packet like [`read_packet`], from a frame of at most `max_len` bytes.
+///
+/// Nothing is allocated for a longer frame, it is an error, and so is a
+/// compressed frame that would decompress to more than `max_len` bytes
+/// (or [`MAX_DECOMPRESSED_LEN`]).
+/// Used to bound what a peer that isn't trusted yet can make us buffer.
+pub async fn read_packet_limited<P>(
+    rd: StreamReader,
+    max_len: usize,
+) -> Result<P, Box<dyn std::error::Error + Send + Sync>>
 where
     P: bincode::Decode<()>,
 {
     let mut reader = rd.lock().await;
 
-    let len = reader.read_u32().await?;
-    let mut buf = vec![0u8; len as usize];
-    reader.read_exact(&mut buf).await?;
+    let len = reader.inner.read_u32().await?;
+    let frame_len = (len & !COMPRESSED_FLAG) as usize;
+    if frame_len > max_len {
+        return Err(format!("❗️Frame of {} bytes is too large", frame_len).into());
+    }
+    let compressed = len & COMPRESSED_FLAG != 0;
+    if compressed && reader.compression.is_none() {
+        return Err("❗️Compressed frame without negotiated compression".into());
+    }
+    let mut buf = vec![0u8; frame_len];
+    reader.inner.read_exact(&mut buf).await?;
+
+    if compressed {
+        let (tag, body) = buf.split_first().ok_or("❗️Empty compressed frame")?;
+        let algo = CompressionAlgo::from_tag(*tag).ok_or("❗️Unknown compression algorithm")?;
+        if reader.compression != Some(algo) {
+            return Err("❗️Compression algorithm wasn't negotiated".into());
+        }
+        buf = decompress_limited(algo, body, max_len.min(MAX_DECOMPRESSED_LEN))?;
+    }
     let (packet, _): (P, usize) = bincode::decode_from_slice(&buf, bincode::config::standard())?;
 
     Ok(packet)
@@ -66,9 +114,11 @@ where
 
 /// Encodes and writes a packet to the provided stream.
 ///
-/// This function acquires a lock on the underlying [`OwnedWriteHalf`] (wrapped in
+/// This function acquires a lock on the underlying [`FrameWriter`] (wrapped in
 /// [`Arc`] + [`tokio::sync::Mutex`]), serializes the given `packet` using [`bincode`],
-/// and writes the encoded bytes to the stream.
+/// and writes the encoded bytes to the stream. If compression was negotiated for
+/// the writer, frames at or above its threshold are compressed, unless that
+/// doesn't make them smaller.
 ///
 /// # Type Parameters
 ///
@@ -83,10 +133,11 @@ where
 /// # Examples
 ///
 /// ```no_run
+/// use common::net::{AsyncStream, FrameWriter, StreamWriter};
+/// use common::utils::net::write_packet;
 /// use tokio::net::TcpStream;
 /// use tokio::sync::Mutex;
 /// use std::sync::Arc;
-/// use my_crate::write_packet;
 ///
 /// #[derive(bincode::Encode)]
 /// struct MyPacket {
@@ -96,9 +147,9 @@ where
 ///
 /// #[tokio::main]
 /// async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-///     let stream = TcpStream::connect("127.0.0.1:8080").await?;
-///     let (_, wt) = stream.into_split();
-///     let writer = Arc::new(Mutex::new(wt));
+///     let stream: Box<dyn AsyncStream> = Box::new(TcpStream::connect("127.0.0.1:8080").await?);
+///     let (_, wt) = tokio::io::split(stream);
+///     let writer: StreamWriter = Arc::new(Mutex::new(FrameWriter::new(wt)));
 ///
 ///     let packet = MyPacket { id: 42, payload: "hello".into() };
 ///     write_packet(writer, packet).await?;
@@ -107,7 +158,7 @@ where
 /// }
 /// ```
 ///
-/// [`OwnedWriteHalf`]: tokio::net::tcp::OwnedWriteHalf
+/// [`FrameWriter`]: crate::net::FrameWriter
 /// [`Arc`]: std::sync::Arc
 /// [`tokio::sync::Mutex`]: tokio::sync::Mutex
 pub async fn write_packet<P>(
@@ -119,8 +170,21 @@ where
 {
     let mut writer = wt.lock().await;
 
-    let encoded = bincode::encode_to_vec(packet, bincode::config::standard())?;
-    writer.write_u32(encoded.len() as u32).await?;
+    let mut encoded = bincode::encode_to_vec(packet, bincode::config::standard())?;
+    let mut len = encoded.len() as u32;
+
+    if let Some(compression) = writer.compression.filter(|c| encoded.len() >= c.threshold) {
+        let mut body = vec![compression.algo.tag()];
+        body.extend(compress(compression.algo, &encoded)?);
+
+        if body.len() < encoded.len() {
+            len = body.len() as u32 | COMPRESSED_FLAG;
+            encoded = body;
+        }
+    }
+
+    let writer = &mut writer.inner;
+    writer.write_u32(len).await?;
     writer.write_all(&encoded).await?;
     writer.flush().await?;
 
@@ -132,6 +196,7 @@ pub async fn close_connection(
     reason: &str,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
     let mut w = writer.lock().await;
+    let w = &mut w.inner;
     let _ = w.write_all(reason.as_bytes()).await;
     let _ = w.flush().await;
     let _ = w.shutdown().await;
@@ -0,0 +1,114 @@
+//! WebSocket transport.
+//!
+//! [`WsStream`] adapts a [`WebSocketStream`] into a byte stream, so the
+//! same length-prefixed frames can be carried over `ws://` and `wss://`
+//! and used everywhere an [`AsyncStream`](crate::net::AsyncStream) is expected.
+//! Every flushed frame is sent as a single binary WebSocket message.
+
+use futures_util::{Sink, Stream};
+use std::{
+    io::{Error, ErrorKind},
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_tungstenite::{WebSocketStream, tungstenite::Message};
+
+/// Byte stream over a WebSocket connection
+pub struct WsStream<S> {
+    inner: WebSocketStream<S>,
+    /// Bytes of the last received message not read yet
+    read_buf: Vec<u8>,
+    read_pos: usize,
+    /// Bytes written since the last flush
+    write_buf: Vec<u8>,
+}
+
+impl<S> WsStream<S> {
+    /// Wraps an established WebSocket connection
+    pub fn new(inner: WebSocketStream<S>) -> Self {
+        WsStream {
+            inner,
+            read_buf: Vec::new(),
+            read_pos: 0,
+            write_buf: Vec::new(),
+        }
+    }
+}
+
+fn to_io_error(err: tokio_tungstenite::tungstenite::Error) -> Error {
+    Error::other(err)
+}
+
+impl<S> AsyncRead for WsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        while self.read_pos >= self.read_buf.len() {
+            match ready!(Pin::new(&mut self.inner).poll_next(cx)) {
+                Some(Ok(Message::Binary(data))) => {
+                    self.read_buf = data.to_vec();
+                    self.read_pos = 0;
+                }
+                // Control frames are answered by tungstenite itself
+                Some(Ok(Message::Ping(_) | Message::Pong(_) | Message::Frame(_))) => continue,
+                Some(Ok(Message::Text(_))) => {
+                    return Poll::Ready(Err(Error::new(
+                        ErrorKind::InvalidData,
+                        "Unexpected text message",
+                    )));
+                }
+                Some(Ok(Message::Close(_))) | None => return Poll::Ready(Ok(())),
+                Some(Err(err)) => return Poll::Ready(Err(to_io_error(err))),
+            }
+        }
+
+        let available = &self.read_buf[self.read_pos..];
+        let len = available.len().min(buf.remaining());
+        buf.put_slice(&available[..len]);
+        self.read_pos += len;
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S> AsyncWrite for WsStream<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        self.write_buf.extend_from_slice(buf);
+        Poll::Ready(Ok(buf.len()))
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        if !self.write_buf.is_empty() {
+            ready!(Pin::new(&mut self.inner).poll_ready(cx)).map_err(to_io_error)?;
+
+            let data = std::mem::take(&mut self.write_buf);
+            Pin::new(&mut self.inner)
+                .start_send(Message::Binary(data.into()))
+                .map_err(to_io_error)?;
+        }
+
+        Pin::new(&mut self.inner)
+            .poll_flush(cx)
+            .map_err(to_io_error)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        ready!(self.as_mut().poll_flush(cx))?;
+        Pin::new(&mut self.inner)
+            .poll_close(cx)
+            .map_err(to_io_error)
+    }
+}
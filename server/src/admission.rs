@@ -0,0 +1,203 @@
+//! Connection admission control.
+//!
+//! Every accepted connection takes a [`Permit`] before anything is read
+//! from it, and is refused while the server, the IP address of the client
+//! or the connections still in the handshake are at their limit. The TLS,
+//! WebSocket or QUIC setup and the handshake must complete before the
+//! deadline of the permit, and frames read until then are bounded by
+//! `max_handshake_frame`, so unauthenticated clients can't make the server
+//! buffer more than `max_pending` of them. Once authenticated, frames are
+//! bounded by `max_frame`, and so are WebSocket messages from the start.
+//! Refused connections are counted and reported by the sweeper.
+
+use crate::state::ServerState;
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+use tokio::time::{Instant, timeout_at};
+
+/// Why a connection was turned away
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rejection {
+    /// `max` connections are open
+    ServerFull,
+    /// `max_per_ip` connections are open from the same IP address
+    IpLimit,
+    /// `max_pending` connections are in the handshake
+    PendingLimit,
+    /// The setup or the handshake didn't complete in time
+    HandshakeTimeout,
+    /// The setup or the handshake failed, e.g. a bad signature or a frame too large
+    HandshakeFailed,
+}
+
+/// Connections refused since the server started, by reason
+#[derive(Default)]
+pub struct RejectedConnections {
+    server_full: AtomicU64,
+    ip_limit: AtomicU64,
+    pending_limit: AtomicU64,
+    handshake_timeout: AtomicU64,
+    handshake_failed: AtomicU64,
+}
+
+impl RejectedConnections {
+    pub fn record(&self, reason: Rejection) {
+        let counter = match reason {
+            Rejection::ServerFull => &self.server_full,
+            Rejection::IpLimit => &self.ip_limit,
+            Rejection::PendingLimit => &self.pending_limit,
+            Rejection::HandshakeTimeout => &self.handshake_timeout,
+            Rejection::HandshakeFailed => &self.handshake_failed,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Connections refused for any reason
+    pub fn total(&self) -> u64 {
+        [
+            &self.server_full,
+            &self.ip_limit,
+            &self.pending_limit,
+            &self.handshake_timeout,
+            &self.handshake_failed,
+        ]
+        .iter()
+        .map(|counter| counter.load(Ordering::Relaxed))
+        .sum()
+    }
+}
+
+#[derive(Default)]
+struct Counts {
+    open: usize,
+    pending: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Connections open and refused
+#[derive(Default)]
+pub struct Admission {
+    counts: Mutex<Counts>,
+    pub rejected: RejectedConnections,
+}
+
+impl Admission {
+    /// Take a permit for a new connection from `ip`, unknown for Unix domain sockets
+    /// The rejection is recorded if the connection has to be refused
+    pub fn admit(state: &Arc<ServerState>, ip: Option<IpAddr>) -> Result<Permit, Rejection> {
+        let config = &state.config.connections;
+        let ip = ip.map(|ip| ip.to_canonical());
+        let admission = &state.admission;
+
+        let rejection = {
+            let mut counts = admission.counts.lock().unwrap();
+            let from_ip = ip.and_then(|ip| counts.per_ip.get(&ip).copied());
+            if counts.open >= config.max {
+                Some(Rejection::ServerFull)
+            } else if from_ip.is_some_and(|open| open >= config.max_per_ip) {
+                Some(Rejection::IpLimit)
+            } else if counts.pending >= config.max_pending {
+                Some(Rejection::PendingLimit)
+            } else {
+                counts.open += 1;
+                counts.pending += 1;
+                if let Some(ip) = ip {
+                    *counts.per_ip.entry(ip).or_default() += 1;
+                }
+                None
+            }
+        };
+        if let Some(rejection) = rejection {
+            admission.rejected.record(rejection);
+            return Err(rejection);
+        }
+
+        Ok(Permit {
+            state: state.clone(),
+            ip,
+            deadline: Instant::now() + Duration::from_secs(config.handshake_timeout),
+            pending: true,
+        })
+    }
+
+    /// One line summary of the connections open and refused, for the logs
+    pub fn report(&self) -> String {
+        let (open, pending) = {
+            let counts = self.counts.lock().unwrap();
+            (counts.open, counts.pending)
+        };
+        let rejected = &self.rejected;
+        format!(
+            "{} open, {} in handshake; refused {}: {} server full, {} IP limit, {} handshake limit, {} timed out, {} failed",
+            open,
+            pending,
+            rejected.total(),
+            rejected.server_full.load(Ordering::Relaxed),
+            rejected.ip_limit.load(Ordering::Relaxed),
+            rejected.pending_limit.load(Ordering::Relaxed),
+            rejected.handshake_timeout.load(Ordering::Relaxed),
+            rejected.handshake_failed.load(Ordering::Relaxed),
+        )
+    }
+}
+
+/// A connection counted against the limits until it is dropped
+pub struct Permit {
+    state: Arc<ServerState>,
+    /// IP address of the client, unknown for Unix domain sockets
+    pub ip: Option<IpAddr>,
+    /// When the setup and the handshake must be done by
+    pub deadline: Instant,
+    pending: bool,
+}
+
+impl Permit {
+    /// Run a step of the setup or of the handshake, `None` if the deadline passed
+    pub async fn before_deadline<F: Future>(&self, step: F) -> Option<F::Output> {
+        match timeout_at(self.deadline, step).await {
+            Ok(output) => Some(output),
+            Err(_) => {
+                self.reject(Rejection::HandshakeTimeout);
+                None
+            }
+        }
+    }
+
+    /// Record why the connection is being dropped
+    pub fn reject(&self, reason: Rejection) {
+        self.state.admission.rejected.record(reason);
+    }
+
+    /// The client is authenticated, it no longer counts against `max_pending`
+    pub fn authenticated(&mut self) {
+        if self.pending {
+            self.pending = false;
+            self.state.admission.counts.lock().unwrap().pending -= 1;
+        }
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut counts = self.state.admission.counts.lock().unwrap();
+        counts.open -= 1;
+        if self.pending {
+            counts.pending -= 1;
+        }
+        if let Some(ip) = self.ip
+            && let Some(open) = counts.per_ip.get_mut(&ip)
+        {
+            *open -= 1;
+            if *open == 0 {
+                counts.per_ip.remove(&ip);
+            }
+        }
+    }
+}
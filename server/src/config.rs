@@ -1,19 +1,362 @@
+use common::{types::CompressionAlgo, utils::compression::DEFAULT_COMPRESSION_THRESHOLD};
 use config::{Config, File};
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use std::{env, error::Error, path::PathBuf};
 
+/// An address the server accepts connections on
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenAddr {
+    /// Unix domain socket at the given path
+    Unix(PathBuf),
+    /// Plain TCP
+    Tcp(String),
+    /// TCP wrapped in TLS
+    Tls(String),
+    /// Plain WebSocket
+    Ws(String),
+    /// WebSocket over TLS
+    Wss(String),
+    /// QUIC, always encrypted with TLS
+    Quic(String),
+}
+
+impl ListenAddr {
+    /// Parse a `<scheme>:<address>` entry of the `listen` list,
+    /// e.g. `unix:/run/null-talk.sock`, `tcp:127.0.0.1:8080` or `tls:[::]:8443`
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (scheme, addr) = match value.split_once(':') {
+            Some((scheme, addr)) if !addr.is_empty() => (scheme, addr.to_string()),
+            _ => return Err(format!("Invalid listen address: {}", value)),
+        };
+
+        match scheme {
+            "unix" => Ok(ListenAddr::Unix(PathBuf::from(addr))),
+            "tcp" => Ok(ListenAddr::Tcp(addr)),
+            "tls" => Ok(ListenAddr::Tls(addr)),
+            "ws" => Ok(ListenAddr::Ws(addr)),
+            "wss" => Ok(ListenAddr::Wss(addr)),
+            "quic" => Ok(ListenAddr::Quic(addr)),
+            _ => Err(format!(
+                "Invalid listen scheme: {}, supported schemes are: unix, tcp, tls, ws, wss, quic",
+                scheme
+            )),
+        }
+    }
+
+    /// Whether the listener needs the `[tls]` section
+    pub fn requires_tls(&self) -> bool {
+        matches!(
+            self,
+            ListenAddr::Tls(_) | ListenAddr::Wss(_) | ListenAddr::Quic(_)
+        )
+    }
+}
+
 #[derive(Debug, Deserialize, Clone)]
 pub struct TLSConfig {
     pub cert_path: String,
     pub key_path: String,
 }
 
+/// Frame compression offered to clients
+#[derive(Debug, Deserialize, Clone)]
+pub struct CompressionConfig {
+    /// Accepted algorithms, in order of preference
+    pub algorithms: Vec<CompressionAlgo>,
+    /// Frames smaller than this many bytes are sent raw
+    #[serde(default = "default_compression_threshold")]
+    pub threshold: usize,
+}
+
+fn default_compression_threshold() -> usize {
+    DEFAULT_COMPRESSION_THRESHOLD
+}
+
+/// Where groups and DM sessions are kept
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    /// Kept in memory, lost on restart
+    Memory,
+    /// Persisted in a SQLite database
+    #[default]
+    Sqlite,
+}
+
+/// Persistent storage of groups and DM sessions
+#[derive(Debug, Deserialize, Clone)]
+pub struct StorageConfig {
+    /// Storage backend, `sqlite` by default
+    #[serde(default)]
+    pub backend: StorageBackend,
+    /// Path of the SQLite database, created if missing
+    #[serde(default = "default_storage_path")]
+    pub path: String,
+    /// File holding the key that encrypts session keys at rest, created if missing
+    /// Defaults to the database path with a `.key` extension
+    pub key_path: Option<String>,
+}
+
+fn default_storage_path() -> String {
+    "null-talk.db".to_string()
+}
+
+impl Default for StorageConfig {
+    fn default() -> Self {
+        StorageConfig {
+            backend: StorageBackend::default(),
+            path: default_storage_path(),
+            key_path: None,
+        }
+    }
+}
+
+impl StorageConfig {
+    /// Get the path of the storage key file
+    pub fn get_key_path(&self) -> PathBuf {
+        match &self.key_path {
+            Some(path) => PathBuf::from(path),
+            None => PathBuf::from(&self.path).with_extension("key"),
+        }
+    }
+}
+
+/// Messages kept for users that are offline
+#[derive(Debug, Deserialize, Clone)]
+pub struct OfflineQueueConfig {
+    /// How long (in seconds) a queued message is kept
+    #[serde(default = "default_offline_ttl")]
+    pub ttl: u64,
+    /// Bytes of messages queued per user, newer messages are dropped once reached
+    #[serde(default = "default_offline_max_bytes")]
+    pub max_bytes: usize,
+}
+
+fn default_offline_ttl() -> u64 {
+    7 * 24 * 3600
+}
+
+fn default_offline_max_bytes() -> usize {
+    1024 * 1024
+}
+
+impl Default for OfflineQueueConfig {
+    fn default() -> Self {
+        OfflineQueueConfig {
+            ttl: default_offline_ttl(),
+            max_bytes: default_offline_max_bytes(),
+        }
+    }
+}
+
+/// Encrypted message history kept for every DM session and group
+#[derive(Debug, Deserialize, Clone)]
+pub struct HistoryConfig {
+    /// How long (in seconds) a message is kept
+    #[serde(default = "default_history_retention")]
+    pub retention: u64,
+}
+
+fn default_history_retention() -> u64 {
+    30 * 24 * 3600
+}
+
+/// Token-bucket limits on what a client sends
+#[derive(Debug, Clone, Copy)]
+pub struct RateLimits {
+    /// Chat messages, typing signals and receipts per second
+    pub messages: f64,
+    /// Commands per second
+    pub commands: f64,
+    /// Payload bytes per second
+    pub bytes: f64,
+    /// Seconds worth of traffic that can be sent at once
+    pub burst: f64,
+}
+
+impl RateLimits {
+    /// Default limits of a single connection
+    fn connection() -> Self {
+        RateLimits {
+            messages: 10.0,
+            commands: 10.0,
+            bytes: 512.0 * 1024.0,
+            burst: 3.0,
+        }
+    }
+
+    /// Default limits shared by the connections of an IP address
+    fn ip() -> Self {
+        RateLimits {
+            messages: 50.0,
+            commands: 50.0,
+            bytes: 4.0 * 1024.0 * 1024.0,
+            burst: 3.0,
+        }
+    }
+}
+
+/// A `[rate_limit.*]` table, the fields left out keep the defaults of the table
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct PartialRateLimits {
+    messages: Option<f64>,
+    commands: Option<f64>,
+    bytes: Option<f64>,
+    burst: Option<f64>,
+}
+
+impl PartialRateLimits {
+    fn or(self, defaults: RateLimits) -> RateLimits {
+        RateLimits {
+            messages: self.messages.unwrap_or(defaults.messages),
+            commands: self.commands.unwrap_or(defaults.commands),
+            bytes: self.bytes.unwrap_or(defaults.bytes),
+            burst: self.burst.unwrap_or(defaults.burst),
+        }
+    }
+}
+
+fn connection_limits<'de, D: Deserializer<'de>>(deserializer: D) -> Result<RateLimits, D::Error> {
+    Ok(PartialRateLimits::deserialize(deserializer)?.or(RateLimits::connection()))
+}
+
+fn ip_limits<'de, D: Deserializer<'de>>(deserializer: D) -> Result<RateLimits, D::Error> {
+    Ok(PartialRateLimits::deserialize(deserializer)?.or(RateLimits::ip()))
+}
+
+/// Flood protection, every connection and every IP address get their own limits
+/// A client going over them is warned first, then throttled, then disconnected
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct RateLimitConfig {
+    /// Limits of a single connection
+    #[serde(deserialize_with = "connection_limits")]
+    pub connection: RateLimits,
+    /// Limits shared by all connections from the same IP address
+    #[serde(deserialize_with = "ip_limits")]
+    pub ip: RateLimits,
+    /// Violations answered with a warning only
+    pub warnings: u32,
+    /// Violations after which the connection is closed, the ones past `warnings` are throttled
+    pub disconnect_after: u32,
+    /// Seconds without a violation after which the previous ones are forgiven
+    pub cooldown: u64,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            connection: RateLimits::connection(),
+            ip: RateLimits::ip(),
+            warnings: 3,
+            disconnect_after: 20,
+            cooldown: 30,
+        }
+    }
+}
+
+/// Limits on the connections the server accepts, so idle or slow clients can't exhaust it
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct ConnectionConfig {
+    /// Connections open at once, including those still in the handshake
+    pub max: usize,
+    /// Connections open at once from the same IP address
+    pub max_per_ip: usize,
+    /// Connections in the handshake at once, before the client is authenticated
+    pub max_pending: usize,
+    /// Seconds a connection has to complete the TLS, WebSocket or QUIC setup and the handshake
+    pub handshake_timeout: u64,
+    /// Longest frame (in bytes) read before the client is authenticated
+    pub max_handshake_frame: usize,
+    /// Longest frame (in bytes) read once the client is authenticated, also bounds WebSocket messages
+    pub max_frame: usize,
+}
+
+impl Default for ConnectionConfig {
+    fn default() -> Self {
+        ConnectionConfig {
+            max: 1024,
+            max_per_ip: 32,
+            max_pending: 128,
+            handshake_timeout: 10,
+            max_handshake_frame: 16 * 1024,
+            max_frame: 1024 * 1024,
+        }
+    }
+}
+
+/// What happens to a connection whose outbound queue is full
+#[derive(Debug, Deserialize, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum SlowClientPolicy {
+    /// Packets are dropped until it catches up, messages are queued as if it was offline
+    Drop,
+    /// The connection is closed, messages are queued until it connects again
+    #[default]
+    Disconnect,
+}
+
+/// Packets waiting to be written to a connection
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct OutboundConfig {
+    /// Packets queued per connection before `slow_client` applies
+    pub queue_len: usize,
+    /// What happens to a connection that doesn't keep up
+    pub slow_client: SlowClientPolicy,
+}
+
+impl Default for OutboundConfig {
+    fn default() -> Self {
+        OutboundConfig {
+            queue_len: 512,
+            slow_client: SlowClientPolicy::default(),
+        }
+    }
+}
+
 /// Configuration for the server
 #[derive(Debug, Deserialize, Clone)]
 pub struct ServerConfig {
     // pub domain: String,
-    pub port: u16,
-    pub tls: Option<TLSConfig>
+    /// Addresses to listen on, e.g. `["unix:/run/null-talk.sock", "tls:[::]:8443"]`
+    /// If empty, the listeners are derived from `port`, `ws_port` and `quic_port`
+    #[serde(default)]
+    pub listen: Vec<String>,
+    pub port: Option<u16>,
+    pub tls: Option<TLSConfig>,
+    /// Port of the WebSocket listener (wss if TLS is configured), disabled if not set
+    pub ws_port: Option<u16>,
+    /// UDP port of the QUIC listener, requires TLS, disabled if not set
+    pub quic_port: Option<u16>,
+    /// How long (in seconds) a resumption ticket stays valid after it was issued
+    #[serde(default = "default_ticket_lifetime")]
+    pub ticket_lifetime: u64,
+    /// Frame compression, disabled if not set
+    pub compression: Option<CompressionConfig>,
+    /// Persistent storage, a `null-talk.db` in the working directory if not set
+    #[serde(default)]
+    pub storage: StorageConfig,
+    /// Messages queued for offline users, kept for 7 days and up to 1 MiB per user if not set
+    #[serde(default)]
+    pub offline_queue: OfflineQueueConfig,
+    /// Message history, disabled if not set
+    pub history: Option<HistoryConfig>,
+    /// Rate limits of connections and IP addresses, see [`RateLimitConfig`] for the defaults
+    #[serde(default)]
+    pub rate_limit: RateLimitConfig,
+    /// Connections accepted, see [`ConnectionConfig`] for the defaults
+    #[serde(default)]
+    pub connections: ConnectionConfig,
+    /// Outbound queue of every connection, see [`OutboundConfig`] for the defaults
+    #[serde(default)]
+    pub outbound: OutboundConfig,
+}
+
+fn default_ticket_lifetime() -> u64 {
+    3600
 }
 
 impl ServerConfig {
@@ -44,8 +387,45 @@ impl ServerConfig {
         Ok(svr_cfg)
     }
 
-    /// Get the server address as a string
-    pub fn get_addr(&self) -> String {
-        format!("0.0.0.0:{}", self.port)
+    /// Get the addresses to listen on
+    pub fn listeners(&self) -> Result<Vec<ListenAddr>, String> {
+        let listeners = match self.listen.is_empty() {
+            false => self
+                .listen
+                .iter()
+                .map(|value| ListenAddr::parse(value))
+                .collect::<Result<Vec<_>, _>>()?,
+            true => self.legacy_listeners()?,
+        };
+
+        if self.tls.is_none() && listeners.iter().any(ListenAddr::requires_tls) {
+            return Err("tls, wss and quic listeners require a [tls] section".to_string());
+        }
+
+        Ok(listeners)
+    }
+
+    /// Listeners on all interfaces, from `port`, `ws_port` and `quic_port`
+    fn legacy_listeners(&self) -> Result<Vec<ListenAddr>, String> {
+        let port = self
+            .port
+            .ok_or("Either `listen` or `port` must be configured")?;
+        let tls = self.tls.is_some();
+
+        let mut listeners = vec![match tls {
+            true => ListenAddr::Tls(format!("0.0.0.0:{}", port)),
+            false => ListenAddr::Tcp(format!("0.0.0.0:{}", port)),
+        }];
+        if let Some(port) = self.ws_port {
+            listeners.push(match tls {
+                true => ListenAddr::Wss(format!("0.0.0.0:{}", port)),
+                false => ListenAddr::Ws(format!("0.0.0.0:{}", port)),
+            });
+        }
+        if let Some(port) = self.quic_port {
+            listeners.push(ListenAddr::Quic(format!("0.0.0.0:{}", port)));
+        }
+
+        Ok(listeners)
     }
 }
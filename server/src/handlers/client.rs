@@ -1,122 +1,236 @@
 use crate::{
-    data::{CLIENTS, CONVERSATIONS, GROUPS},
-    handlers::task::start_reader_task,
+    admission::{Permit, Rejection},
+    handlers::{
+        broadcast_presence, claim_username, flush_offline_queue, requeue_outbox,
+        take_offline_queue,
+        task::{start_reader_task, start_writer_task},
+    },
     net::perform_handshake,
-    types::Client,
+    state::ServerState,
+    ticket::unix_now,
+    types::{Client, Outbox, SuspendedSession},
 };
 use common::{
-    net::{AsyncStream, Packet, StreamReader, StreamWriter},
-    utils::enc::public_key_to_user_id,
+    net::{AsyncStream, FrameReader, FrameWriter, StreamReader, StreamWriter},
+    utils::enc::{public_key_to_user_id, to_ssh_public_key},
+};
+use std::{sync::Arc, time::Duration};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::{Mutex as AsyncMutex, Notify, mpsc},
 };
-use std::sync::Arc;
-use tokio::sync::{Mutex as AsyncMutex, mpsc::UnboundedSender};
 
 /// Handle a new client connection
+/// The handshake must complete before the deadline of the permit
+/// `secure` is set for encrypted or local transports, where resumption tickets are allowed
 pub async fn handle_client(
     stream: Box<dyn AsyncStream>,
-    tx: Arc<AsyncMutex<UnboundedSender<Packet>>>,
+    secure: bool,
+    mut permit: Permit,
+    state: Arc<ServerState>,
 ) {
     let (rd, wt) = tokio::io::split(stream);
-    let rd: StreamReader = Arc::new(AsyncMutex::new(rd));
-    let wt: StreamWriter = Arc::new(AsyncMutex::new(wt));
+    let rd: StreamReader = Arc::new(AsyncMutex::new(FrameReader::new(rd)));
+    let wt: StreamWriter = Arc::new(AsyncMutex::new(FrameWriter::new(wt)));
 
-    let (name, session_key, public_key) = match perform_handshake(rd.clone(), wt.clone()).await {
-        Ok(data) => data,
-        Err(_) => return,
+    let handshake = match permit
+        .before_deadline(perform_handshake(rd.clone(), wt.clone(), &state, secure))
+        .await
+    {
+        Some(Ok(data)) => data,
+        Some(Err(_)) => {
+            permit.reject(Rejection::HandshakeFailed);
+            return;
+        }
+        None => return,
     };
+    permit.authenticated();
 
-    let client_id = public_key_to_user_id(&public_key);
+    let client_id = public_key_to_user_id(&handshake.public_key);
+    let device_id = handshake.device_id.clone();
+    let kick = Arc::new(Notify::new());
+    let (queue, outgoing) = mpsc::channel(state.config.outbound.queue_len.max(1));
+    let outgoing = Arc::new(AsyncMutex::new(outgoing));
+    let outbox = Outbox {
+        queue,
+        policy: state.config.outbound.slow_client,
+        closing: Arc::default(),
+    };
     let client = Client {
-        username: name.clone().to_string(),
+        username: handshake.username.clone(),
         user_id: client_id.clone(),
-        session_key: hex::encode(&session_key),
+        public_key: to_ssh_public_key(&handshake.public_key)
+            .to_openssh()
+            .unwrap_or_default(),
+        device_id: device_id.clone(),
+        connected_at: unix_now(),
+        session_key: hex::encode(&handshake.session_key),
         dms: Vec::new(),
         groups: Vec::new(),
-        writer: wt.clone(),
+        outbox: outbox.clone(),
+        ticket_id: handshake.ticket_id.clone(),
+        kick: kick.clone(),
     };
 
-    // Add new client to the server
-    {
-        let mut clients_lock = CLIENTS.lock().await;
-        clients_lock.insert(client_id.clone(), client);
+    // Messages are queued offline until the device is registered, then pushed to its outbox
+    let queued = {
+        let _delivering = state.delivery.of(&client_id).lock().await;
+        // A device connecting again replaces its previous connection
+        if let Some(previous) = state.store.add_client(client).await {
+            previous.kick.notify_one();
+        }
+        take_offline_queue(&state, &client_id).await
+    };
+    // Newer packets wait in the outbox until the messages queued while it was offline are sent
+    flush_offline_queue(&state, &client_id, queued, wt.clone()).await;
+    let mut write_task = start_writer_task(outgoing.clone(), wt.clone());
+
+    match handshake.resumed {
+        Some(session) => {
+            restore_client_data(&state, &client_id, &device_id, session).await;
+            println!(
+                "🔗 Client resumed session: {} ({})",
+                &client_id[..8],
+                device_id
+            );
+        }
+        None => println!(
+            "🔗 New client connected: {} ({})",
+            &client_id[..8],
+            device_id
+        ),
+    }
+
+    claim_username(&state, &client_id, &device_id).await;
+
+    // The user just came online
+    if state.store.get_devices(&client_id).await.len() == 1 {
+        broadcast_presence(&state, &client_id).await;
     }
-    println!("🔗 New client connected: {}", &client_id[..8]);
 
     // Spawn reader task
-    let read_task = start_reader_task(rd.clone(), wt.clone(), client_id.clone(), tx.clone()).await;
-    let _ = read_task.await;
+    let mut read_task = start_reader_task(
+        rd.clone(),
+        outbox,
+        client_id.clone(),
+        device_id.clone(),
+        permit.ip,
+        state.clone(),
+    )
+    .await;
+    tokio::select! {
+        _ = &mut read_task => {}
+        _ = &mut write_task => {}
+        // The device was revoked, connected again or doesn't keep up
+        _ = kick.notified() => {
+            read_task.abort();
+            write_task.abort();
+            let _ = (&mut write_task).await;
+            let shutdown = async { wt.lock().await.inner.shutdown().await };
+            let _ = tokio::time::timeout(Duration::from_secs(1), shutdown).await;
+        }
+    }
+    read_task.abort();
+    write_task.abort();
+    // What is left in the outbox goes to the other devices of the user, or waits for the next one
+    requeue_outbox(&state, &client_id, &outgoing).await;
 
-    println!("🔗 client disconnected: {}", &client_id[..8]);
+    println!(
+        "🔗 client disconnected: {} ({})",
+        &client_id[..8],
+        device_id
+    );
     drop(rd);
     drop(wt);
-    cleanup_client_data(client_id.clone()).await;
+    cleanup_client_data(&state, &client_id, &device_id, &handshake.ticket_id).await;
 }
 
-/// Removes user from groups and update/remove DM Session, etc.
-async fn cleanup_client_data(client_id: String) {
-    // Get client from CLIENTS
-    let client = {
-        let clients_lock = CLIENTS.lock().await;
-        clients_lock.get(&client_id).cloned()
-    };
-
-    match client {
-        Some(client) => {
-            for dm in client.dms {
-                update_or_remove_dm_session(&dm, client_id.clone()).await;
-            }
-            for gp in client.groups {
-                update_or_remove_group_session(&gp, client_id.clone()).await;
-            }
+/// Puts a resumed client back into the DMs and groups it was part of
+async fn restore_client_data(
+    state: &ServerState,
+    client_id: &str,
+    device_id: &str,
+    session: SuspendedSession,
+) {
+    let mut dms = Vec::new();
+    for dm_id in session.dms {
+        if state
+            .store
+            .set_dm_member_active(&dm_id, client_id, true)
+            .await
+        {
+            dms.push(dm_id);
         }
-        None => {
-            println!("⚠️ Client not found for cleanup: {}", &client_id[..8]);
+    }
+
+    let mut groups = Vec::new();
+    for group_id in session.groups {
+        if state
+            .store
+            .set_group_member_active(&group_id, client_id, true)
+            .await
+        {
+            groups.push(group_id);
         }
     }
-    // Additional cleanup logic can be added here
 
-    // Remove client from CLIENTS
-    {
-        let mut clients_lock = CLIENTS.lock().await;
-        clients_lock.remove(&client_id);
-    };
+    state
+        .store
+        .set_client_chats(client_id, device_id, dms, groups)
+        .await;
 }
 
-async fn update_or_remove_dm_session(session_id: &str, client_id: String) {
-    let mut conv = CONVERSATIONS.lock().await;
-    let need_to_remove = match conv.get_mut(session_id) {
-        Some(dm) => {
-            dm.members.insert(client_id.clone(), false);
-            // Check if we need to remove the DM session
-            dm.members.values().all(|is_active| !is_active)
-        }
-        None => {
-            println!("⚠️ DM session not found for ID: {}", session_id);
-            false
-        }
+/// Marks the user inactive in its groups and DM sessions once its last device is gone,
+/// and tells the members that it went offline.
+/// The memberships are kept for `ticket_lifetime` seconds so the client can resume them.
+async fn cleanup_client_data(
+    state: &ServerState,
+    client_id: &str,
+    device_id: &str,
+    ticket_id: &str,
+) {
+    // Nothing to do if the device was replaced by a newer connection
+    let Some(client) = state
+        .store
+        .remove_client(client_id, device_id, ticket_id)
+        .await
+    else {
+        return;
     };
 
-    if need_to_remove {
-        conv.remove(session_id);
-    }
-}
+    let session = SuspendedSession {
+        user_id: client_id.to_string(),
+        dms: client.dms.clone(),
+        groups: client.groups.clone(),
+        expires_at: unix_now() + state.config.ticket_lifetime,
+    };
+    state
+        .store
+        .suspend_session(&client.ticket_id, session)
+        .await;
 
-async fn update_or_remove_group_session(session_id: &str, client_id: String) {
-    let mut groups = GROUPS.lock().await;
-    let need_to_remove = match groups.get_mut(session_id) {
-        Some(group) => {
-            group.members.insert(client_id.clone(), false);
+    // Other devices of the user are still taking part
+    if !state.store.get_devices(client_id).await.is_empty() {
+        return;
+    }
 
-            // Check if we need to remove the group session
-            group.members.values().all(|is_active| !is_active)
+    for dm in client.dms {
+        if !state
+            .store
+            .set_dm_member_active(&dm, client_id, false)
+            .await
+        {
+            println!("⚠️ DM session not found for ID: {}", dm);
         }
-        None => {
-            println!("⚠️ Group session not found for ID: {}", session_id);
-            false
+    }
+    for gp in client.groups {
+        if !state
+            .store
+            .set_group_member_active(&gp, client_id, false)
+            .await
+        {
+            println!("⚠️ Group session not found for ID: {}", gp);
         }
-    };
-
-    if need_to_remove {
-        groups.remove(session_id);
     }
+    broadcast_presence(state, client_id).await;
 }
@@ -1,24 +1,70 @@
 use std::collections::HashMap;
 
 use crate::{
-    data::{CLIENTS, CONVERSATIONS, GROUPS},
-    types::{DmChat, GroupChat},
+    handlers::{
+        accept_contact, add_group_member, block_user, broadcast_presence, claim_handle,
+        create_invite, delete_group, exchange_presence, join_group, leave_group, list_blocked,
+        list_contact_requests, list_group_members, reject_contact, rekey_group,
+        remove_group_member, rename_group, request_contact, set_group_admin, set_group_role,
+        subscribe_channel, transfer_group, unblock_user, unsubscribe_channel, whois,
+    },
+    state::ServerState,
+    types::{DmChat, GroupChat, UserStatus},
 };
 use common::{
     types::{
-        AddGroupMemberPayload, ChatMode, NewGroupPayload, NewGroupResponse, NewSessionPayload,
-        NewSessionResponse, ServerResponse,
+        ChatMode, DeviceInfo, DeviceListResponse, GroupRole, HistoryMessage, HistoryRequest,
+        HistoryResponse, Message, NewGroupPayload, NewGroupResponse, NewSessionPayload,
+        NewSessionResponse, Presence, RevokeDevicePayload, ServerResponse, SetStatusPayload,
     },
     utils::enc::{generate_session_data, hash_string},
 };
 use uuid::Uuid;
 
+/// Most messages returned by a single `history` command
+const MAX_HISTORY_PAGE: usize = 100;
+
+/// Longest status line a client may set
+const MAX_STATUS_LEN: usize = 128;
+
 /// Process a command from a client
-pub async fn process_command(payload: Vec<u8>, client_id: String, cmd: &str) -> ServerResponse {
+pub async fn process_command(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+    device_id: &str,
+    cmd: &str,
+) -> ServerResponse {
     match cmd {
-        "mkgp" => create_new_group(payload, client_id.clone()).await,
-        "addgpm" => add_group_member(payload, client_id.clone()).await,
-        "new" => create_new_session(payload, client_id).await,
+        "mkgp" => create_new_group(state, payload, client_id.clone(), device_id, false).await,
+        "mkch" => create_new_group(state, payload, client_id.clone(), device_id, true).await,
+        "addgpm" => add_group_member(state, payload, client_id.clone()).await,
+        "rmgpm" => remove_group_member(state, payload, client_id).await,
+        "leave" => leave_group(state, payload, client_id).await,
+        "rngp" => rename_group(state, payload, client_id).await,
+        "rmgp" => delete_group(state, payload, client_id).await,
+        "promote" => set_group_admin(state, payload, client_id, true).await,
+        "demote" => set_group_admin(state, payload, client_id, false).await,
+        "transfer" => transfer_group(state, payload, client_id).await,
+        "role" => set_group_role(state, payload, client_id).await,
+        "invite" => create_invite(state, payload, client_id).await,
+        "join" => join_group(state, payload, client_id).await,
+        "sub" => subscribe_channel(state, payload, client_id).await,
+        "unsub" => unsubscribe_channel(state, payload, client_id).await,
+        "rekey" => rekey_group(state, payload, client_id).await,
+        "members" => list_group_members(state, payload, client_id).await,
+        "new" => create_new_session(state, payload, client_id, device_id).await,
+        "history" => get_history(state, payload, client_id).await,
+        "devices" => list_devices(state, client_id, device_id).await,
+        "revoke" => revoke_device(state, payload, client_id, device_id).await,
+        "status" => set_status(state, payload, client_id).await,
+        "handle" => claim_handle(state, payload, client_id, device_id).await,
+        "whois" => whois(state, payload).await,
+        "requests" => list_contact_requests(state, client_id).await,
+        "reject" => reject_contact(state, payload, client_id).await,
+        "block" => block_user(state, payload, client_id).await,
+        "unblock" => unblock_user(state, payload, client_id).await,
+        "blocked" => list_blocked(state, client_id).await,
         _ => ServerResponse {
             success: false,
             payload: None,
@@ -29,7 +75,13 @@ pub async fn process_command(payload: Vec<u8>, client_id: String, cmd: &str) ->
 
 /// Create a new session
 /// It can be a group or direct message
-async fn create_new_session(payload: Vec<u8>, client_id: String) -> ServerResponse {
+/// The client and the other members learn each other's presence
+async fn create_new_session(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+    device_id: &str,
+) -> ServerResponse {
     let mut response = ServerResponse {
         success: true,
         payload: None,
@@ -47,67 +99,90 @@ async fn create_new_session(payload: Vec<u8>, client_id: String) -> ServerRespon
             }
         };
 
-    let (session_id, session_key) = match new_session.mode {
+    let (session_id, session_key, members, pending) = match new_session.mode {
         ChatMode::Dm(_) => {
-            match CLIENTS.lock().await.get(&new_session.id) {
-                Some(client) => client,
-                None => {
-                    response.success = false;
-                    response.error = Some("Member not online".to_string());
+            if state.store.is_blocked(&new_session.id, &client_id).await {
+                response.success = false;
+                response.error = Some("This user doesn't accept messages from you".to_string());
 
-                    return response;
-                }
-            };
+                return response;
+            }
 
-            let mut session_id: String =
+            let session_id: String =
                 hash_string(&format!("{}{}", client_id.clone(), new_session.id.clone()));
-            let (mut session_key, _) = generate_session_data();
-
-            {
-                let session_id2 =
-                    hash_string(&format!("{}{}", new_session.id.clone(), client_id.clone()));
+            let session_id2 =
+                hash_string(&format!("{}{}", new_session.id.clone(), client_id.clone()));
 
-                let mut conversations = CONVERSATIONS.lock().await;
-                let dm1 = conversations.get(&session_id).cloned();
-                let dm2 = conversations.get(&session_id2).cloned();
+            let existing = match state.store.get_dm(&session_id).await {
+                Some(dm) => Some(dm),
+                None => state.store.get_dm(&session_id2).await,
+            };
 
-                match dm1.or(dm2) {
-                    Some(dm) => {
-                        session_id = dm.dm_id.clone();
-                        session_key = dm.session_key.clone();
-                    }
-                    None => {
-                        let mut members: HashMap<String, bool> = HashMap::new();
-                        members.insert(client_id.clone(), true);
-                        members.insert(new_session.id.clone(), true);
-
-                        for member in members.keys() {
-                            let mut guard = CLIENTS.lock().await;
-                            if let Some(client) = guard.get_mut(member) {
-                                if !client.dms.contains(&session_id) {
-                                    client.dms.push(session_id.clone());
-                                }
+            let dm = match existing {
+                Some(dm) => {
+                    // The member asked to join the DM accepts the contact request by opening it
+                    let dm = match dm.requester.as_ref().is_some_and(|id| *id != client_id) {
+                        true => match accept_contact(state, &dm, &client_id).await {
+                            Ok(dm) => dm,
+                            Err(err) => {
+                                response.success = false;
+                                response.error = Some(err);
+
+                                return response;
                             }
+                        },
+                        false => dm,
+                    };
+                    state
+                        .store
+                        .set_dm_member_active(&dm.dm_id, &client_id, true)
+                        .await;
+                    state.store.add_client_dm(&client_id, &dm.dm_id).await;
+                    dm
+                }
+                None => {
+                    let (session_key, _) = generate_session_data();
+                    let mut members: HashMap<String, bool> = HashMap::new();
+                    members.insert(client_id.clone(), true);
+                    members.insert(new_session.id.clone(), true);
+
+                    let dm_chat = DmChat {
+                        dm_id: session_id.clone(),
+                        session_key,
+                        members,
+                        requester: Some(client_id.clone()),
+                    };
+
+                    let dm = match state.store.insert_dm(dm_chat).await {
+                        Ok(dm) => dm,
+                        Err(err) => {
+                            response.success = false;
+                            response.error = Some(format!("Failed to store DM session: {}", err));
+
+                            return response;
                         }
-
-                        let dm_chat = DmChat {
-                            dm_id: session_id.clone(),
-                            session_key: session_key.clone(),
-                            members,
-                        };
-
-                        conversations
-                            .entry(session_id.clone())
-                            .or_insert(dm_chat.clone());
+                    };
+                    for member in dm.members.keys() {
+                        state.store.add_client_dm(member, &dm.dm_id).await;
                     }
+                    if dm.requester.as_ref() == Some(&client_id) {
+                        request_contact(state, &dm, &client_id).await;
+                    }
+                    dm
                 }
-            }
+            };
 
-            (session_id, session_key)
+            // Presence is only shared once the contact request is accepted
+            let pending = dm.requester.is_some();
+            let members = match pending {
+                true => Vec::new(),
+                false => dm.members.into_keys().collect(),
+            };
+            (dm.dm_id, dm.session_key, members, pending)
         }
-        ChatMode::Group(_) => {
-            let group = match GROUPS.lock().await.get(&new_session.id) {
-                Some(group) => group.to_owned(),
+        ChatMode::Group(_) | ChatMode::Channel(_) => {
+            let group = match state.store.get_group(&new_session.id).await {
+                Some(group) => group,
                 None => {
                     response.success = false;
                     response.error = Some("Group not found".to_string());
@@ -123,31 +198,30 @@ async fn create_new_session(payload: Vec<u8>, client_id: String) -> ServerRespon
                 return response;
             }
 
-            let session_id = group.group_id.clone();
-            let session_key = group.session_key.clone();
-
-            {
-                let mut guard = CLIENTS.lock().await;
-                if let Some(client) = guard.get_mut(&client_id) {
-                    if !client.groups.contains(&session_id) {
-                        client.groups.push(session_id.clone());
-                    }
-                }
-            }
-            {
-                let mut groups = GROUPS.lock().await;
-                if let Some(group) = groups.get_mut(&session_id) {
-                    group.members.insert(client_id.clone(), true);
-                }
-            }
-
-            (session_id, session_key)
+            state
+                .store
+                .add_client_group(&client_id, &group.group_id)
+                .await;
+            state
+                .store
+                .set_group_member_active(&group.group_id, &client_id, true)
+                .await;
+
+            // Subscribers of a channel don't learn about each other
+            let members = match group.channel {
+                true => Vec::new(),
+                false => group.members.into_keys().collect(),
+            };
+            (group.group_id, group.session_key, members, false)
         }
     };
 
+    exchange_presence(state, &client_id, device_id, &session_id, members).await;
+
     let response_payload = NewSessionResponse {
         id: session_id,
         session_key,
+        pending,
     };
 
     response.payload =
@@ -156,9 +230,15 @@ async fn create_new_session(payload: Vec<u8>, client_id: String) -> ServerRespon
     return response;
 }
 
-/// Create a new group
+/// Create a new group, or a channel whose listed members are its publishers
 /// If the group already exists, it won't create another group
-async fn create_new_group(payload: Vec<u8>, client_id: String) -> ServerResponse {
+async fn create_new_group(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+    device_id: &str,
+    channel: bool,
+) -> ServerResponse {
     let (mut session_key, _) = generate_session_data();
 
     let mut response = ServerResponse {
@@ -180,7 +260,14 @@ async fn create_new_group(payload: Vec<u8>, client_id: String) -> ServerResponse
             }
         };
 
-    for member in &group_info.members {
+    // Users that blocked the client aren't added
+    let mut invited = Vec::new();
+    for member in group_info.members {
+        if !state.store.is_blocked(&member, &client_id).await {
+            invited.push(member);
+        }
+    }
+    for member in &invited {
         members.insert(member.clone(), false);
     }
     members.insert(client_id.clone(), true);
@@ -188,42 +275,49 @@ async fn create_new_group(payload: Vec<u8>, client_id: String) -> ServerResponse
     let group_id = group_info
         .group_id
         .unwrap_or_else(|| hash_string(&Uuid::new_v4().to_string()));
-    let mut new_group = GroupChat {
+    let roles = match channel {
+        true => invited
+            .iter()
+            .map(|member| (member.clone(), GroupRole::Member))
+            .collect(),
+        false => HashMap::new(),
+    };
+    let new_group = GroupChat {
         group_name: group_info.name,
         group_id: group_id.clone(),
         session_key: session_key.clone(),
         admin: client_id.clone(),
+        roles,
         members: members.clone(),
+        channel,
     };
 
-    {
-        let mut groups = GROUPS.lock().await;
+    let group = match state.store.insert_group(new_group).await {
+        Ok(group) => group,
+        Err(err) => {
+            response.success = false;
+            response.error = Some(format!("Failed to store group: {}", err));
 
-        match groups.get(&group_id) {
-            Some(group) => {
-                if group.admin != client_id {
-                    response.success = false;
-                    response.error = Some(format!("Group with ID {} already exists", group_id));
+            return response;
+        }
+    };
+    if group.session_key != session_key {
+        // The group already existed, only its admin gets the key again
+        if group.admin != client_id {
+            response.success = false;
+            response.error = Some(format!("Group with ID {} already exists", group_id));
 
-                    return response;
-                } else {
-                    new_group.session_key = group.session_key.clone();
-                    session_key = group.session_key.clone();
-                }
-            }
-            None => {
-                groups.insert(group_id.clone(), new_group.clone());
-            }
+            return response;
         }
+        session_key = group.session_key.clone();
     }
 
     for member in members.keys() {
-        let mut guard = CLIENTS.lock().await;
-        if let Some(client) = guard.get_mut(member) {
-            if !client.groups.contains(&group_id) {
-                client.groups.push(group_id.clone());
-            }
-        }
+        state.store.add_client_group(member, &group_id).await;
+    }
+    if !group.channel {
+        let members = group.members.into_keys().collect();
+        exchange_presence(state, &client_id, device_id, &group_id, members).await;
     }
 
     let res_payload = NewGroupResponse {
@@ -237,15 +331,21 @@ async fn create_new_group(payload: Vec<u8>, client_id: String) -> ServerResponse
     return response;
 }
 
-/// Add a member to an existing group
-async fn add_group_member(payload: Vec<u8>, client_id: String) -> ServerResponse {
+/// Get a page of the history of a DM session or group the client is part of
+async fn get_history(state: &ServerState, payload: Vec<u8>, client_id: String) -> ServerResponse {
     let mut response = ServerResponse {
         success: true,
         payload: None,
         error: None,
     };
 
-    let (data, _): (AddGroupMemberPayload, usize) =
+    if state.config.history.is_none() {
+        response.success = false;
+        response.error = Some("History is disabled on this server".to_string());
+        return response;
+    }
+
+    let (request, _): (HistoryRequest, usize) =
         match bincode::decode_from_slice(&payload, bincode::config::standard()) {
             Ok(data) => data,
             Err(err) => {
@@ -256,38 +356,169 @@ async fn add_group_member(payload: Vec<u8>, client_id: String) -> ServerResponse
             }
         };
 
-    let mut group = match GROUPS.lock().await.get(&data.group_id) {
-        Some(group) => group.to_owned(),
-        None => {
+    let is_member = match state.store.get_dm(&request.session_id).await {
+        Some(dm) => dm.members.contains_key(&client_id),
+        None => match state.store.get_group(&request.session_id).await {
+            Some(group) => group.members.contains_key(&client_id),
+            None => false,
+        },
+    };
+    if !is_member {
+        response.success = false;
+        response.error = Some("Session not found".to_string());
+        return response;
+    }
+
+    // One more than requested tells whether older messages are left
+    let limit = (request.limit as usize).clamp(1, MAX_HISTORY_PAGE);
+    let mut entries = match state
+        .store
+        .load_history(&request.session_id, request.before, limit + 1)
+        .await
+    {
+        Ok(entries) => entries,
+        Err(err) => {
             response.success = false;
-            response.error = Some("Group not found".to_string());
+            response.error = Some(format!("Failed to load history: {}", err));
             return response;
         }
     };
+    let more = entries.len() > limit;
+    if more {
+        entries.remove(0);
+    }
 
-    if group.admin != client_id {
+    let messages = entries
+        .into_iter()
+        .filter_map(|entry| {
+            let (message, _): (Message, usize) =
+                bincode::decode_from_slice(&entry.payload, bincode::config::standard()).ok()?;
+            Some(HistoryMessage {
+                id: entry.id,
+                message,
+            })
+        })
+        .collect();
+
+    let res_payload = HistoryResponse { messages, more };
+    response.payload =
+        Some(bincode::encode_to_vec(&res_payload, bincode::config::standard()).unwrap());
+
+    response
+}
+
+/// List the connected devices of the client
+async fn list_devices(state: &ServerState, client_id: String, device_id: &str) -> ServerResponse {
+    let mut devices: Vec<DeviceInfo> = state
+        .store
+        .get_devices(&client_id)
+        .await
+        .into_iter()
+        .map(|device| DeviceInfo {
+            current: device.device_id == device_id,
+            device_id: device.device_id,
+            connected_at: device.connected_at,
+        })
+        .collect();
+    devices.sort_by_key(|device| device.connected_at);
+
+    let res_payload = DeviceListResponse { devices };
+    ServerResponse {
+        success: true,
+        payload: Some(bincode::encode_to_vec(&res_payload, bincode::config::standard()).unwrap()),
+        error: None,
+    }
+}
+
+/// Revoke another device of the client and disconnect it
+async fn revoke_device(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+    device_id: &str,
+) -> ServerResponse {
+    let mut response = ServerResponse {
+        success: true,
+        payload: None,
+        error: None,
+    };
+
+    let (data, _): (RevokeDevicePayload, usize) =
+        match bincode::decode_from_slice(&payload, bincode::config::standard()) {
+            Ok(data) => data,
+            Err(err) => {
+                response.success = false;
+                response.error = Some(format!("Failed to decode payload: {}", err));
+
+                return response;
+            }
+        };
+
+    if data.device_id == device_id {
         response.success = false;
-        response.error = Some("Only group admin can add members".to_string());
+        response.error = Some("The current device can't be revoked".to_string());
         return response;
     }
 
-    match CLIENTS.lock().await.get(&data.member_id).cloned() {
-        Some(mut member) => {
-            if !group.members.contains_key(&member.user_id) {
-                group.members.insert(member.user_id.clone(), true);
-            }
-            member.groups.push(group.group_id.clone());
-        }
-        None => {
-            if !group.members.contains_key(&data.member_id) {
-                group.members.insert(data.member_id.clone(), false);
-            }
-        }
+    if let Err(err) = state.store.revoke_device(&client_id, &data.device_id).await {
+        response.success = false;
+        response.error = Some(format!("Failed to revoke device: {}", err));
+        return response;
+    }
+    if let Some(device) = state.store.get_client(&client_id, &data.device_id).await {
+        device.kick.notify_one();
+    }
+    println!(
+        "🔒 Device revoked: {} ({})",
+        &client_id[..8],
+        data.device_id
+    );
+
+    response
+}
+
+/// Set the presence and status line of the client and tell its contacts
+async fn set_status(state: &ServerState, payload: Vec<u8>, client_id: String) -> ServerResponse {
+    let mut response = ServerResponse {
+        success: true,
+        payload: None,
+        error: None,
     };
 
-    response.payload = Some(
-        bincode::encode_to_vec("Member Added successfully", bincode::config::standard()).unwrap(),
-    );
+    let (data, _): (SetStatusPayload, usize) =
+        match bincode::decode_from_slice(&payload, bincode::config::standard()) {
+            Ok(data) => data,
+            Err(err) => {
+                response.success = false;
+                response.error = Some(format!("Failed to decode payload: {}", err));
+                return response;
+            }
+        };
 
-    return response;
+    if data.presence == Presence::Offline {
+        response.success = false;
+        response.error = Some("Presence can't be set to offline".to_string());
+        return response;
+    }
+    if data
+        .status
+        .as_ref()
+        .is_some_and(|status| status.chars().count() > MAX_STATUS_LEN)
+    {
+        response.success = false;
+        response.error = Some(format!(
+            "Status is longer than {} characters",
+            MAX_STATUS_LEN
+        ));
+        return response;
+    }
+
+    let status = UserStatus {
+        presence: data.presence,
+        status: data.status.filter(|status| !status.is_empty()),
+    };
+    state.store.set_status(&client_id, status).await;
+    broadcast_presence(state, &client_id).await;
+
+    response
 }
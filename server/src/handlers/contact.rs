@@ -0,0 +1,196 @@
+use crate::{
+    handlers::{
+        deliver_event, display_name,
+        group::{decode_payload, failed, succeeded},
+    },
+    state::ServerState,
+    types::DmChat,
+};
+use common::{
+    types::{
+        ContactEvent, ContactEventKind, ContactPayload, ServerEvent, ServerResponse, UserInfo,
+        UserListResponse,
+    },
+    utils::enc::hash_string,
+};
+
+/// Tell a user that `requester_id` wants to start a DM with it
+/// Queued if the user is offline, like a message
+pub async fn request_contact(state: &ServerState, dm: &DmChat, requester_id: &str) {
+    let Some(target_id) = dm.members.keys().find(|member| *member != requester_id) else {
+        return;
+    };
+    notify_contact(
+        state,
+        dm,
+        requester_id,
+        target_id,
+        ContactEventKind::Requested,
+    )
+    .await;
+    println!(
+        "✉️ {} asked {} to start a DM",
+        &requester_id[..8],
+        &target_id[..8]
+    );
+}
+
+/// Accept the contact request of a DM session the client was asked to join
+/// Returns the accepted session
+pub async fn accept_contact(
+    state: &ServerState,
+    dm: &DmChat,
+    client_id: &str,
+) -> Result<DmChat, String> {
+    let Some(requester_id) = dm.requester.clone() else {
+        return Ok(dm.clone());
+    };
+    let dm = match state.store.accept_dm(&dm.dm_id).await {
+        Ok(Some(dm)) => dm,
+        Ok(None) => return Err("DM session not found".to_string()),
+        Err(err) => return Err(format!("Failed to accept contact request: {}", err)),
+    };
+
+    notify_contact(
+        state,
+        &dm,
+        client_id,
+        &requester_id,
+        ContactEventKind::Accepted,
+    )
+    .await;
+    Ok(dm)
+}
+
+/// Reject the contact request of a user, the DM session is deleted
+pub async fn reject_contact(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+) -> ServerResponse {
+    let data: ContactPayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let Some(dm) = find_dm(state, &client_id, &data.user_id).await else {
+        return failed("No contact request from this user");
+    };
+    if dm.requester.as_deref() != Some(data.user_id.as_str()) {
+        return failed("No contact request from this user");
+    }
+
+    if let Err(err) = state.store.delete_dm(&dm.dm_id).await {
+        return failed(format!("Failed to reject contact request: {}", err));
+    }
+    notify_contact(
+        state,
+        &dm,
+        &client_id,
+        &data.user_id,
+        ContactEventKind::Rejected,
+    )
+    .await;
+    succeeded()
+}
+
+/// List the users waiting for the client to accept their contact request
+pub async fn list_contact_requests(state: &ServerState, client_id: String) -> ServerResponse {
+    let (dms, _) = state.store.get_user_chats(&client_id).await;
+    let requesters = dms
+        .into_iter()
+        .filter_map(|dm| dm.requester)
+        .filter(|requester| *requester != client_id)
+        .collect();
+
+    user_list(state, requesters).await
+}
+
+/// Block a user, it can't message the client nor add it to groups anymore
+pub async fn block_user(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+) -> ServerResponse {
+    let data: ContactPayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    if data.user_id == client_id {
+        return failed("You can't block yourself");
+    }
+
+    match state.store.block_user(&client_id, &data.user_id).await {
+        Ok(()) => succeeded(),
+        Err(err) => failed(format!("Failed to block user: {}", err)),
+    }
+}
+
+/// Unblock a user
+pub async fn unblock_user(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+) -> ServerResponse {
+    let data: ContactPayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    match state.store.unblock_user(&client_id, &data.user_id).await {
+        Ok(true) => succeeded(),
+        Ok(false) => failed("This user isn't blocked"),
+        Err(err) => failed(format!("Failed to unblock user: {}", err)),
+    }
+}
+
+/// List the users blocked by the client
+pub async fn list_blocked(state: &ServerState, client_id: String) -> ServerResponse {
+    let blocked = state.store.get_blocked(&client_id).await;
+    user_list(state, blocked).await
+}
+
+/// The DM session between two users, whichever opened it
+async fn find_dm(state: &ServerState, user_id: &str, other_id: &str) -> Option<DmChat> {
+    let dm_id = hash_string(&format!("{}{}", user_id, other_id));
+    match state.store.get_dm(&dm_id).await {
+        Some(dm) => Some(dm),
+        None => {
+            let dm_id = hash_string(&format!("{}{}", other_id, user_id));
+            state.store.get_dm(&dm_id).await
+        }
+    }
+}
+
+/// Tell `target_id` what `user_id` did with their contact request
+async fn notify_contact(
+    state: &ServerState,
+    dm: &DmChat,
+    user_id: &str,
+    target_id: &str,
+    kind: ContactEventKind,
+) {
+    let event = ContactEvent {
+        dm_id: dm.dm_id.clone(),
+        user_id: user_id.to_string(),
+        username: display_name(state, user_id).await,
+        kind,
+    };
+    deliver_event(state, target_id, ServerEvent::Contact(event)).await;
+}
+
+async fn user_list(state: &ServerState, user_ids: Vec<String>) -> ServerResponse {
+    let mut users = Vec::new();
+    for user_id in user_ids {
+        users.push(UserInfo {
+            username: state.store.handle_of(&user_id).await,
+            user_id,
+        });
+    }
+
+    let res_payload = UserListResponse { users };
+    ServerResponse {
+        success: true,
+        payload: Some(bincode::encode_to_vec(&res_payload, bincode::config::standard()).unwrap()),
+        error: None,
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{
+    handlers::{
+        broadcast_presence,
+        group::{decode_payload, failed, succeeded},
+    },
+    state::ServerState,
+    ticket::unix_now,
+    types::HandleEntry,
+};
+use common::types::{ClaimHandlePayload, ServerResponse, WhoisPayload, WhoisResponse};
+
+/// Shortest handle a user can claim
+const MIN_HANDLE_LEN: usize = 3;
+
+/// Longest handle a user can claim
+const MAX_HANDLE_LEN: usize = 32;
+
+/// Claim a handle, or change the handle of the client
+/// A handle belongs to the key that claimed it first
+pub async fn claim_handle(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+    device_id: &str,
+) -> ServerResponse {
+    let data: ClaimHandlePayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let handle = data.handle.to_lowercase();
+    if let Err(err) = validate_handle(&handle) {
+        return failed(err);
+    }
+
+    match register(state, &client_id, device_id, &handle).await {
+        Ok(true) => {
+            println!("🪪 {} is now @{}", &client_id[..8], handle);
+            broadcast_presence(state, &client_id).await;
+            succeeded()
+        }
+        Ok(false) => failed(format!("@{} is taken", handle)),
+        Err(err) => failed(format!("Failed to claim handle: {}", err)),
+    }
+}
+
+/// Look up the user id and public key a handle is registered to
+pub async fn whois(state: &ServerState, payload: Vec<u8>) -> ServerResponse {
+    let data: WhoisPayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+
+    let Some(entry) = state.store.get_handle(&data.handle.to_lowercase()).await else {
+        return failed(format!("No user is called @{}", data.handle));
+    };
+
+    let res_payload = WhoisResponse {
+        handle: entry.handle,
+        user_id: entry.user_id,
+        public_key: entry.public_key,
+    };
+    ServerResponse {
+        success: true,
+        payload: Some(bincode::encode_to_vec(&res_payload, bincode::config::standard()).unwrap()),
+        error: None,
+    }
+}
+
+/// Name other users see, the registered handle of the user or the username of one of its devices
+pub async fn display_name(state: &ServerState, user_id: &str) -> Option<String> {
+    match state.store.handle_of(user_id).await {
+        Some(handle) => Some(handle),
+        None => state
+            .store
+            .get_devices(user_id)
+            .await
+            .first()
+            .map(|device| device.username.clone()),
+    }
+}
+
+/// Claim the username a device connected with, if the user has no handle yet and it's free
+pub async fn claim_username(state: &ServerState, client_id: &str, device_id: &str) {
+    if state.store.handle_of(client_id).await.is_some() {
+        return;
+    }
+    let Some(client) = state.store.get_client(client_id, device_id).await else {
+        return;
+    };
+    let handle = client.username.to_lowercase();
+    if validate_handle(&handle).is_err() {
+        return;
+    }
+
+    match register(state, client_id, device_id, &handle).await {
+        Ok(true) => println!("🪪 {} claimed @{}", &client_id[..8], handle),
+        Ok(false) => {}
+        Err(err) => eprintln!("❌ Failed to claim @{}: {}", handle, err),
+    }
+}
+
+/// Register a handle to the key of a connected device
+async fn register(
+    state: &ServerState,
+    client_id: &str,
+    device_id: &str,
+    handle: &str,
+) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+    let Some(client) = state.store.get_client(client_id, device_id).await else {
+        return Err("Device isn't connected".into());
+    };
+
+    state
+        .store
+        .claim_handle(HandleEntry {
+            handle: handle.to_string(),
+            user_id: client_id.to_string(),
+            public_key: client.public_key,
+            claimed_at: unix_now(),
+        })
+        .await
+}
+
+/// Check that a lowercase handle is 3 to 32 letters, digits, `_`, `.` or `-`
+fn validate_handle(handle: &str) -> Result<(), String> {
+    if handle.len() < MIN_HANDLE_LEN || handle.len() > MAX_HANDLE_LEN {
+        return Err(format!(
+            "A handle is {} to {} characters long",
+            MIN_HANDLE_LEN, MAX_HANDLE_LEN
+        ));
+    }
+    if !handle
+        .chars()
+        .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || matches!(c, '_' | '.' | '-'))
+    {
+        return Err("A handle only has letters, digits, '_', '.' and '-'".to_string());
+    }
+    Ok(())
+}
@@ -0,0 +1,666 @@
+use crate::{
+    handlers::send_event,
+    invite::{issue_invite, open_invite, redeem_invite},
+    state::ServerState,
+    ticket::unix_now,
+    types::{GroupChange, GroupChat},
+};
+use common::{
+    types::{
+        AddGroupMemberPayload, CreateInvitePayload, GroupEvent, GroupEventKind, GroupMemberInfo,
+        GroupMemberPayload, GroupMembersResponse, GroupPayload, GroupPermission, GroupRole,
+        GroupRolePayload, InviteResponse, JoinGroupPayload, JoinGroupResponse, RenameGroupPayload,
+        ServerEvent, ServerResponse,
+    },
+    utils::enc::generate_session_data,
+};
+
+/// Longest name a group can be renamed to
+const MAX_GROUP_NAME_LEN: usize = 64;
+
+/// Add a member to a group, moderators and above
+pub async fn add_group_member(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+) -> ServerResponse {
+    let data: AddGroupMemberPayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let (group, role) = match member_group(state, &data.group_id, &client_id).await {
+        Ok(group) => group,
+        Err(response) => return response,
+    };
+
+    if !role.can(GroupPermission::Invite) {
+        return failed(format!("A {} can't add members", role));
+    }
+    if group.members.contains_key(&data.member_id) {
+        return failed("Already a member of this group");
+    }
+    if state.store.is_blocked(&data.member_id, &client_id).await {
+        return failed("This user doesn't accept invitations from you");
+    }
+
+    admit_member(state, group, &data.member_id, &client_id).await
+}
+
+/// Create an invite token to a group, moderators and above
+pub async fn create_invite(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+) -> ServerResponse {
+    let data: CreateInvitePayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let (group, role) = match member_group(state, &data.group_id, &client_id).await {
+        Ok(group) => group,
+        Err(response) => return response,
+    };
+
+    if !role.can(GroupPermission::Invite) {
+        return failed(format!("A {} can't invite members", role));
+    }
+    if data.max_uses == Some(0) || data.expires_in == Some(0) {
+        return failed("An invite needs at least one use and a lifetime");
+    }
+
+    let expires_at = data.expires_in.map(|secs| unix_now().saturating_add(secs));
+    let token = match issue_invite(state, &group.group_id, data.max_uses, expires_at).await {
+        Ok(token) => token,
+        Err(err) => return failed(format!("Failed to issue invite: {}", err)),
+    };
+    println!(
+        "🎟️ Invite to group {} issued by {}",
+        &group.group_id[..8],
+        &client_id[..8]
+    );
+
+    let res_payload = InviteResponse { token };
+    ServerResponse {
+        success: true,
+        payload: Some(bincode::encode_to_vec(&res_payload, bincode::config::standard()).unwrap()),
+        error: None,
+    }
+}
+
+/// Join a group with an invite token
+pub async fn join_group(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+) -> ServerResponse {
+    let data: JoinGroupPayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let Some((invite_id, group_id)) = open_invite(state, &data.token).await else {
+        return failed("Invalid or expired invite");
+    };
+    let Some(group) = state.store.get_group(&group_id).await else {
+        return failed("Group not found");
+    };
+    if group.members.contains_key(&client_id) {
+        return failed("You are already a member of this group");
+    }
+    if !redeem_invite(state, &invite_id).await {
+        return failed("Invalid or expired invite");
+    }
+
+    let response = admit_member(state, group, &client_id, &client_id).await;
+    if !response.success {
+        return response;
+    }
+    let Some(group) = state.store.get_group(&group_id).await else {
+        return failed("Group not found");
+    };
+
+    let res_payload = JoinGroupResponse {
+        group_id: group.group_id,
+        group_name: group.group_name,
+        session_key: group.session_key,
+        channel: group.channel,
+    };
+    ServerResponse {
+        success: true,
+        payload: Some(bincode::encode_to_vec(&res_payload, bincode::config::standard()).unwrap()),
+        error: None,
+    }
+}
+
+/// Subscribe to a channel, anyone knowing its id can
+pub async fn subscribe_channel(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+) -> ServerResponse {
+    let data: GroupPayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let group = match state.store.get_group(&data.group_id).await {
+        Some(group) if group.channel => group,
+        _ => return failed("Channel not found"),
+    };
+    if group.members.contains_key(&client_id) {
+        return failed("You are already subscribed to this channel");
+    }
+
+    let response = admit_member(state, group, &client_id, &client_id).await;
+    if !response.success {
+        return response;
+    }
+    let Some(group) = state.store.get_group(&data.group_id).await else {
+        return failed("Channel not found");
+    };
+
+    let res_payload = JoinGroupResponse {
+        group_id: group.group_id,
+        group_name: group.group_name,
+        session_key: group.session_key,
+        channel: group.channel,
+    };
+    ServerResponse {
+        success: true,
+        payload: Some(bincode::encode_to_vec(&res_payload, bincode::config::standard()).unwrap()),
+        error: None,
+    }
+}
+
+/// Unsubscribe from a channel, the owner has to transfer or delete it instead
+pub async fn unsubscribe_channel(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+) -> ServerResponse {
+    let data: GroupPayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let (group, role) = match member_group(state, &data.group_id, &client_id).await {
+        Ok(group) => group,
+        Err(response) => return response,
+    };
+
+    if !group.channel {
+        return failed("Not a channel, use leave to leave a group");
+    }
+    if role == GroupRole::Owner {
+        return failed("Transfer the ownership or delete the channel before unsubscribing");
+    }
+
+    let change = GroupChange::RemoveMember(client_id.clone());
+    change_group(state, group, &client_id, change, GroupEventKind::MemberLeft).await
+}
+
+/// Remove a member from a group
+/// Moderators and above can remove members with a lower role
+pub async fn remove_group_member(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+) -> ServerResponse {
+    let data: GroupMemberPayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let (group, role) = match member_group(state, &data.group_id, &client_id).await {
+        Ok(group) => group,
+        Err(response) => return response,
+    };
+
+    if data.member_id == client_id {
+        return failed("Use leave to leave the group");
+    }
+    let Some(member_role) = group.role_of(&data.member_id) else {
+        return failed("Not a member of this group");
+    };
+    if !role.can(GroupPermission::Remove) {
+        return failed(format!("A {} can't remove members", role));
+    }
+    if member_role >= role {
+        return failed("You can only remove members with a lower role");
+    }
+
+    let change = GroupChange::RemoveMember(data.member_id.clone());
+    let kind = GroupEventKind::MemberRemoved(data.member_id);
+    change_group(state, group, &client_id, change, kind).await
+}
+
+/// Leave a group
+/// The owner has to transfer the ownership first, unless it is the last member
+pub async fn leave_group(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+) -> ServerResponse {
+    let data: GroupPayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let (group, role) = match member_group(state, &data.group_id, &client_id).await {
+        Ok(group) => group,
+        Err(response) => return response,
+    };
+
+    if role == GroupRole::Owner {
+        if group.members.len() > 1 {
+            return failed("Transfer the ownership or delete the group before leaving");
+        }
+        return remove_group(state, group, &client_id).await;
+    }
+
+    let change = GroupChange::RemoveMember(client_id.clone());
+    change_group(state, group, &client_id, change, GroupEventKind::MemberLeft).await
+}
+
+/// Rename a group, admins and the owner only
+pub async fn rename_group(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+) -> ServerResponse {
+    let data: RenameGroupPayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let (group, role) = match member_group(state, &data.group_id, &client_id).await {
+        Ok(group) => group,
+        Err(response) => return response,
+    };
+
+    if !role.can(GroupPermission::Rename) {
+        return failed(format!("A {} can't rename the group", role));
+    }
+    let name = data.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_GROUP_NAME_LEN {
+        return failed(format!(
+            "Group name must be 1 to {} characters",
+            MAX_GROUP_NAME_LEN
+        ));
+    }
+
+    let change = GroupChange::Rename(name.to_string());
+    change_group(state, group, &client_id, change, GroupEventKind::Renamed).await
+}
+
+/// Delete a group along with its history, owner only
+pub async fn delete_group(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+) -> ServerResponse {
+    let data: GroupPayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let (group, role) = match member_group(state, &data.group_id, &client_id).await {
+        Ok(group) => group,
+        Err(response) => return response,
+    };
+
+    if role != GroupRole::Owner {
+        return failed("Only the group owner can delete the group");
+    }
+    remove_group(state, group, &client_id).await
+}
+
+/// Give a member of a group another role
+pub async fn set_group_role(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+) -> ServerResponse {
+    let data: GroupRolePayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    change_role(state, &client_id, &data.group_id, data.member_id, data.role).await
+}
+
+/// Make a member of a group an admin (`true`), or a plain member (`false`)
+pub async fn set_group_admin(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+    admin: bool,
+) -> ServerResponse {
+    let data: GroupMemberPayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let role = match admin {
+        true => GroupRole::Admin,
+        false => GroupRole::Member,
+    };
+    change_role(state, &client_id, &data.group_id, data.member_id, role).await
+}
+
+/// Make another member the owner of a group, the previous owner becomes an admin
+pub async fn transfer_group(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+) -> ServerResponse {
+    let data: GroupMemberPayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let (group, role) = match member_group(state, &data.group_id, &client_id).await {
+        Ok(group) => group,
+        Err(response) => return response,
+    };
+
+    if role != GroupRole::Owner {
+        return failed("Only the group owner can transfer the ownership");
+    }
+    if !group.members.contains_key(&data.member_id) || data.member_id == client_id {
+        return failed("Not a member of this group");
+    }
+
+    let change = GroupChange::TransferOwnership(data.member_id.clone());
+    let kind = GroupEventKind::OwnerChanged(data.member_id);
+    change_group(state, group, &client_id, change, kind).await
+}
+
+/// Replace the session key of a group, admins and the owner only
+/// Members get the new key pushed, removed members can't read new messages anymore
+pub async fn rekey_group(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+) -> ServerResponse {
+    let data: GroupPayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let (group, role) = match member_group(state, &data.group_id, &client_id).await {
+        Ok(group) => group,
+        Err(response) => return response,
+    };
+
+    if !role.can(GroupPermission::Rekey) {
+        return failed(format!("A {} can't rekey the group", role));
+    }
+
+    let (session_key, _) = generate_session_data();
+    let change = GroupChange::Rekey(session_key.clone());
+    change_group(
+        state,
+        group,
+        &client_id,
+        change,
+        GroupEventKind::Rekeyed(session_key),
+    )
+    .await
+}
+
+/// List the members of a group along with their roles, highest role first
+pub async fn list_group_members(
+    state: &ServerState,
+    payload: Vec<u8>,
+    client_id: String,
+) -> ServerResponse {
+    let data: GroupPayload = match decode_payload(&payload) {
+        Ok(data) => data,
+        Err(response) => return response,
+    };
+    let (group, _) = match member_group(state, &data.group_id, &client_id).await {
+        Ok(group) => group,
+        Err(response) => return response,
+    };
+
+    // Subscribers of a channel only see its publishers and themselves
+    let listed = |user_id: &String| {
+        !group.channel
+            || group.can_post(&client_id)
+            || group.can_post(user_id)
+            || *user_id == client_id
+    };
+    let mut members: Vec<GroupMemberInfo> = group
+        .members
+        .keys()
+        .filter(|user_id| listed(user_id))
+        .map(|user_id| GroupMemberInfo {
+            user_id: user_id.clone(),
+            role: group.role_of(user_id).unwrap_or_default(),
+        })
+        .collect();
+    members.sort_by(|a, b| b.role.cmp(&a.role).then_with(|| a.user_id.cmp(&b.user_id)));
+
+    let res_payload = GroupMembersResponse { members };
+    ServerResponse {
+        success: true,
+        payload: Some(bincode::encode_to_vec(&res_payload, bincode::config::standard()).unwrap()),
+        error: None,
+    }
+}
+
+/// Add a member to a group and tell the members
+/// The new member's connected devices take part right away and get the session key
+async fn admit_member(
+    state: &ServerState,
+    group: GroupChat,
+    member_id: &str,
+    actor_id: &str,
+) -> ServerResponse {
+    let group_id = group.group_id.clone();
+    let change = GroupChange::AddMember(member_id.to_string());
+    let kind = GroupEventKind::MemberAdded(member_id.to_string());
+    let response = change_group(state, group, actor_id, change, kind).await;
+    if !response.success {
+        return response;
+    }
+
+    // The member doesn't need to join with `new` if it's connected
+    let Some(group) = state.store.get_group(&group_id).await else {
+        return response;
+    };
+    if !state.store.get_devices(member_id).await.is_empty() {
+        state.store.add_client_group(member_id, &group_id).await;
+        state
+            .store
+            .set_group_member_active(&group_id, member_id, true)
+            .await;
+    }
+    let event = GroupEvent {
+        group_id,
+        group_name: group.group_name,
+        actor_id: actor_id.to_string(),
+        kind: GroupEventKind::Joined {
+            session_key: group.session_key,
+            channel: group.channel,
+        },
+    };
+    notify_members(state, [&member_id.to_string()], event).await;
+
+    response
+}
+
+/// Give a member a role lower than the one of the client, the member's role must be lower too
+async fn change_role(
+    state: &ServerState,
+    client_id: &str,
+    group_id: &str,
+    member_id: String,
+    new_role: GroupRole,
+) -> ServerResponse {
+    let (group, role) = match member_group(state, group_id, client_id).await {
+        Ok(group) => group,
+        Err(response) => return response,
+    };
+
+    if new_role == GroupRole::Owner {
+        return failed("Use transfer to change the owner");
+    }
+    if member_id == client_id {
+        return failed("You can't change your own role");
+    }
+    let Some(member_role) = group.role_of(&member_id) else {
+        return failed("Not a member of this group");
+    };
+    if !role.can(GroupPermission::SetRole) {
+        return failed(format!("A {} can't change roles", role));
+    }
+    if member_role >= role || new_role >= role {
+        return failed("You can only give roles lower than yours to members with a lower role");
+    }
+    if member_role == new_role {
+        return failed(format!("Already a {}", new_role));
+    }
+
+    let change = GroupChange::SetRole(member_id.clone(), new_role);
+    let kind = GroupEventKind::RoleChanged(member_id, new_role);
+    change_group(state, group, client_id, change, kind).await
+}
+
+/// Apply a change to a group and tell its members, including the ones it removed
+/// Added members are not told here, they need the session key
+async fn change_group(
+    state: &ServerState,
+    group: GroupChat,
+    actor_id: &str,
+    change: GroupChange,
+    kind: GroupEventKind,
+) -> ServerResponse {
+    let updated = match state.store.update_group(&group.group_id, change).await {
+        Ok(Some(updated)) => updated,
+        Ok(None) => return failed("Group not found"),
+        Err(err) => return failed(format!("Failed to update group: {}", err)),
+    };
+
+    let removed = group
+        .members
+        .keys()
+        .filter(|member| !updated.members.contains_key(*member));
+    for member_id in removed {
+        state
+            .store
+            .remove_client_group(member_id, &group.group_id)
+            .await;
+    }
+
+    let event = GroupEvent {
+        group_id: updated.group_id.clone(),
+        group_name: updated.group_name.clone(),
+        actor_id: actor_id.to_string(),
+        kind,
+    };
+    // Never log the new session key
+    let summary = match &event.kind {
+        GroupEventKind::Rekeyed(_) => "Rekeyed".to_string(),
+        GroupEventKind::Joined { .. } => "Joined".to_string(),
+        kind => format!("{:?}", kind),
+    };
+    println!(
+        "👥 Group {} changed by {}: {}",
+        &updated.group_id[..8],
+        &actor_id[..8],
+        summary
+    );
+    let members = audience(&group, &event.kind);
+    notify_members(state, members, event).await;
+
+    succeeded()
+}
+
+/// Members told about a change, subscribers of a channel only learn about
+/// changes to the channel itself and to their own membership
+fn audience<'a>(group: &'a GroupChat, kind: &GroupEventKind) -> Vec<&'a String> {
+    let subject = match kind {
+        GroupEventKind::MemberAdded(member_id)
+        | GroupEventKind::MemberRemoved(member_id)
+        | GroupEventKind::RoleChanged(member_id, _) => Some(member_id.as_str()),
+        GroupEventKind::MemberLeft => None,
+        _ => return group.members.keys().collect(),
+    };
+    group
+        .members
+        .keys()
+        .filter(|member_id| {
+            !group.channel || group.can_post(member_id) || subject == Some(member_id.as_str())
+        })
+        .collect()
+}
+
+/// Delete a group and tell its former members
+async fn remove_group(state: &ServerState, group: GroupChat, actor_id: &str) -> ServerResponse {
+    if let Err(err) = state.store.delete_group(&group.group_id).await {
+        return failed(format!("Failed to delete group: {}", err));
+    }
+    for member_id in group.members.keys() {
+        state
+            .store
+            .remove_client_group(member_id, &group.group_id)
+            .await;
+    }
+
+    println!(
+        "🗑️ Group {} deleted by {}",
+        &group.group_id[..8],
+        &actor_id[..8]
+    );
+    let event = GroupEvent {
+        group_id: group.group_id.clone(),
+        group_name: group.group_name.clone(),
+        actor_id: actor_id.to_string(),
+        kind: GroupEventKind::Deleted,
+    };
+    notify_members(state, group.members.keys(), event).await;
+
+    succeeded()
+}
+
+/// Push a group event to every connected device of the given members
+async fn notify_members<'a>(
+    state: &ServerState,
+    members: impl IntoIterator<Item = &'a String>,
+    event: GroupEvent,
+) {
+    for member_id in members {
+        for device in state.store.get_devices(member_id).await {
+            send_event(&device, ServerEvent::Group(event.clone()));
+        }
+    }
+}
+
+/// Get a group the client is a member of, along with the role of the client
+async fn member_group(
+    state: &ServerState,
+    group_id: &str,
+    client_id: &str,
+) -> Result<(GroupChat, GroupRole), ServerResponse> {
+    let Some(group) = state.store.get_group(group_id).await else {
+        return Err(failed("Group not found"));
+    };
+    match group.role_of(client_id) {
+        Some(role) => Ok((group, role)),
+        None => Err(failed("You are not a member of this group")),
+    }
+}
+
+pub(super) fn decode_payload<T: bincode::Decode<()>>(payload: &[u8]) -> Result<T, ServerResponse> {
+    match bincode::decode_from_slice(payload, bincode::config::standard()) {
+        Ok((data, _)) => Ok(data),
+        Err(err) => Err(failed(format!("Failed to decode payload: {}", err))),
+    }
+}
+
+pub(super) fn succeeded() -> ServerResponse {
+    ServerResponse {
+        success: true,
+        payload: None,
+        error: None,
+    }
+}
+
+pub(super) fn failed(error: impl Into<String>) -> ServerResponse {
+    ServerResponse {
+        success: false,
+        payload: None,
+        error: Some(error.into()),
+    }
+}
@@ -1,8 +1,16 @@
 pub mod client;
+pub mod contact;
+pub mod directory;
+pub mod group;
 pub mod msg;
+pub mod presence;
 pub mod task;
 pub mod cmd;
 
 pub use client::*;
+pub use contact::*;
+pub use directory::*;
+pub use group::*;
 pub use msg::*;
+pub use presence::*;
 pub use cmd::*;
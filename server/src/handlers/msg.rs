@@ -1,70 +1,395 @@
-use crate::data;
-use common::{net::Packet, types::Message, utils::net::write_packet};
+use crate::{
+    handlers::display_name,
+    state::ServerState,
+    ticket::unix_now,
+    types::{
+        Client, Delivery, HistoryEntry, Outgoing, PendingDelivery, QueuedPacket, StatusReport,
+    },
+};
+use common::{
+    net::{ChatMessageKind, Packet, StreamWriter},
+    types::{Message, MessageStatus, Receipt, ServerEvent, TypingEvent},
+    utils::net::write_packet,
+};
+use std::sync::Arc;
+use tokio::sync::{Mutex as AsyncMutex, mpsc::Receiver};
+use uuid::Uuid;
 
 /// Handle a group message
-/// Send the message to every active member of the group
-pub async fn handle_group_message(packet: Packet, group_id: &str) {
+/// Queue the message for every member of the group, members that are offline get it queued until they connect
+/// The other devices of the sender get a copy too
+/// The message is given an id, which the sender learns from the status
+pub async fn handle_group_message(
+    state: &ServerState,
+    sender_id: &str,
+    device_id: &str,
+    packet: Packet,
+    group_id: &str,
+) {
     // Find the group chat
-    let group = match data::GROUPS.lock().await.get(group_id) {
-        Some(group) => group.clone(),
+    let group = match state.store.get_group(group_id).await {
+        Some(group) => group,
         None => return,
     };
+    // Read-only members, channel subscribers and outsiders can't post
+    if !group.can_post(sender_id) {
+        return;
+    }
 
-    // Decode the message
-    let message: Message =
-        match bincode::decode_from_slice(&packet.payload, bincode::config::standard()) {
-            Ok((message, _)) => message,
-            Err(_) => return,
-        };
+    let (message, packet) = match stamp_message(sender_id, packet) {
+        Some(stamped) => stamped,
+        None => return,
+    };
+
+    record_history(state, group_id, &message, &packet).await;
 
     // Broadcast the message to all clients in the group
-    for (member_id, is_active) in group.members {
-        if !is_active {
+    let sender = state.store.get_client(sender_id, device_id).await;
+    let report = StatusReport::new(sender.as_ref(), new_status(group_id, &message));
+    let mut offline = Vec::new();
+    for member_id in group.members.keys() {
+        if member_id == sender_id {
             continue;
         }
-        if member_id == message.sender_id.clone() {
-            continue;
+        let delivery = PendingDelivery::new(Some(report.clone()));
+        let _delivering = state.delivery.of(member_id).lock().await;
+        if !push_devices(state, member_id, &packet, &delivery).await {
+            offline.push((member_id, delivery));
         }
-        let member = match data::CLIENTS.lock().await.get(&member_id) {
-            Some(member) => member.clone(),
-            None => continue,
-        };
-
-        let _ = write_packet::<Packet>(member.writer.clone(), packet.clone()).await;
     }
+    // Online members don't wait for the offline queues to be written
+    for (member_id, delivery) in offline {
+        deliver(state, member_id, &packet, delivery).await;
+    }
+    sync_devices(state, sender_id, device_id, &packet).await;
 }
 
 // Handle a direct message
-pub async fn handle_direct_message(packet: Packet, session_id: &str) {
+pub async fn handle_direct_message(
+    state: &ServerState,
+    sender_id: &str,
+    device_id: &str,
+    packet: Packet,
+    session_id: &str,
+) {
     // Find the conversation
-    let dm = match data::CONVERSATIONS.lock().await.get(session_id) {
-        Some(dm) => dm.clone(),
+    let dm = match state.store.get_dm(session_id).await {
+        Some(dm) => dm,
         None => return,
     };
+    if !dm.members.contains_key(sender_id) {
+        return;
+    }
+
+    let (message, packet) = match stamp_message(sender_id, packet) {
+        Some(stamped) => stamped,
+        None => return,
+    };
+
+    // Find recipient
+    let recipient = match dm.members.keys().find(|member| *member != sender_id) {
+        Some(recipient) => recipient,
+        None => return,
+    };
+
+    // Nothing is relayed until the contact request is accepted, nor to a recipient that blocked the sender
+    if dm.requester.is_some() || state.store.is_blocked(recipient, sender_id).await {
+        let mut status = new_status(session_id, &message);
+        status.refused += 1;
+        send_status(state, sender_id, device_id, status).await;
+        return;
+    }
+
+    record_history(state, session_id, &message, &packet).await;
 
-    // Decode the message
-    let message: Message =
+    let sender = state.store.get_client(sender_id, device_id).await;
+    let report = StatusReport::new(sender.as_ref(), new_status(session_id, &message));
+    deliver(
+        state,
+        recipient,
+        &packet,
+        PendingDelivery::new(Some(report)),
+    )
+    .await;
+    sync_devices(state, sender_id, device_id, &packet).await;
+}
+
+/// Handle receipts for messages of a DM or group
+/// Relayed to the author of the messages, or queued while it is offline, never stored
+pub async fn handle_receipt(
+    state: &ServerState,
+    sender_id: &str,
+    packet: Packet,
+    session_id: &str,
+) {
+    let members = match state.store.get_dm(session_id).await {
+        Some(dm) => dm.members,
+        None => match state.store.get_group(session_id).await {
+            Some(group) => group.members,
+            None => return,
+        },
+    };
+
+    let (mut receipt, _): (Receipt, usize) =
         match bincode::decode_from_slice(&packet.payload, bincode::config::standard()) {
-            Ok((message, _)) => message,
+            Ok(decoded) => decoded,
             Err(_) => return,
         };
+    if !members.contains_key(sender_id)
+        || !members.contains_key(&receipt.author_id)
+        || receipt.author_id == sender_id
+        || state.store.is_blocked(&receipt.author_id, sender_id).await
+    {
+        return;
+    }
+    receipt.reader_id = sender_id.to_string();
 
-    // Find recipient
-    let mut recipient = String::new();
-    for (member, _) in &dm.members {
-        if member == &message.sender_id {
+    let payload = match bincode::encode_to_vec(&receipt, bincode::config::standard()) {
+        Ok(payload) => payload,
+        Err(_) => return,
+    };
+    let packet = Packet {
+        kind: packet.kind,
+        payload,
+    };
+    deliver(
+        state,
+        &receipt.author_id,
+        &packet,
+        PendingDelivery::new(None),
+    )
+    .await;
+}
+
+/// Handle a typing signal
+/// Relayed to the connected devices of the other members, never queued nor stored
+/// Members that blocked the sender don't get it
+pub async fn handle_typing(state: &ServerState, sender_id: &str, session_id: &str) {
+    let members = match state.store.get_dm(session_id).await {
+        // Nothing is relayed until the contact request is accepted
+        Some(dm) if dm.requester.is_some() => return,
+        Some(dm) => dm.members,
+        None => match state.store.get_group(session_id).await {
+            // Read-only members don't type
+            Some(group) if group.can_post(sender_id) => group.members,
+            _ => return,
+        },
+    };
+    if !members.contains_key(sender_id) {
+        return;
+    }
+
+    let event = TypingEvent {
+        session_id: session_id.to_string(),
+        user_id: sender_id.to_string(),
+        username: display_name(state, sender_id).await,
+    };
+    for member_id in members.keys().filter(|member| *member != sender_id) {
+        if state.store.is_blocked(member_id, sender_id).await {
             continue;
-        } else {
-            recipient = member.clone();
         }
+        for device in state.store.get_devices(member_id).await {
+            send_event(&device, ServerEvent::Typing(event.clone()));
+        }
+    }
+}
+
+/// Take the messages queued for a user whose device just connected
+pub async fn take_offline_queue(state: &ServerState, user_id: &str) -> Vec<QueuedPacket> {
+    state
+        .store
+        .take_offline(user_id)
+        .await
+        .unwrap_or_else(|err| {
+            eprintln!("❌ Failed to load queued messages: {}", err);
+            Vec::new()
+        })
+}
+
+/// Send the messages queued for a user whose device just connected, oldest first
+/// Written before the writer task of the connection starts, so they come before newer ones
+pub async fn flush_offline_queue(
+    state: &ServerState,
+    user_id: &str,
+    queued: Vec<QueuedPacket>,
+    writer: StreamWriter,
+) {
+    if queued.is_empty() {
+        return;
     }
 
-    // Check if client is online
-    let recipient = match data::CLIENTS.lock().await.get(&recipient) {
-        Some(client) => client.clone(),
-        None => return,
+    let total = queued.len();
+    let mut queued = queued.into_iter();
+    while let Some(next) = queued.next() {
+        if write_packet::<Packet>(writer.clone(), next.packet.clone())
+            .await
+            .is_err()
+        {
+            // The connection is already gone, keep the rest for the next one
+            for rest in std::iter::once(next).chain(queued) {
+                let _ = state.store.enqueue_offline(user_id, rest, usize::MAX).await;
+            }
+            return;
+        }
+    }
+
+    println!(
+        "📬 Delivered {} queued messages to {}",
+        total,
+        &user_id[..8]
+    );
+}
+
+/// Keep the message in the history of the chat, if history is enabled
+async fn record_history(state: &ServerState, chat_id: &str, message: &Message, packet: &Packet) {
+    if state.config.history.is_none() {
+        return;
+    }
+
+    let entry = HistoryEntry {
+        id: 0,
+        chat_id: chat_id.to_string(),
+        timestamps: message.timestamps,
+        payload: packet.payload.clone(),
+        stored_at: unix_now(),
+    };
+    if let Err(err) = state.store.append_history(entry).await {
+        eprintln!("❌ Failed to record history: {}", err);
+    }
+}
+
+/// Queue a packet for every device of a recipient without waiting for any of them,
+/// or queue it until one connects if none is connected or keeps up
+/// Pushed packets are settled once a writer takes them, or delivered again if their connections close
+async fn deliver(
+    state: &ServerState,
+    recipient_id: &str,
+    packet: &Packet,
+    delivery: Arc<PendingDelivery>,
+) {
+    let _delivering = state.delivery.of(recipient_id).lock().await;
+    if push_devices(state, recipient_id, packet, &delivery).await {
+        return;
+    }
+
+    let config = &state.config.offline_queue;
+    let queued = QueuedPacket {
+        packet: packet.clone(),
+        expires_at: unix_now() + config.ttl,
     };
+    match state
+        .store
+        .enqueue_offline(recipient_id, queued, config.max_bytes)
+        .await
+    {
+        Ok(true) => delivery.settle(Delivery::Queued),
+        Ok(false) => {}
+        Err(err) => eprintln!("❌ Failed to queue message: {}", err),
+    }
+}
+
+/// Queue a packet for every connected device of a recipient, false if none of them took it
+/// The delivery lock of the recipient must be held
+async fn push_devices(
+    state: &ServerState,
+    recipient_id: &str,
+    packet: &Packet,
+    delivery: &Arc<PendingDelivery>,
+) -> bool {
+    let mut pushed = false;
+    delivery.pushing(true);
+    for device in state.store.get_devices(recipient_id).await {
+        pushed |= device.push_delivery(packet.clone(), delivery);
+    }
+    delivery.pushing(false);
+    pushed
+}
+
+/// Deliver again the packets left in the outbox of a closed connection,
+/// unless another device of the user took them or still waits for them
+pub async fn requeue_outbox(
+    state: &ServerState,
+    user_id: &str,
+    outbox: &AsyncMutex<Receiver<Outgoing>>,
+) {
+    // Waits for the writer task to let go of it
+    let mut outbox = outbox.lock().await;
+    outbox.close();
+    while let Ok(outgoing) = outbox.try_recv() {
+        let Some(delivery) = outgoing.delivery else {
+            continue;
+        };
+        if delivery.abandoned() {
+            deliver(state, user_id, &outgoing.packet, delivery).await;
+        }
+    }
+}
+
+/// Give a message its id and the authenticated sender, the packet is encoded again
+fn stamp_message(sender_id: &str, packet: Packet) -> Option<(Message, Packet)> {
+    let (mut message, _): (Message, usize) =
+        bincode::decode_from_slice(&packet.payload, bincode::config::standard()).ok()?;
+    message.sender_id = sender_id.to_string();
+    message.message_id = Some(Uuid::new_v4().simple().to_string());
+
+    let payload = bincode::encode_to_vec(&message, bincode::config::standard()).ok()?;
+    Some((
+        message,
+        Packet {
+            kind: packet.kind,
+            payload,
+        },
+    ))
+}
+
+fn new_status(session_id: &str, message: &Message) -> MessageStatus {
+    MessageStatus {
+        session_id: session_id.to_string(),
+        timestamps: message.timestamps,
+        message_id: message.message_id.clone().unwrap_or_default(),
+        delivered: 0,
+        queued: 0,
+        dropped: 0,
+        refused: 0,
+    }
+}
+
+/// Send a packet to the devices of the sender other than the one it came from
+/// Devices that aren't connected can load it from the history instead
+async fn sync_devices(state: &ServerState, sender_id: &str, device_id: &str, packet: &Packet) {
+    for device in state.store.get_devices(sender_id).await {
+        if device.device_id != device_id {
+            device.push(packet.clone());
+        }
+    }
+}
+
+/// Tell the sending device what happened to its message
+async fn send_status(state: &ServerState, sender_id: &str, device_id: &str, status: MessageStatus) {
+    if let Some(sender) = state.store.get_client(sender_id, device_id).await {
+        send_event(&sender, ServerEvent::MessageStatus(status));
+    }
+}
+
+/// Push an event to every device of a user, or queue it until one connects
+pub async fn deliver_event(state: &ServerState, user_id: &str, event: ServerEvent) {
+    if let Some(packet) = event_packet(&event) {
+        deliver(state, user_id, &packet, PendingDelivery::new(None)).await;
+    }
+}
+
+/// Push an event to a connected device, dropped if its queue is full
+pub fn send_event(client: &Client, event: ServerEvent) {
+    if let Some(packet) = event_packet(&event) {
+        client.push(packet);
+    }
+}
 
-    // Send the packet to the recipient
-    let _ = write_packet::<Packet>(recipient.writer.clone(), packet.clone()).await;
+/// Packet carrying an event
+pub fn event_packet(event: &ServerEvent) -> Option<Packet> {
+    let payload = bincode::encode_to_vec(event, bincode::config::standard()).ok()?;
+    Some(Packet {
+        kind: ChatMessageKind::Event,
+        payload,
+    })
 }
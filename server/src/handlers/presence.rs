@@ -0,0 +1,108 @@
+use crate::{
+    handlers::{display_name, send_event},
+    state::ServerState,
+};
+use common::types::{Presence, PresenceUpdate, ServerEvent};
+use std::collections::HashMap;
+
+/// Tell every connected user sharing a DM or group with `user_id` about its presence
+/// Sent when its first device connects, its last device disconnects and when it changes its status
+pub async fn broadcast_presence(state: &ServerState, user_id: &str) {
+    let update = presence_of(state, user_id).await;
+    for (contact_id, chats) in contacts_of(state, user_id).await {
+        let update = PresenceUpdate {
+            chats,
+            ..update.clone()
+        };
+        send_to_user(state, &contact_id, update).await;
+    }
+}
+
+/// Exchange the presence of a user and of the other members of a chat it joined
+/// The device that joined gets the presence of every member, members get the one of the user
+/// Members blocked either way are skipped
+pub async fn exchange_presence(
+    state: &ServerState,
+    user_id: &str,
+    device_id: &str,
+    chat_id: &str,
+    members: Vec<String>,
+) {
+    let device = state.store.get_client(user_id, device_id).await;
+    let mut update = presence_of(state, user_id).await;
+    update.chats = vec![chat_id.to_string()];
+
+    for member_id in members.iter().filter(|member| *member != user_id) {
+        if blocked_pair(state, user_id, member_id).await {
+            continue;
+        }
+        if let Some(device) = &device {
+            let mut member = presence_of(state, member_id).await;
+            member.chats = vec![chat_id.to_string()];
+            send_event(device, ServerEvent::Presence(member));
+        }
+        send_to_user(state, member_id, update.clone()).await;
+    }
+}
+
+/// Current presence of a user, offline if none of its devices is connected
+/// The registered handle of the user is preferred to the username of its devices
+async fn presence_of(state: &ServerState, user_id: &str) -> PresenceUpdate {
+    let devices = state.store.get_devices(user_id).await;
+    let status = state.store.get_status(user_id).await;
+    PresenceUpdate {
+        user_id: user_id.to_string(),
+        username: display_name(state, user_id).await,
+        presence: match devices.is_empty() {
+            true => Presence::Offline,
+            false => status.presence,
+        },
+        status: status.status,
+        chats: Vec::new(),
+    }
+}
+
+/// Users sharing a DM or group with `user_id`, along with the chats they share
+/// Channels are left out, subscribers don't learn about each other,
+/// so are DMs whose contact request wasn't accepted yet and users blocked either way
+async fn contacts_of(state: &ServerState, user_id: &str) -> HashMap<String, Vec<String>> {
+    let (dms, groups) = state.store.get_user_chats(user_id).await;
+    let chats = dms
+        .into_iter()
+        .filter(|dm| dm.requester.is_none())
+        .map(|dm| (dm.dm_id, dm.members))
+        .chain(
+            groups
+                .into_iter()
+                .filter(|group| !group.channel)
+                .map(|group| (group.group_id, group.members)),
+        );
+
+    let mut contacts: HashMap<String, Vec<String>> = HashMap::new();
+    for (chat_id, members) in chats {
+        for member_id in members.into_keys().filter(|member| member != user_id) {
+            contacts.entry(member_id).or_default().push(chat_id.clone());
+        }
+    }
+
+    let mut visible = HashMap::new();
+    for (contact_id, chats) in contacts {
+        if !blocked_pair(state, user_id, &contact_id).await {
+            visible.insert(contact_id, chats);
+        }
+    }
+    visible
+}
+
+/// Whether either user blocked the other, their presence isn't shared then
+async fn blocked_pair(state: &ServerState, user_id: &str, other_id: &str) -> bool {
+    state.store.is_blocked(user_id, other_id).await
+        || state.store.is_blocked(other_id, user_id).await
+}
+
+/// Push a presence update to every connected device of a user
+async fn send_to_user(state: &ServerState, user_id: &str, update: PresenceUpdate) {
+    for device in state.store.get_devices(user_id).await {
+        send_event(&device, ServerEvent::Presence(update.clone()));
+    }
+}
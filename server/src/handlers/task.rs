@@ -1,62 +1,191 @@
-use crate::handlers::{handle_direct_message, handle_group_message, process_command};
+use crate::{
+    handlers::{
+        event_packet, handle_direct_message, handle_group_message, handle_receipt, handle_typing,
+        process_command,
+    },
+    invite::purge_expired_invites,
+    limit::{RateLimiter, Verdict},
+    state::ServerState,
+    ticket::unix_now,
+    types::{Outbox, Outgoing},
+};
 use common::{
     net::{ChatMessageKind, Packet, StreamReader, StreamWriter},
-    types::ServerResponse,
-    utils::net::{read_packet, write_packet},
+    types::{RateLimitKind, ServerEvent},
+    utils::net::{read_packet_limited, write_packet},
 };
-use std::sync::Arc;
+use std::{net::IpAddr, sync::Arc, time::Duration};
 use tokio::{
-    sync::{
-        Mutex as AsyncMutex,
-        mpsc::{UnboundedReceiver, UnboundedSender},
-    },
+    sync::{Mutex as AsyncMutex, mpsc::Receiver},
     task::JoinHandle,
 };
 
-/// Start the writer task
-/// This task is responsible for sending packets to the appropriate clients
-pub async fn start_writer_task(mut rx: UnboundedReceiver<Packet>) -> JoinHandle<()> {
+/// Start the writer task of a connection
+/// This task writes the packets queued in the outbox of the connection, in order,
+/// so a slow connection only holds back its own packets
+/// The outbox is held until the task stops, what it didn't take is left in it
+pub fn start_writer_task(
+    rx: Arc<AsyncMutex<Receiver<Outgoing>>>,
+    wt: StreamWriter,
+) -> JoinHandle<()> {
     tokio::spawn(async move {
-        loop {
-            match rx.recv().await {
-                Some(packet) => match packet.kind.clone() {
-                    ChatMessageKind::DirectMessage(id) => {
-                        handle_direct_message(packet, &id).await;
-                    }
-                    ChatMessageKind::GroupMessage(id) => {
-                        handle_group_message(packet, &id).await;
-                    }
-                    _ => {}
-                },
-                None => break, // channel closed
+        let mut rx = rx.lock().await;
+        while let Some(outgoing) = rx.recv().await {
+            if let Some(delivery) = &outgoing.delivery {
+                delivery.taken();
+            }
+            if write_packet::<Packet>(wt.clone(), outgoing.packet)
+                .await
+                .is_err()
+            {
+                break;
             }
         }
     })
 }
 
 /// Start the reader task
-/// This task is responsible for reading packets from the client
-/// and processing commands
+/// This task is responsible for reading packets from the client,
+/// routing its messages and processing its commands,
+/// within the rate limits of the connection and of its IP address
 pub async fn start_reader_task(
     rd: StreamReader,
-    wt: StreamWriter,
+    outbox: Outbox,
     id: String,
-    tx: Arc<AsyncMutex<UnboundedSender<Packet>>>,
+    device_id: String,
+    peer: Option<IpAddr>,
+    state: Arc<ServerState>,
 ) -> JoinHandle<()> {
     tokio::spawn(async move {
+        let mut limiter = RateLimiter::new(&state.config.rate_limit, &state.ip_limits, peer).await;
+        let max_frame = state.config.connections.max_frame;
         loop {
-            let packet: Packet = match read_packet(rd.clone()).await {
+            let packet: Packet = match read_packet_limited(rd.clone(), max_frame).await {
                 Ok(packet) => packet,
                 Err(_) => break,
             };
 
+            // Events are only sent by the server, they still count before being dropped
+            let kind = match packet.kind {
+                ChatMessageKind::Command(_) => RateLimitKind::Commands,
+                _ => RateLimitKind::Messages,
+            };
+            if let Err(limit) =
+                within_limits(&mut limiter, &outbox, kind, packet.payload.len()).await
+            {
+                println!(
+                    "🚫 Disconnected {} ({}): too many {:?} rate limit violations",
+                    &id[..8],
+                    device_id,
+                    limit
+                );
+                break;
+            }
+
             match packet.kind.clone() {
                 ChatMessageKind::Command(cmd) => {
-                    let response = process_command(packet.payload, id.clone(), &cmd).await;
-                    let _ = write_packet::<ServerResponse>(wt.clone(), response).await;
+                    let response =
+                        process_command(&state, packet.payload, id.clone(), &device_id, &cmd).await;
+                    let payload =
+                        match bincode::encode_to_vec(&response, bincode::config::standard()) {
+                            Ok(payload) => payload,
+                            Err(_) => continue,
+                        };
+                    let response = Packet {
+                        kind: ChatMessageKind::Command(cmd),
+                        payload,
+                    };
+                    // Responses wait for room in the outbox instead of being dropped
+                    let _ = outbox
+                        .queue
+                        .send(Outgoing {
+                            packet: response,
+                            delivery: None,
+                        })
+                        .await;
+                }
+                ChatMessageKind::DirectMessage(dm_id) => {
+                    handle_direct_message(&state, &id, &device_id, packet, &dm_id).await;
+                }
+                ChatMessageKind::GroupMessage(group_id) => {
+                    handle_group_message(&state, &id, &device_id, packet, &group_id).await;
+                }
+                ChatMessageKind::Typing(session_id) => {
+                    handle_typing(&state, &id, &session_id).await;
+                }
+                ChatMessageKind::Receipt(session_id) => {
+                    handle_receipt(&state, &id, packet, &session_id).await;
                 }
-                _ => {
-                    let _ = tx.lock().await.send(packet);
+                ChatMessageKind::Event => {}
+            }
+        }
+    })
+}
+
+/// Apply the verdict of the rate limiter on a packet, the client is warned or held back
+/// Fails with the exceeded limit once the connection has to be closed
+async fn within_limits(
+    limiter: &mut RateLimiter,
+    outbox: &Outbox,
+    kind: RateLimitKind,
+    bytes: usize,
+) -> Result<(), RateLimitKind> {
+    match limiter.check(kind, bytes).await {
+        Verdict::Allow => {}
+        Verdict::Warn(event) => warn(outbox, ServerEvent::RateLimit(event)).await,
+        Verdict::Throttle(delay, event) => {
+            if let Some(event) = event {
+                warn(outbox, ServerEvent::RateLimit(event)).await;
+            }
+            tokio::time::sleep(delay).await;
+            limiter.take(kind, bytes).await;
+        }
+        Verdict::Disconnect(limit) => return Err(limit),
+    }
+    Ok(())
+}
+
+async fn warn(outbox: &Outbox, event: ServerEvent) {
+    if let Some(packet) = event_packet(&event) {
+        let _ = outbox
+            .queue
+            .send(Outgoing {
+                packet,
+                delivery: None,
+            })
+            .await;
+    }
+}
+
+/// Start the sweeper task
+/// This task periodically drops suspended sessions whose ticket has expired, expired invites,
+/// queued messages whose TTL has passed, history past its retention
+/// and the rate limits of IP addresses that disconnected
+/// It also reports the connections refused since the previous run
+pub async fn start_sweeper_task(state: Arc<ServerState>) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        let mut rejected = 0;
+        loop {
+            interval.tick().await;
+            if state.admission.rejected.total() != rejected {
+                rejected = state.admission.rejected.total();
+                println!("📊 Connections: {}", state.admission.report());
+            }
+            state.store.purge_sessions(unix_now()).await;
+            purge_expired_invites(&state).await;
+            state.ip_limits.purge_idle().await;
+            match state.store.purge_offline(unix_now()).await {
+                Ok(0) => {}
+                Ok(purged) => println!("🧹 Dropped {} expired queued messages", purged),
+                Err(err) => eprintln!("❌ Failed to purge queued messages: {}", err),
+            }
+            if let Some(history) = &state.config.history {
+                let stored_before = unix_now().saturating_sub(history.retention);
+                match state.store.purge_history(stored_before).await {
+                    Ok(0) => {}
+                    Ok(purged) => println!("🧹 Dropped {} messages past history retention", purged),
+                    Err(err) => eprintln!("❌ Failed to purge history: {}", err),
                 }
             }
         }
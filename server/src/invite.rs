@@ -0,0 +1,93 @@
+//! Group invite tokens.
+//!
+//! Members allowed to invite can issue a token for their group, optionally
+//! limited to a number of uses and a lifetime. The token is sealed with a
+//! server key so it can't be forged or pointed at another group, and is
+//! shared as a single URL-safe string. Anyone presenting a valid token with
+//! `join` becomes a member of the group.
+//! Invites and the sealing key are kept in the store, with SQLite they
+//! survive a restart.
+
+use crate::{
+    state::ServerState,
+    ticket::unix_now,
+    types::{Invite, InviteToken},
+};
+use base64::{Engine as _, engine::general_purpose::URL_SAFE_NO_PAD as b64};
+use common::{
+    types::{EncryptionConfig, SymmetricAlgo},
+    utils::enc::{decrypt_bytes, encrypt_bytes},
+};
+use uuid::Uuid;
+
+/// Purpose of the server key sealing invite tokens
+const INVITE_KEY: &str = "invite";
+
+async fn invite_encryption(
+    state: &ServerState,
+) -> Result<EncryptionConfig, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(EncryptionConfig {
+        algo: SymmetricAlgo::AES256,
+        encryption_key: Some(state.store.server_key(INVITE_KEY).await?),
+    })
+}
+
+/// Issue a new invite token for a group
+pub async fn issue_invite(
+    state: &ServerState,
+    group_id: &str,
+    max_uses: Option<u32>,
+    expires_at: Option<u64>,
+) -> Result<String, Box<dyn std::error::Error + Send + Sync>> {
+    let token = InviteToken {
+        invite_id: Uuid::new_v4().simple().to_string(),
+        group_id: group_id.to_string(),
+    };
+
+    let encoded = bincode::encode_to_vec(&token, bincode::config::standard())?;
+    let sealed = encrypt_bytes(&encoded, invite_encryption(state).await?)?;
+
+    let invite = Invite {
+        group_id: token.group_id,
+        uses_left: max_uses,
+        expires_at,
+    };
+    state.store.insert_invite(&token.invite_id, invite).await?;
+
+    Ok(b64.encode(sealed))
+}
+
+/// Open an invite token without redeeming it
+/// Returns the invite id and the group ID if the token is valid and the invite can still be used
+pub async fn open_invite(state: &ServerState, token: &str) -> Option<(String, String)> {
+    let sealed = b64.decode(token.trim()).ok()?;
+    let encoded = decrypt_bytes(&sealed, invite_encryption(state).await.ok()?).ok()?;
+    let (token, _): (InviteToken, usize) =
+        bincode::decode_from_slice(&encoded, bincode::config::standard()).ok()?;
+
+    let invite = state.store.get_invite(&token.invite_id, unix_now()).await?;
+    if invite.group_id != token.group_id {
+        return None;
+    }
+
+    Some((token.invite_id, token.group_id))
+}
+
+/// Use an invite once, the invite is dropped after its last use
+/// Returns `false` if it was used up or expired in the meantime
+pub async fn redeem_invite(state: &ServerState, invite_id: &str) -> bool {
+    match state.store.redeem_invite(invite_id, unix_now()).await {
+        Ok(redeemed) => redeemed,
+        Err(err) => {
+            eprintln!("❌ Failed to redeem invite: {}", err);
+            false
+        }
+    }
+}
+
+/// Drop expired and used up invites
+pub async fn purge_expired_invites(state: &ServerState) {
+    if let Err(err) = state.store.purge_invites(unix_now()).await {
+        eprintln!("❌ Failed to purge invites: {}", err);
+    }
+}
@@ -1,7 +1,13 @@
+pub mod admission;
 pub mod config;
-pub mod data;
 pub mod handlers;
+pub mod invite;
+pub mod limit;
+pub mod listener;
 pub mod net;
+pub mod state;
+pub mod store;
+pub mod ticket;
 pub mod types;
 
 pub use config::*;
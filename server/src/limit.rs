@@ -0,0 +1,241 @@
+//! Flood protection.
+//!
+//! Every connection gets token buckets for the messages, commands and
+//! payload bytes it sends, and the connections coming from the same IP
+//! address share another set. A packet arriving while a bucket is empty is
+//! a violation: the first ones are only answered with a warning event, the
+//! next ones are held until the buckets refill, and the connection is
+//! closed once it has too many. Violations are forgiven after `cooldown`
+//! seconds without one.
+
+use crate::{RateLimitConfig, RateLimits};
+use common::types::{RateLimitEvent, RateLimitKind};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::sync::Mutex as AsyncMutex;
+
+/// Tokens refilled at `rate` per second, up to `capacity`
+struct TokenBucket {
+    rate: f64,
+    capacity: f64,
+    tokens: f64,
+    updated: Instant,
+}
+
+impl TokenBucket {
+    fn new(rate: f64, burst: f64) -> Self {
+        let capacity = (rate * burst).max(1.0);
+        TokenBucket {
+            rate,
+            capacity,
+            tokens: capacity,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate).min(self.capacity);
+        self.updated = now;
+    }
+
+    /// How long until `cost` tokens are available, a rate of 0 disables the bucket
+    fn wait(&mut self, cost: f64) -> Duration {
+        if self.rate <= 0.0 {
+            return Duration::ZERO;
+        }
+        self.refill();
+        let missing = cost.min(self.capacity) - self.tokens;
+        match missing > 0.0 {
+            true => Duration::from_secs_f64(missing / self.rate),
+            false => Duration::ZERO,
+        }
+    }
+
+    /// Take `cost` tokens, the bucket goes into debt if there aren't enough
+    fn take(&mut self, cost: f64) {
+        if self.rate <= 0.0 {
+            return;
+        }
+        self.refill();
+        self.tokens -= cost.min(self.capacity);
+    }
+}
+
+/// Buckets of a connection or of an IP address
+pub struct Buckets {
+    messages: TokenBucket,
+    commands: TokenBucket,
+    bytes: TokenBucket,
+}
+
+impl Buckets {
+    pub fn new(limits: &RateLimits) -> Self {
+        Buckets {
+            messages: TokenBucket::new(limits.messages, limits.burst),
+            commands: TokenBucket::new(limits.commands, limits.burst),
+            bytes: TokenBucket::new(limits.bytes, limits.burst),
+        }
+    }
+
+    fn bucket(&mut self, kind: RateLimitKind) -> &mut TokenBucket {
+        match kind {
+            RateLimitKind::Messages => &mut self.messages,
+            RateLimitKind::Commands => &mut self.commands,
+            RateLimitKind::Bytes => &mut self.bytes,
+        }
+    }
+
+    /// How long until a packet of `kind` fits, and the limit holding it back
+    fn wait(&mut self, kind: RateLimitKind, bytes: usize) -> (Duration, RateLimitKind) {
+        let count = (self.bucket(kind).wait(1.0), kind);
+        let size = (self.bytes.wait(bytes as f64), RateLimitKind::Bytes);
+        match size.0 > count.0 {
+            true => size,
+            false => count,
+        }
+    }
+
+    fn take(&mut self, kind: RateLimitKind, bytes: usize) {
+        self.bucket(kind).take(1.0);
+        self.bytes.take(bytes as f64);
+    }
+}
+
+/// Buckets shared by the connections of an IP address
+pub type SharedBuckets = Arc<AsyncMutex<Buckets>>;
+
+/// Rate limit buckets shared by the connections of each IP address
+#[derive(Default)]
+pub struct IpLimits {
+    buckets: AsyncMutex<HashMap<IpAddr, SharedBuckets>>,
+}
+
+impl IpLimits {
+    /// Buckets of an IP address, created with `limits` for its first connection
+    async fn of(&self, ip: IpAddr, limits: &RateLimits) -> SharedBuckets {
+        self.buckets
+            .lock()
+            .await
+            .entry(ip.to_canonical())
+            .or_insert_with(|| Arc::new(AsyncMutex::new(Buckets::new(limits))))
+            .clone()
+    }
+
+    /// Drop the buckets of IP addresses without connections left
+    pub async fn purge_idle(&self) {
+        self.buckets
+            .lock()
+            .await
+            .retain(|_, buckets| Arc::strong_count(buckets) > 1);
+    }
+}
+
+/// What to do with a packet sent by a client
+pub enum Verdict {
+    /// The packet is within the limits
+    Allow,
+    /// The packet goes through, the client is warned
+    Warn(RateLimitEvent),
+    /// The packet is held for the given time, the client is told the first time
+    Throttle(Duration, Option<RateLimitEvent>),
+    /// The client went over the limits too many times
+    Disconnect(RateLimitKind),
+}
+
+/// Rate limits of a connection
+pub struct RateLimiter {
+    config: RateLimitConfig,
+    connection: Buckets,
+    ip: Option<SharedBuckets>,
+    violations: u32,
+    last_violation: Option<Instant>,
+}
+
+impl RateLimiter {
+    /// Limiter of a new connection, sharing the buckets of its IP address if it has one
+    pub async fn new(config: &RateLimitConfig, ip_limits: &IpLimits, ip: Option<IpAddr>) -> Self {
+        let ip = match ip {
+            Some(ip) => Some(ip_limits.of(ip, &config.ip).await),
+            None => None,
+        };
+
+        RateLimiter {
+            config: config.clone(),
+            connection: Buckets::new(&config.connection),
+            ip,
+            violations: 0,
+            last_violation: None,
+        }
+    }
+
+    /// Check a packet of `kind` with a payload of `bytes`
+    /// Its tokens are taken unless it is held back, see [`RateLimiter::take`]
+    pub async fn check(&mut self, kind: RateLimitKind, bytes: usize) -> Verdict {
+        let mut ip = match &self.ip {
+            Some(buckets) => Some(buckets.lock().await),
+            None => None,
+        };
+
+        let mut wait = self.connection.wait(kind, bytes);
+        if let Some(ip) = ip.as_mut() {
+            let ip_wait = ip.wait(kind, bytes);
+            if ip_wait.0 > wait.0 {
+                wait = ip_wait;
+            }
+        }
+        let (delay, limit) = wait;
+        if delay.is_zero() {
+            self.connection.take(kind, bytes);
+            if let Some(ip) = ip.as_mut() {
+                ip.take(kind, bytes);
+            }
+            return Verdict::Allow;
+        }
+
+        let now = Instant::now();
+        if self
+            .last_violation
+            .is_some_and(|last| now.duration_since(last).as_secs() >= self.config.cooldown)
+        {
+            self.violations = 0;
+        }
+        self.violations += 1;
+        self.last_violation = Some(now);
+
+        if self.violations > self.config.disconnect_after {
+            return Verdict::Disconnect(limit);
+        }
+        let event = RateLimitEvent {
+            limit,
+            throttled: self.violations > self.config.warnings,
+            remaining: self.config.disconnect_after - self.violations,
+        };
+        match event.throttled {
+            false => {
+                self.connection.take(kind, bytes);
+                if let Some(ip) = ip.as_mut() {
+                    ip.take(kind, bytes);
+                }
+                Verdict::Warn(event)
+            }
+            true => {
+                let first = self.violations == self.config.warnings + 1;
+                Verdict::Throttle(delay, first.then_some(event))
+            }
+        }
+    }
+
+    /// Take the tokens of a packet once it was held back
+    pub async fn take(&mut self, kind: RateLimitKind, bytes: usize) {
+        self.connection.take(kind, bytes);
+        if let Some(ip) = &self.ip {
+            ip.lock().await.take(kind, bytes);
+        }
+    }
+}
@@ -0,0 +1,275 @@
+//! Listeners accepting client connections.
+//! Every listener hands its connections to [`handle_client`] as an [`AsyncStream`],
+//! along with the [`Permit`](crate::admission::Permit) they were admitted with.
+
+use crate::{
+    ConnectionConfig, ListenAddr,
+    admission::{Admission, Rejection},
+    handlers::handle_client,
+    net::create_quic_endpoint,
+    state::ServerState,
+};
+use common::{net::AsyncStream, quic::QuicStream, ws::WsStream};
+use quinn::{Endpoint, Incoming};
+use std::sync::Arc;
+#[cfg(unix)]
+use std::{
+    fs::Permissions,
+    os::unix::fs::{FileTypeExt, PermissionsExt},
+    path::PathBuf,
+};
+#[cfg(unix)]
+use tokio::net::UnixListener;
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpListener,
+};
+use tokio_rustls::TlsAcceptor;
+use tokio_tungstenite::tungstenite::protocol::WebSocketConfig;
+
+/// Run the listener for the given address until it fails
+/// `acceptor` must be set for listeners requiring TLS
+pub async fn run_listener(
+    listen: ListenAddr,
+    acceptor: Option<TlsAcceptor>,
+    state: Arc<ServerState>,
+) {
+    match listen {
+        #[cfg(unix)]
+        ListenAddr::Unix(path) => run_unix_listener(path, state).await,
+        #[cfg(not(unix))]
+        ListenAddr::Unix(path) => eprintln!(
+            "Failed to bind {}: Unix domain sockets are not supported on this platform",
+            path.display()
+        ),
+        ListenAddr::Tcp(addr) => run_tcp_listener(addr, None, state).await,
+        ListenAddr::Tls(addr) => run_tcp_listener(addr, acceptor, state).await,
+        ListenAddr::Ws(addr) => run_ws_listener(addr, None, state).await,
+        ListenAddr::Wss(addr) => run_ws_listener(addr, acceptor, state).await,
+        ListenAddr::Quic(addr) => {
+            let Some(tls_cfg) = &state.config.tls else {
+                eprintln!("QUIC requires TLS, add a [tls] section to the configuration");
+                return;
+            };
+            match create_quic_endpoint(tls_cfg, &addr) {
+                Ok(endpoint) => run_quic_listener(endpoint, state).await,
+                Err(e) => eprintln!("Failed to bind QUIC endpoint {}: {:?}", addr, e),
+            }
+        }
+    }
+}
+
+/// Accept connections on a Unix domain socket, only the user running the server can connect
+/// A socket file left behind by a previous run is replaced, one a server still answers on is not
+#[cfg(unix)]
+pub async fn run_unix_listener(path: PathBuf, state: Arc<ServerState>) {
+    if let Ok(meta) = std::fs::symlink_metadata(&path)
+        && meta.file_type().is_socket()
+    {
+        if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+            eprintln!(
+                "Failed to bind {}: another server is listening on it",
+                path.display()
+            );
+            return;
+        }
+        let _ = std::fs::remove_file(&path);
+    }
+
+    let listener = match UnixListener::bind(&path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to bind {}: {:?}", path.display(), e);
+            return;
+        }
+    };
+    if let Err(e) = std::fs::set_permissions(&path, Permissions::from_mode(0o600)) {
+        eprintln!("Failed to restrict {}: {:?}", path.display(), e);
+        return;
+    }
+    println!("🔌 Unix socket Server listening on {}", path.display());
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                let Ok(permit) = Admission::admit(&state, None) else {
+                    continue;
+                };
+                let state = state.clone();
+
+                tokio::spawn(async move {
+                    handle_client(Box::new(stream), true, permit, state).await;
+                });
+            }
+            Err(e) => eprintln!("Failed to accept connection: {:?}", e),
+        }
+    }
+}
+
+/// Accept TCP connections, wrapped in TLS if an acceptor is given
+pub async fn run_tcp_listener(
+    addr: String,
+    acceptor: Option<TlsAcceptor>,
+    state: Arc<ServerState>,
+) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to bind {}: {:?}", addr, e);
+            return;
+        }
+    };
+    match acceptor {
+        Some(_) => println!("🔒 TLS Server listening on {}", &addr),
+        None => println!("🚀 Plain TCP Server listening on {}", &addr),
+    }
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let Ok(permit) = Admission::admit(&state, Some(peer.ip())) else {
+                    continue;
+                };
+                let acceptor = acceptor.clone();
+                let state = state.clone();
+
+                tokio::spawn(async move {
+                    match acceptor {
+                        Some(acceptor) => {
+                            match permit.before_deadline(acceptor.accept(stream)).await {
+                                Some(Ok(tls_stream)) => {
+                                    handle_client(Box::new(tls_stream), true, permit, state).await
+                                }
+                                Some(Err(e)) => {
+                                    permit.reject(Rejection::HandshakeFailed);
+                                    eprintln!("TLS handshake failed: {:?}", e);
+                                }
+                                None => {}
+                            }
+                        }
+                        None => handle_client(Box::new(stream), false, permit, state).await,
+                    }
+                });
+            }
+            Err(e) => eprintln!("Failed to accept connection: {:?}", e),
+        }
+    }
+}
+
+/// Accept WebSocket connections, over TLS (wss) if an acceptor is given
+pub async fn run_ws_listener(addr: String, acceptor: Option<TlsAcceptor>, state: Arc<ServerState>) {
+    let listener = match TcpListener::bind(&addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Failed to bind {}: {:?}", addr, e);
+            return;
+        }
+    };
+    match acceptor {
+        Some(_) => println!("🔒 WebSocket (wss) Server listening on {}", &addr),
+        None => println!("🌐 WebSocket (ws) Server listening on {}", &addr),
+    }
+
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                let Ok(permit) = Admission::admit(&state, Some(peer.ip())) else {
+                    continue;
+                };
+                let acceptor = acceptor.clone();
+                let secure = acceptor.is_some();
+                let state = state.clone();
+
+                tokio::spawn(async move {
+                    let upgrade = async {
+                        match acceptor {
+                            Some(acceptor) => match acceptor.accept(stream).await {
+                                Ok(tls_stream) => {
+                                    accept_websocket(tls_stream, &state.config.connections).await
+                                }
+                                Err(e) => {
+                                    eprintln!("TLS handshake failed: {:?}", e);
+                                    None
+                                }
+                            },
+                            None => accept_websocket(stream, &state.config.connections).await,
+                        }
+                    };
+
+                    match permit.before_deadline(upgrade).await {
+                        Some(Some(stream)) => handle_client(stream, secure, permit, state).await,
+                        Some(None) => permit.reject(Rejection::HandshakeFailed),
+                        None => {}
+                    }
+                });
+            }
+            Err(e) => eprintln!("Failed to accept connection: {:?}", e),
+        }
+    }
+}
+
+/// Accept QUIC connections on a bound endpoint
+/// The first bidirectional stream opened by the client carries the session
+pub async fn run_quic_listener(endpoint: Endpoint, state: Arc<ServerState>) {
+    match endpoint.local_addr() {
+        Ok(addr) => println!("⚡ QUIC Server listening on {}", addr),
+        Err(e) => {
+            eprintln!("Failed to get QUIC listener address: {:?}", e);
+            return;
+        }
+    }
+
+    while let Some(incoming) = endpoint.accept().await {
+        let Ok(permit) = Admission::admit(&state, Some(incoming.remote_address().ip())) else {
+            incoming.refuse();
+            continue;
+        };
+        let state = state.clone();
+
+        tokio::spawn(async move {
+            match permit.before_deadline(accept_quic(incoming)).await {
+                Some(Some(stream)) => handle_client(stream, true, permit, state).await,
+                Some(None) => permit.reject(Rejection::HandshakeFailed),
+                None => {}
+            }
+        });
+    }
+}
+
+/// Complete the QUIC handshake and wait for the client to open its stream
+async fn accept_quic(incoming: Incoming) -> Option<Box<dyn AsyncStream>> {
+    let connection = match incoming.await {
+        Ok(connection) => connection,
+        Err(e) => {
+            eprintln!("QUIC handshake failed: {:?}", e);
+            return None;
+        }
+    };
+
+    match connection.accept_bi().await {
+        Ok((send, recv)) => Some(Box::new(QuicStream::new(connection, send, recv))),
+        Err(e) => {
+            eprintln!("Failed to accept QUIC stream: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Perform the WebSocket upgrade on an accepted stream
+/// Messages are bounded by the longest frame read from a client, plus its length prefix
+async fn accept_websocket<S>(stream: S, config: &ConnectionConfig) -> Option<Box<dyn AsyncStream>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let max_len = config.max_frame.max(config.max_handshake_frame) + 4;
+    let ws_config = WebSocketConfig::default()
+        .max_message_size(Some(max_len))
+        .max_frame_size(Some(max_len));
+    match tokio_tungstenite::accept_async_with_config(stream, Some(ws_config)).await {
+        Ok(ws) => Some(Box::new(WsStream::new(ws))),
+        Err(e) => {
+            eprintln!("WebSocket handshake failed: {:?}", e);
+            None
+        }
+    }
+}
@@ -1,14 +1,14 @@
-use common::net::Packet;
 use null_talk_server::{
     ServerConfig,
-    handlers::{handle_client, task::start_writer_task},
+    admission::Admission,
+    handlers::task::start_sweeper_task,
+    limit::IpLimits,
+    listener::run_listener,
     net::create_tls_acceptor,
+    state::{DeliveryLocks, ServerState},
+    store::open_store,
 };
 use std::sync::Arc;
-use tokio::{
-    net::TcpListener,
-    sync::{Mutex as AsyncMutex, mpsc},
-};
 
 /// Main entry point for the server
 #[tokio::main]
@@ -22,53 +22,48 @@ async fn main() {
     };
     println!("🔧 Configuration Loaded");
 
-    let server_address = config.get_addr();
+    let listeners = match config.listeners() {
+        Ok(listeners) => listeners,
+        Err(e) => {
+            eprintln!("Invalid listener configuration: {}", e);
+            return;
+        }
+    };
+    let store = match open_store(&config.storage).await {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!("Failed to open storage: {:?}", e);
+            return;
+        }
+    };
 
-    // Shared channel for communication
-    let (tx, rx) = mpsc::unbounded_channel::<Packet>();
-    let _ = start_writer_task(rx).await;
-    let sender: Arc<AsyncMutex<mpsc::UnboundedSender<Packet>>> = Arc::new(AsyncMutex::new(tx));
+    let state = Arc::new(ServerState {
+        store,
+        config: Arc::new(config),
+        admission: Admission::default(),
+        ip_limits: IpLimits::default(),
+        delivery: DeliveryLocks::default(),
+    });
+    start_sweeper_task(state.clone()).await;
 
     // TLS check
-    if let Some(tls_cfg) = &config.tls {
-        let acceptor = match create_tls_acceptor(tls_cfg).await {
-            Ok(acceptor) => acceptor,
+    let acceptor = match &state.config.tls {
+        Some(tls_cfg) => match create_tls_acceptor(tls_cfg).await {
+            Ok(acceptor) => Some(acceptor),
             Err(e) => {
                 eprintln!("Failed to create TLS acceptor: {:?}", e);
                 return;
             }
-        };
-        let listener = TcpListener::bind(&server_address).await.unwrap();
-        println!("🔒 TLS Server listening on {}", &server_address);
-
-        loop {
-            match listener.accept().await {
-                Ok((stream, _)) => {
-                    let acceptor = acceptor.clone();
-                    let sd_clone = sender.clone();
-
-                    tokio::spawn(async move {
-                        match acceptor.accept(stream).await {
-                            Ok(tls_stream) => handle_client(Box::new(tls_stream), sd_clone).await,
-                            Err(e) => eprintln!("TLS handshake failed: {:?}", e),
-                        }
-                    });
-                }
-                Err(e) => eprintln!("Failed to accept connection: {:?}", e),
-            }
-        }
-    } else {
-        let listener = TcpListener::bind(&server_address).await.unwrap();
-        println!("🚀 Plain TCP Server listening on {}", &server_address);
+        },
+        None => None,
+    };
 
-        loop {
-            match listener.accept().await {
-                Ok((stream, _)) => {
-                    let sd_clone = sender.clone();
-                    tokio::spawn(async move { handle_client(Box::new(stream), sd_clone).await });
-                }
-                Err(e) => eprintln!("Failed to accept connection: {:?}", e),
-            }
-        }
+    // Every listener runs until it fails, the server stops once all of them did
+    let handles: Vec<_> = listeners
+        .into_iter()
+        .map(|listen| tokio::spawn(run_listener(listen, acceptor.clone(), state.clone())))
+        .collect();
+    for handle in handles {
+        let _ = handle.await;
     }
 }
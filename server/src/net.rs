@@ -1,25 +1,43 @@
-use crate::TLSConfig;
+use crate::{
+    TLSConfig,
+    state::ServerState,
+    ticket::{issue_ticket, redeem_ticket},
+    types::HandshakeOutcome,
+};
 use common::{
-    net::{HandshakePacket, StreamReader, StreamWriter},
+    net::{HandshakePacket, MAX_DEVICE_ID_LEN, StreamReader, StreamWriter},
+    quic::QUIC_ALPN,
+    types::Compression,
     utils::{
-        enc::{generate_session_data, parse_public_key, verify_nonce_signature},
-        net::{close_connection, read_packet, write_packet},
+        enc::{
+            generate_session_data, parse_public_key, public_key_to_user_id, verify_nonce_signature,
+        },
+        net::{close_connection, read_packet_limited, write_packet},
     },
 };
-use rsa::RsaPublicKey;
+use quinn::{Endpoint, crypto::rustls::QuicServerConfig};
 use rustls::ServerConfig;
 use rustls::pki_types::{CertificateDer, PrivateKeyDer};
-use std::{fs::File, io::BufReader, sync::Arc};
+use std::{fs::File, io::BufReader, net::SocketAddr, sync::Arc};
 use tokio_rustls::TlsAcceptor;
 
 /// Perform the handshake process with the client
+/// If the client presents a valid resumption ticket the signature verification is skipped
+/// Tickets are only accepted and handed out on `secure` transports, they can't be sniffed there
+/// Frame compression is negotiated from the algorithms offered by the client
+/// Revoked devices are rejected once the client is authenticated
+/// Frames are bounded by `max_handshake_frame`, the client isn't trusted yet
 pub async fn perform_handshake(
     rd: StreamReader,
     wt: StreamWriter,
-) -> Result<(String, Vec<u8>, RsaPublicKey), Box<dyn std::error::Error + Send + Sync>> {
+    state: &ServerState,
+    secure: bool,
+) -> Result<HandshakeOutcome, Box<dyn std::error::Error + Send + Sync>> {
+    let max_frame = state.config.connections.max_handshake_frame;
+
     // Step: 0
-    // Receive handshake packet from client with username and public_key
-    let packet: HandshakePacket = read_packet(rd.clone()).await?;
+    // Receive handshake packet from client with username, public_key and optional ticket
+    let packet: HandshakePacket = read_packet_limited(rd.clone(), max_frame).await?;
     if packet.step != 0 {
         let _ = close_connection(wt.clone(), "Invalid handshake step").await;
         return Err("Invalid handshake step".into());
@@ -32,41 +50,75 @@ pub async fn perform_handshake(
         Some(key) => parse_public_key(&key).map_err(|_| "❗️Failed to parse public key")?,
         None => return Err("❗️Missing Public Key".into()),
     };
+    let user_id = public_key_to_user_id(&public_key);
+    let device_id = packet.device_id.unwrap_or_else(|| "default".to_string());
+    if device_id.is_empty() || device_id.len() > MAX_DEVICE_ID_LEN {
+        return Err("❗️Invalid device id".into());
+    }
+
+    // Pick the first of our algorithms the client supports
+    let offered = packet.compression.unwrap_or_default();
+    let compression = state.config.compression.as_ref().and_then(|cfg| {
+        cfg.algorithms
+            .iter()
+            .find(|algo| offered.contains(algo))
+            .map(|algo| Compression {
+                algo: *algo,
+                threshold: cfg.threshold,
+            })
+    });
 
-    // Step: 1
     // Generate session data (nonce & session_key)
-    // Send handshake packet from server with nonce
     let (session_key, nonce) = generate_session_data();
 
-    let packet = HandshakePacket {
-        step: 1,
-        username: None,
-        public_key: None,
-        nonce: Some(nonce.clone()),
-        signature: None,
-        session_key: None,
+    let resumed = match packet.ticket {
+        Some(ticket) if secure => redeem_ticket(state, &ticket, &user_id).await,
+        _ => None,
     };
-    write_packet(wt.clone(), packet).await?;
 
-    // Step: 2
-    // Receive signature and verify it
-    let packet: HandshakePacket = read_packet(rd.clone()).await?;
-    if packet.step != 2 {
-        let _ = close_connection(wt.clone(), "Invalid handshake step").await;
-        return Err("Invalid handshake step".into());
+    // Steps 1 & 2 are skipped when the session is resumed
+    if resumed.is_none() {
+        // Step: 1
+        // Send handshake packet from server with nonce
+        let packet = HandshakePacket {
+            step: 1,
+            username: None,
+            public_key: None,
+            nonce: Some(nonce.clone()),
+            signature: None,
+            session_key: None,
+            ticket: None,
+            compression: None,
+            device_id: None,
+        };
+        write_packet(wt.clone(), packet).await?;
+
+        // Step: 2
+        // Receive signature and verify it
+        let packet: HandshakePacket = read_packet_limited(rd.clone(), max_frame).await?;
+        if packet.step != 2 {
+            let _ = close_connection(wt.clone(), "Invalid handshake step").await;
+            return Err("Invalid handshake step".into());
+        }
+        let signature = match packet.signature {
+            Some(signature) => signature,
+            None => return Err("❗️Missing signature".into()),
+        };
+
+        if !verify_nonce_signature(&public_key, &nonce, &signature) {
+            let _ = close_connection(wt.clone(), "Invalid signature!").await;
+            return Err("Invalid signature!".into());
+        }
     }
-    let signature = match packet.signature {
-        Some(signature) => signature,
-        None => return Err("❗️Missing signature".into()),
-    };
 
-    if !verify_nonce_signature(&public_key, &nonce, &signature) {
-        let _ = close_connection(wt.clone(), "Invalid signature!").await;
-        return Err("Invalid signature!".into());
+    if state.store.is_device_revoked(&user_id, &device_id).await {
+        return Err("❗️Device revoked".into());
     }
 
     // Step: 3
-    // Send Session Key After Successful Verification
+    // Send Session Key and a new resumption ticket after successful verification
+    // The ticket id still identifies the connection when the ticket isn't sent
+    let (ticket_id, ticket) = issue_ticket(state, &user_id).await?;
     let packet = HandshakePacket {
         step: 3,
         username: None,
@@ -74,15 +126,30 @@ pub async fn perform_handshake(
         nonce: None,
         signature: None,
         session_key: Some(session_key.clone()),
+        ticket: secure.then_some(ticket),
+        compression: compression.map(|c| vec![c.algo]),
+        device_id: Some(device_id.clone()),
     };
     write_packet(wt.clone(), packet).await?;
 
-    Ok((user_name, session_key, public_key))
+    // Every frame after the handshake may be compressed
+    wt.lock().await.compression = compression;
+    rd.lock().await.compression = compression.map(|c| c.algo);
+
+    Ok(HandshakeOutcome {
+        username: user_name,
+        session_key,
+        public_key,
+        device_id,
+        ticket_id,
+        resumed,
+    })
 }
 
-pub async fn create_tls_acceptor(
+/// Load the certificate chain and private key into a rustls configuration
+fn load_tls_config(
     tls_config: &TLSConfig,
-) -> Result<TlsAcceptor, Box<dyn std::error::Error + Send + Sync>> {
+) -> Result<ServerConfig, Box<dyn std::error::Error + Send + Sync>> {
     // Load the certificate chain from `fullchain.pem`
     let cert_file = match File::open(&tls_config.cert_path) {
         Ok(file) => file,
@@ -111,8 +178,31 @@ pub async fn create_tls_acceptor(
         .with_single_cert(certs, key_pkcs8)
         .expect("Failed to create server config");
 
+    Ok(config)
+}
+
+pub async fn create_tls_acceptor(
+    tls_config: &TLSConfig,
+) -> Result<TlsAcceptor, Box<dyn std::error::Error + Send + Sync>> {
+    let config = load_tls_config(tls_config)?;
+
     // Create the async TLS acceptor
     let acceptor = TlsAcceptor::from(Arc::new(config));
 
     Ok(acceptor)
 }
+
+/// Bind a QUIC endpoint on the given address, using the TLS certificate of the server
+pub fn create_quic_endpoint(
+    tls_config: &TLSConfig,
+    addr: &str,
+) -> Result<Endpoint, Box<dyn std::error::Error + Send + Sync>> {
+    let mut config = load_tls_config(tls_config)?;
+    config.alpn_protocols = vec![QUIC_ALPN.to_vec()];
+
+    let crypto = QuicServerConfig::try_from(config)?;
+    let server_config = quinn::ServerConfig::with_crypto(Arc::new(crypto));
+    let addr: SocketAddr = addr.parse()?;
+
+    Ok(Endpoint::server(server_config, addr)?)
+}
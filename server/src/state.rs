@@ -0,0 +1,47 @@
+use crate::{ServerConfig, admission::Admission, limit::IpLimits, store::Store};
+use std::{
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    sync::Arc,
+};
+use tokio::sync::Mutex as AsyncMutex;
+
+/// Shared state handed to every listener and handler
+pub struct ServerState {
+    /// Clients, DM sessions and groups
+    pub store: Arc<dyn Store>,
+    /// Configuration the server was started with
+    pub config: Arc<ServerConfig>,
+    /// Connections open and refused
+    pub admission: Admission,
+    /// Rate limit buckets of the IP addresses connected
+    pub ip_limits: IpLimits,
+    /// Delivery to a user against its devices connecting
+    pub delivery: DeliveryLocks,
+}
+
+/// Number of locks the users are spread over
+const DELIVERY_LOCKS: usize = 64;
+
+/// Locks held while a packet is delivered to a user, and while a device of the user
+/// registers and takes its offline queue, so nothing is queued once the device is online
+pub struct DeliveryLocks {
+    locks: Vec<AsyncMutex<()>>,
+}
+
+impl DeliveryLocks {
+    /// Lock of a user, users share the locks by hash of their id
+    pub fn of(&self, user_id: &str) -> &AsyncMutex<()> {
+        let mut hasher = DefaultHasher::new();
+        user_id.hash(&mut hasher);
+        &self.locks[hasher.finish() as usize % self.locks.len()]
+    }
+}
+
+impl Default for DeliveryLocks {
+    fn default() -> Self {
+        Self {
+            locks: (0..DELIVERY_LOCKS).map(|_| AsyncMutex::new(())).collect(),
+        }
+    }
+}
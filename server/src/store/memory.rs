@@ -0,0 +1,550 @@
+use super::{Store, StoreResult};
+use crate::{
+    ticket::unix_now,
+    types::{
+        Client, DmChat, GroupChange, GroupChat, HandleEntry, HistoryEntry, Invite, QueuedPacket,
+        SuspendedSession, UserStatus,
+    },
+};
+use async_trait::async_trait;
+use common::{types::HistoryCursor, utils::enc::generate_session_data};
+use std::collections::{HashMap, HashSet, VecDeque};
+use tokio::sync::Mutex as AsyncMutex;
+
+/// Store keeping every client, DM session, group, queued message and history entry in memory
+#[derive(Default)]
+pub struct MemoryStore {
+    /// Connected devices, keyed by user id then device id
+    clients: AsyncMutex<HashMap<String, HashMap<String, Client>>>,
+    /// Memberships of disconnected devices, keyed by ticket id
+    sessions: AsyncMutex<HashMap<String, SuspendedSession>>,
+    /// Revoked `(user_id, device_id)` pairs
+    revoked: AsyncMutex<HashSet<(String, String)>>,
+    /// Blocked `(user_id, blocked_id)` pairs
+    blocked: AsyncMutex<HashSet<(String, String)>>,
+    /// Presence and status line of every user that set one
+    statuses: AsyncMutex<HashMap<String, UserStatus>>,
+    handles: AsyncMutex<Handles>,
+    dms: AsyncMutex<HashMap<String, DmChat>>,
+    groups: AsyncMutex<HashMap<String, GroupChat>>,
+    /// Invites that can still be redeemed, keyed by invite id
+    invites: AsyncMutex<HashMap<String, Invite>>,
+    /// Keys of the server, keyed by purpose
+    keys: AsyncMutex<HashMap<String, Vec<u8>>>,
+    offline: AsyncMutex<HashMap<String, VecDeque<QueuedPacket>>>,
+    history: AsyncMutex<History>,
+}
+
+/// Registered handles, along with the handle of every user
+#[derive(Default)]
+struct Handles {
+    entries: HashMap<String, HandleEntry>,
+    by_user: HashMap<String, String>,
+}
+
+/// History entries of every chat, along with the id of the next entry
+#[derive(Default)]
+struct History {
+    chats: HashMap<String, Vec<HistoryEntry>>,
+    next_id: u64,
+}
+
+impl MemoryStore {
+    /// Create a store holding the given DM sessions, groups, invites, revoked devices, blocked users and handles
+    pub fn with_chats(
+        dms: Vec<DmChat>,
+        groups: Vec<GroupChat>,
+        invites: Vec<(String, Invite)>,
+        revoked: Vec<(String, String)>,
+        blocked: Vec<(String, String)>,
+        handles: Vec<HandleEntry>,
+    ) -> Self {
+        MemoryStore {
+            clients: AsyncMutex::new(HashMap::new()),
+            sessions: AsyncMutex::new(HashMap::new()),
+            revoked: AsyncMutex::new(revoked.into_iter().collect()),
+            blocked: AsyncMutex::new(blocked.into_iter().collect()),
+            statuses: AsyncMutex::new(HashMap::new()),
+            handles: AsyncMutex::new(Handles {
+                by_user: handles
+                    .iter()
+                    .map(|entry| (entry.user_id.clone(), entry.handle.clone()))
+                    .collect(),
+                entries: handles
+                    .into_iter()
+                    .map(|entry| (entry.handle.clone(), entry))
+                    .collect(),
+            }),
+            dms: AsyncMutex::new(dms.into_iter().map(|dm| (dm.dm_id.clone(), dm)).collect()),
+            groups: AsyncMutex::new(
+                groups
+                    .into_iter()
+                    .map(|group| (group.group_id.clone(), group))
+                    .collect(),
+            ),
+            invites: AsyncMutex::new(invites.into_iter().collect()),
+            keys: AsyncMutex::new(HashMap::new()),
+            offline: AsyncMutex::new(HashMap::new()),
+            history: AsyncMutex::new(History::default()),
+        }
+    }
+}
+
+#[async_trait]
+impl Store for MemoryStore {
+    async fn add_client(&self, client: Client) -> Option<Client> {
+        self.clients
+            .lock()
+            .await
+            .entry(client.user_id.clone())
+            .or_default()
+            .insert(client.device_id.clone(), client)
+    }
+
+    async fn get_client(&self, user_id: &str, device_id: &str) -> Option<Client> {
+        self.clients
+            .lock()
+            .await
+            .get(user_id)
+            .and_then(|devices| devices.get(device_id))
+            .cloned()
+    }
+
+    async fn get_devices(&self, user_id: &str) -> Vec<Client> {
+        match self.clients.lock().await.get(user_id) {
+            Some(devices) => devices.values().cloned().collect(),
+            None => Vec::new(),
+        }
+    }
+
+    async fn remove_client(
+        &self,
+        user_id: &str,
+        device_id: &str,
+        ticket_id: &str,
+    ) -> Option<Client> {
+        let mut clients = self.clients.lock().await;
+        let devices = clients.get_mut(user_id)?;
+        if devices.get(device_id)?.ticket_id != ticket_id {
+            return None;
+        }
+
+        let client = devices.remove(device_id);
+        if devices.is_empty() {
+            clients.remove(user_id);
+        }
+        client
+    }
+
+    async fn set_client_chats(
+        &self,
+        user_id: &str,
+        device_id: &str,
+        dms: Vec<String>,
+        groups: Vec<String>,
+    ) {
+        if let Some(client) = self
+            .clients
+            .lock()
+            .await
+            .get_mut(user_id)
+            .and_then(|devices| devices.get_mut(device_id))
+        {
+            client.dms = dms;
+            client.groups = groups;
+        }
+    }
+
+    async fn add_client_dm(&self, user_id: &str, dm_id: &str) {
+        if let Some(devices) = self.clients.lock().await.get_mut(user_id) {
+            for client in devices.values_mut() {
+                if !client.dms.iter().any(|id| id == dm_id) {
+                    client.dms.push(dm_id.to_string());
+                }
+            }
+        }
+    }
+
+    async fn add_client_group(&self, user_id: &str, group_id: &str) {
+        if let Some(devices) = self.clients.lock().await.get_mut(user_id) {
+            for client in devices.values_mut() {
+                if !client.groups.iter().any(|id| id == group_id) {
+                    client.groups.push(group_id.to_string());
+                }
+            }
+        }
+    }
+
+    async fn remove_client_group(&self, user_id: &str, group_id: &str) {
+        if let Some(devices) = self.clients.lock().await.get_mut(user_id) {
+            for client in devices.values_mut() {
+                client.groups.retain(|id| id != group_id);
+            }
+        }
+    }
+
+    async fn suspend_session(&self, ticket_id: &str, session: SuspendedSession) {
+        self.sessions
+            .lock()
+            .await
+            .insert(ticket_id.to_string(), session);
+    }
+
+    async fn take_session(&self, ticket_id: &str) -> Option<SuspendedSession> {
+        self.sessions.lock().await.remove(ticket_id)
+    }
+
+    async fn purge_sessions(&self, now: u64) {
+        self.sessions
+            .lock()
+            .await
+            .retain(|_, session| session.expires_at > now);
+    }
+
+    async fn revoke_device(&self, user_id: &str, device_id: &str) -> StoreResult<()> {
+        self.revoked
+            .lock()
+            .await
+            .insert((user_id.to_string(), device_id.to_string()));
+        Ok(())
+    }
+
+    async fn is_device_revoked(&self, user_id: &str, device_id: &str) -> bool {
+        self.revoked
+            .lock()
+            .await
+            .contains(&(user_id.to_string(), device_id.to_string()))
+    }
+
+    async fn block_user(&self, user_id: &str, blocked_id: &str) -> StoreResult<()> {
+        self.blocked
+            .lock()
+            .await
+            .insert((user_id.to_string(), blocked_id.to_string()));
+        Ok(())
+    }
+
+    async fn unblock_user(&self, user_id: &str, blocked_id: &str) -> StoreResult<bool> {
+        Ok(self
+            .blocked
+            .lock()
+            .await
+            .remove(&(user_id.to_string(), blocked_id.to_string())))
+    }
+
+    async fn is_blocked(&self, user_id: &str, blocked_id: &str) -> bool {
+        self.blocked
+            .lock()
+            .await
+            .contains(&(user_id.to_string(), blocked_id.to_string()))
+    }
+
+    async fn get_blocked(&self, user_id: &str) -> Vec<String> {
+        self.blocked
+            .lock()
+            .await
+            .iter()
+            .filter(|(blocker, _)| blocker == user_id)
+            .map(|(_, blocked)| blocked.clone())
+            .collect()
+    }
+
+    async fn set_status(&self, user_id: &str, status: UserStatus) {
+        self.statuses
+            .lock()
+            .await
+            .insert(user_id.to_string(), status);
+    }
+
+    async fn get_status(&self, user_id: &str) -> UserStatus {
+        self.statuses
+            .lock()
+            .await
+            .get(user_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    async fn claim_handle(&self, entry: HandleEntry) -> StoreResult<bool> {
+        let mut handles = self.handles.lock().await;
+        if let Some(owner) = handles.entries.get(&entry.handle) {
+            return Ok(owner.user_id == entry.user_id);
+        }
+
+        if let Some(previous) = handles.by_user.remove(&entry.user_id) {
+            handles.entries.remove(&previous);
+        }
+        handles
+            .by_user
+            .insert(entry.user_id.clone(), entry.handle.clone());
+        handles.entries.insert(entry.handle.clone(), entry);
+        Ok(true)
+    }
+
+    async fn get_handle(&self, handle: &str) -> Option<HandleEntry> {
+        self.handles.lock().await.entries.get(handle).cloned()
+    }
+
+    async fn handle_of(&self, user_id: &str) -> Option<String> {
+        self.handles.lock().await.by_user.get(user_id).cloned()
+    }
+
+    async fn get_user_chats(&self, user_id: &str) -> (Vec<DmChat>, Vec<GroupChat>) {
+        let dms = self
+            .dms
+            .lock()
+            .await
+            .values()
+            .filter(|dm| dm.members.contains_key(user_id))
+            .cloned()
+            .collect();
+        let groups = self
+            .groups
+            .lock()
+            .await
+            .values()
+            .filter(|group| group.members.contains_key(user_id))
+            .cloned()
+            .collect();
+        (dms, groups)
+    }
+
+    async fn get_dm(&self, dm_id: &str) -> Option<DmChat> {
+        self.dms.lock().await.get(dm_id).cloned()
+    }
+
+    async fn insert_dm(&self, dm: DmChat) -> StoreResult<DmChat> {
+        let mut dms = self.dms.lock().await;
+        Ok(dms.entry(dm.dm_id.clone()).or_insert(dm).clone())
+    }
+
+    async fn set_dm_member_active(&self, dm_id: &str, user_id: &str, active: bool) -> bool {
+        match self
+            .dms
+            .lock()
+            .await
+            .get_mut(dm_id)
+            .and_then(|dm| dm.members.get_mut(user_id))
+        {
+            Some(is_active) => {
+                *is_active = active;
+                true
+            }
+            None => false,
+        }
+    }
+
+    async fn accept_dm(&self, dm_id: &str) -> StoreResult<Option<DmChat>> {
+        Ok(self.dms.lock().await.get_mut(dm_id).map(|dm| {
+            dm.requester = None;
+            dm.clone()
+        }))
+    }
+
+    async fn delete_dm(&self, dm_id: &str) -> StoreResult<Option<DmChat>> {
+        let dm = self.dms.lock().await.remove(dm_id);
+        if dm.is_some() {
+            self.history.lock().await.chats.remove(dm_id);
+        }
+        Ok(dm)
+    }
+
+    async fn get_group(&self, group_id: &str) -> Option<GroupChat> {
+        self.groups.lock().await.get(group_id).cloned()
+    }
+
+    async fn insert_group(&self, group: GroupChat) -> StoreResult<GroupChat> {
+        let mut groups = self.groups.lock().await;
+        Ok(groups
+            .entry(group.group_id.clone())
+            .or_insert(group)
+            .clone())
+    }
+
+    async fn set_group_member_active(&self, group_id: &str, user_id: &str, active: bool) -> bool {
+        match self
+            .groups
+            .lock()
+            .await
+            .get_mut(group_id)
+            .and_then(|group| group.members.get_mut(user_id))
+        {
+            Some(is_active) => {
+                *is_active = active;
+                true
+            }
+            None => false,
+        }
+    }
+
+    async fn update_group(
+        &self,
+        group_id: &str,
+        change: GroupChange,
+    ) -> StoreResult<Option<GroupChat>> {
+        Ok(self.groups.lock().await.get_mut(group_id).map(|group| {
+            group.apply(&change);
+            group.clone()
+        }))
+    }
+
+    async fn delete_group(&self, group_id: &str) -> StoreResult<Option<GroupChat>> {
+        let group = self.groups.lock().await.remove(group_id);
+        if group.is_some() {
+            self.history.lock().await.chats.remove(group_id);
+            self.invites
+                .lock()
+                .await
+                .retain(|_, invite| invite.group_id != group_id);
+        }
+        Ok(group)
+    }
+
+    async fn insert_invite(&self, invite_id: &str, invite: Invite) -> StoreResult<()> {
+        self.invites
+            .lock()
+            .await
+            .insert(invite_id.to_string(), invite);
+        Ok(())
+    }
+
+    async fn get_invite(&self, invite_id: &str, now: u64) -> Option<Invite> {
+        self.invites
+            .lock()
+            .await
+            .get(invite_id)
+            .filter(|invite| !invite.is_expired(now))
+            .cloned()
+    }
+
+    async fn redeem_invite(&self, invite_id: &str, now: u64) -> StoreResult<bool> {
+        let mut invites = self.invites.lock().await;
+        let Some(invite) = invites.get_mut(invite_id) else {
+            return Ok(false);
+        };
+        if invite.is_expired(now) {
+            invites.remove(invite_id);
+            return Ok(false);
+        }
+
+        match invite.uses_left.as_mut() {
+            Some(1) => {
+                invites.remove(invite_id);
+            }
+            Some(uses_left) => *uses_left -= 1,
+            None => {}
+        }
+        Ok(true)
+    }
+
+    async fn purge_invites(&self, now: u64) -> StoreResult<usize> {
+        let mut invites = self.invites.lock().await;
+        let len = invites.len();
+        invites.retain(|_, invite| !invite.is_expired(now));
+        Ok(len - invites.len())
+    }
+
+    async fn server_key(&self, purpose: &str) -> StoreResult<Vec<u8>> {
+        Ok(self
+            .keys
+            .lock()
+            .await
+            .entry(purpose.to_string())
+            .or_insert_with(|| generate_session_data().0)
+            .clone())
+    }
+
+    async fn enqueue_offline(
+        &self,
+        user_id: &str,
+        queued: QueuedPacket,
+        max_bytes: usize,
+    ) -> StoreResult<bool> {
+        let now = unix_now();
+        let mut offline = self.offline.lock().await;
+        let queue = offline.entry(user_id.to_string()).or_default();
+        queue.retain(|q| q.expires_at > now);
+
+        let used: usize = queue.iter().map(QueuedPacket::size).sum();
+        if used.saturating_add(queued.size()) > max_bytes {
+            return Ok(false);
+        }
+
+        queue.push_back(queued);
+        Ok(true)
+    }
+
+    async fn take_offline(&self, user_id: &str) -> StoreResult<Vec<QueuedPacket>> {
+        let now = unix_now();
+        Ok(match self.offline.lock().await.remove(user_id) {
+            Some(queue) => queue.into_iter().filter(|q| q.expires_at > now).collect(),
+            None => Vec::new(),
+        })
+    }
+
+    async fn purge_offline(&self, now: u64) -> StoreResult<usize> {
+        let mut purged = 0;
+        let mut offline = self.offline.lock().await;
+        for queue in offline.values_mut() {
+            let len = queue.len();
+            queue.retain(|q| q.expires_at > now);
+            purged += len - queue.len();
+        }
+        offline.retain(|_, queue| !queue.is_empty());
+
+        Ok(purged)
+    }
+
+    async fn append_history(&self, mut entry: HistoryEntry) -> StoreResult<u64> {
+        let mut history = self.history.lock().await;
+        history.next_id += 1;
+        entry.id = history.next_id;
+
+        let id = entry.id;
+        history
+            .chats
+            .entry(entry.chat_id.clone())
+            .or_default()
+            .push(entry);
+
+        Ok(id)
+    }
+
+    async fn load_history(
+        &self,
+        chat_id: &str,
+        before: Option<HistoryCursor>,
+        limit: usize,
+    ) -> StoreResult<Vec<HistoryEntry>> {
+        let history = self.history.lock().await;
+        let entries = match history.chats.get(chat_id) {
+            Some(entries) => entries,
+            None => return Ok(Vec::new()),
+        };
+
+        let matching: Vec<&HistoryEntry> = entries
+            .iter()
+            .filter(|e| match before {
+                Some(HistoryCursor::Id(id)) => e.id < id,
+                Some(HistoryCursor::Timestamp(ts)) => e.timestamps < ts,
+                None => true,
+            })
+            .collect();
+
+        let start = matching.len().saturating_sub(limit);
+        Ok(matching[start..].iter().map(|e| (*e).clone()).collect())
+    }
+
+    async fn purge_history(&self, stored_before: u64) -> StoreResult<usize> {
+        let mut purged = 0;
+        let mut history = self.history.lock().await;
+        for entries in history.chats.values_mut() {
+            let len = entries.len();
+            entries.retain(|e| e.stored_at >= stored_before);
+            purged += len - entries.len();
+        }
+        history.chats.retain(|_, entries| !entries.is_empty());
+
+        Ok(purged)
+    }
+}
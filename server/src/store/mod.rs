@@ -0,0 +1,212 @@
+//! Storage of clients, DM sessions and groups.
+//!
+//! Handlers only access the server state through the [`Store`] trait,
+//! which is handed to them in the [`ServerState`](crate::state::ServerState).
+//! [`MemoryStore`] keeps everything in memory, [`SqliteStore`] additionally
+//! persists DM sessions, groups, invites, handles, queued messages, history
+//! and the keys of the server so they survive restarts.
+//! Every method is atomic, handlers never hold a lock across calls.
+
+pub mod memory;
+pub mod sqlite;
+#[cfg(test)]
+mod tests;
+
+pub use memory::MemoryStore;
+pub use sqlite::SqliteStore;
+
+use crate::{
+    StorageBackend, StorageConfig,
+    types::{
+        Client, DmChat, GroupChange, GroupChat, HandleEntry, HistoryEntry, Invite, QueuedPacket,
+        SuspendedSession, UserStatus,
+    },
+};
+use async_trait::async_trait;
+use common::types::HistoryCursor;
+use std::sync::Arc;
+
+pub type StoreResult<T> = Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+#[async_trait]
+pub trait Store: Send + Sync {
+    /// Register a connected device, replacing a previous connection of the same device
+    /// Returns the replaced connection
+    async fn add_client(&self, client: Client) -> Option<Client>;
+
+    /// Get a connected device of a user
+    async fn get_client(&self, user_id: &str, device_id: &str) -> Option<Client>;
+
+    /// Get every connected device of a user
+    async fn get_devices(&self, user_id: &str) -> Vec<Client>;
+
+    /// Remove a disconnected device, unless it was replaced by a newer connection
+    /// `ticket_id` identifies the connection being removed
+    async fn remove_client(&self, user_id: &str, device_id: &str, ticket_id: &str)
+    -> Option<Client>;
+
+    /// Replace the DMs and groups a connected device takes part in
+    async fn set_client_chats(
+        &self,
+        user_id: &str,
+        device_id: &str,
+        dms: Vec<String>,
+        groups: Vec<String>,
+    );
+
+    /// Record that every connected device of a user takes part in a DM session
+    async fn add_client_dm(&self, user_id: &str, dm_id: &str);
+
+    /// Record that every connected device of a user takes part in a group
+    async fn add_client_group(&self, user_id: &str, group_id: &str);
+
+    /// Record that the connected devices of a user no longer take part in a group
+    async fn remove_client_group(&self, user_id: &str, group_id: &str);
+
+    /// Keep the memberships of a disconnected device until its ticket is redeemed or expires
+    /// Only ever kept in memory, tickets don't survive a restart
+    async fn suspend_session(&self, ticket_id: &str, session: SuspendedSession);
+
+    /// Take the session suspended under a ticket, so the ticket can only be redeemed once
+    async fn take_session(&self, ticket_id: &str) -> Option<SuspendedSession>;
+
+    /// Drop the suspended sessions expired at `now` (unix seconds)
+    async fn purge_sessions(&self, now: u64);
+
+    /// Revoke a device, it can't connect anymore
+    async fn revoke_device(&self, user_id: &str, device_id: &str) -> StoreResult<()>;
+
+    /// Whether a device was revoked
+    async fn is_device_revoked(&self, user_id: &str, device_id: &str) -> bool;
+
+    /// Block a user, it can't message `user_id` nor add it to groups anymore
+    async fn block_user(&self, user_id: &str, blocked_id: &str) -> StoreResult<()>;
+
+    /// Unblock a user
+    /// Returns `false` if it wasn't blocked
+    async fn unblock_user(&self, user_id: &str, blocked_id: &str) -> StoreResult<bool>;
+
+    /// Whether `user_id` blocked `blocked_id`
+    async fn is_blocked(&self, user_id: &str, blocked_id: &str) -> bool;
+
+    /// Get the users blocked by a user
+    async fn get_blocked(&self, user_id: &str) -> Vec<String>;
+
+    /// Set the presence and status line of a user
+    async fn set_status(&self, user_id: &str, status: UserStatus);
+
+    /// Get the presence and status line of a user, the default if never set
+    async fn get_status(&self, user_id: &str) -> UserStatus;
+
+    /// Register a handle to a user, replacing the previous handle of the user
+    /// Returns `false` if the handle belongs to another user
+    async fn claim_handle(&self, entry: HandleEntry) -> StoreResult<bool>;
+
+    /// Get the user a handle is registered to
+    async fn get_handle(&self, handle: &str) -> Option<HandleEntry>;
+
+    /// Get the handle registered to a user
+    async fn handle_of(&self, user_id: &str) -> Option<String>;
+
+    /// Get every DM session and group a user is a member of
+    async fn get_user_chats(&self, user_id: &str) -> (Vec<DmChat>, Vec<GroupChat>);
+
+    /// Get a DM session
+    async fn get_dm(&self, dm_id: &str) -> Option<DmChat>;
+
+    /// Insert a DM session unless one with the same id exists
+    /// Returns the stored session
+    async fn insert_dm(&self, dm: DmChat) -> StoreResult<DmChat>;
+
+    /// Mark a member of a DM session active or inactive
+    /// Returns `false` if the session or the member doesn't exist
+    async fn set_dm_member_active(&self, dm_id: &str, user_id: &str, active: bool) -> bool;
+
+    /// Accept the contact request of a DM session, both members can message each other
+    /// Returns the accepted session, `None` if it doesn't exist
+    async fn accept_dm(&self, dm_id: &str) -> StoreResult<Option<DmChat>>;
+
+    /// Delete a DM session along with its history
+    /// Returns the deleted session
+    async fn delete_dm(&self, dm_id: &str) -> StoreResult<Option<DmChat>>;
+
+    /// Get a group
+    async fn get_group(&self, group_id: &str) -> Option<GroupChat>;
+
+    /// Insert a group unless one with the same id exists
+    /// Returns the stored group
+    async fn insert_group(&self, group: GroupChat) -> StoreResult<GroupChat>;
+
+    /// Mark a member of a group active or inactive
+    /// Returns `false` if the group or the member doesn't exist
+    async fn set_group_member_active(&self, group_id: &str, user_id: &str, active: bool) -> bool;
+
+    /// Apply a change to a group
+    /// Returns the updated group, `None` if it doesn't exist
+    async fn update_group(&self, group_id: &str, change: GroupChange)
+    -> StoreResult<Option<GroupChat>>;
+
+    /// Delete a group along with its history and invites
+    /// Returns the deleted group
+    async fn delete_group(&self, group_id: &str) -> StoreResult<Option<GroupChat>>;
+
+    /// Record an invite to a group
+    async fn insert_invite(&self, invite_id: &str, invite: Invite) -> StoreResult<()>;
+
+    /// Get an invite that can still be redeemed at `now` (unix seconds)
+    async fn get_invite(&self, invite_id: &str, now: u64) -> Option<Invite>;
+
+    /// Use an invite once, it is dropped after its last use
+    /// Returns `false` if it doesn't exist, or is used up or expired at `now`
+    async fn redeem_invite(&self, invite_id: &str, now: u64) -> StoreResult<bool>;
+
+    /// Drop the invites used up or expired at `now`
+    /// Returns the number of dropped invites
+    async fn purge_invites(&self, now: u64) -> StoreResult<usize>;
+
+    /// Key of the server used for `purpose`, generated on first use
+    async fn server_key(&self, purpose: &str) -> StoreResult<Vec<u8>>;
+
+    /// Queue a packet for an offline user
+    /// Returns `false` if it would exceed the `max_bytes` quota of the user
+    async fn enqueue_offline(
+        &self,
+        user_id: &str,
+        queued: QueuedPacket,
+        max_bytes: usize,
+    ) -> StoreResult<bool>;
+
+    /// Take every unexpired packet queued for a user, oldest first
+    async fn take_offline(&self, user_id: &str) -> StoreResult<Vec<QueuedPacket>>;
+
+    /// Drop the queued packets that expired at `now` (unix seconds)
+    /// Returns the number of dropped packets
+    async fn purge_offline(&self, now: u64) -> StoreResult<usize>;
+
+    /// Append a message to the history of a DM session or group, the `id` of the entry is ignored
+    /// Returns the id assigned to the entry
+    async fn append_history(&self, entry: HistoryEntry) -> StoreResult<u64>;
+
+    /// Get up to `limit` entries of a chat preceding `before`, or the latest ones, oldest first
+    async fn load_history(
+        &self,
+        chat_id: &str,
+        before: Option<HistoryCursor>,
+        limit: usize,
+    ) -> StoreResult<Vec<HistoryEntry>>;
+
+    /// Drop the history entries stored before `stored_before` (unix seconds)
+    /// Returns the number of dropped entries
+    async fn purge_history(&self, stored_before: u64) -> StoreResult<usize>;
+}
+
+/// Open the store selected in the configuration
+pub async fn open_store(config: &StorageConfig) -> StoreResult<Arc<dyn Store>> {
+    match config.backend {
+        StorageBackend::Memory => {
+            println!("🗄️ Using in-memory storage, nothing survives a restart");
+            Ok(Arc::new(MemoryStore::default()))
+        }
+        StorageBackend::Sqlite => Ok(Arc::new(SqliteStore::open(config).await?)),
+    }
+}
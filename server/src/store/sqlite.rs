@@ -0,0 +1,1211 @@
+//! Persistent storage.
+//!
+//! Groups, DM sessions, their memberships, group invites, revoked devices, blocked users,
+//! handles and the keys of the server are kept in a SQLite database, so they survive
+//! restarts and members being offline.
+//! Messages queued for offline users and the message history are only
+//! kept in the database.
+//! Presence, status lines and the sessions suspended for resumption tickets are only kept in memory.
+//! Everything is loaded into a [`MemoryStore`] on startup and
+//! written through on every change.
+//! Session keys and the keys of the server are encrypted at rest with a key kept in a separate file.
+//! Database calls block, so they run on the blocking thread pool.
+//! Pending schema migrations are applied when the database is opened.
+
+use super::{MemoryStore, Store, StoreResult};
+use crate::{
+    StorageConfig,
+    ticket::unix_now,
+    types::{
+        Client, DmChat, GroupChange, GroupChat, HandleEntry, HistoryEntry, Invite, QueuedPacket,
+        SuspendedSession, UserStatus,
+    },
+};
+use async_trait::async_trait;
+use common::{
+    net::Packet,
+    types::{EncryptionConfig, GroupRole, HistoryCursor, SymmetricAlgo},
+    utils::enc::{decrypt_bytes, encrypt_bytes, generate_session_data},
+};
+use rusqlite::{Connection, OptionalExtension, Transaction, params};
+use std::{
+    collections::HashMap,
+    fs,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tokio::sync::Mutex as AsyncMutex;
+
+/// Schema migrations, applied in order
+/// Never edit a released migration, append a new one instead
+const MIGRATIONS: &[&str] = &[
+    // 1: groups, DM sessions and their members
+    "CREATE TABLE groups (
+        group_id TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        admin TEXT NOT NULL,
+        session_key BLOB NOT NULL
+    );
+    CREATE TABLE group_members (
+        group_id TEXT NOT NULL REFERENCES groups (group_id) ON DELETE CASCADE,
+        user_id TEXT NOT NULL,
+        PRIMARY KEY (group_id, user_id)
+    );
+    CREATE TABLE dms (
+        dm_id TEXT PRIMARY KEY,
+        session_key BLOB NOT NULL
+    );
+    CREATE TABLE dm_members (
+        dm_id TEXT NOT NULL REFERENCES dms (dm_id) ON DELETE CASCADE,
+        user_id TEXT NOT NULL,
+        PRIMARY KEY (dm_id, user_id)
+    );",
+    // 2: messages queued for offline users
+    "CREATE TABLE offline_queue (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        user_id TEXT NOT NULL,
+        packet BLOB NOT NULL,
+        size INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL
+    );
+    CREATE INDEX offline_queue_user ON offline_queue (user_id, seq);",
+    // 3: message history of DM sessions and groups
+    "CREATE TABLE history (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        chat_id TEXT NOT NULL,
+        timestamps INTEGER NOT NULL,
+        payload BLOB NOT NULL,
+        stored_at INTEGER NOT NULL
+    );
+    CREATE INDEX history_chat ON history (chat_id, id);
+    CREATE INDEX history_stored_at ON history (stored_at);",
+    // 4: revoked devices
+    "CREATE TABLE revoked_devices (
+        user_id TEXT NOT NULL,
+        device_id TEXT NOT NULL,
+        revoked_at INTEGER NOT NULL DEFAULT (unixepoch()),
+        PRIMARY KEY (user_id, device_id)
+    );",
+    // 5: roles of the group members, the owner is the admin of the group
+    "ALTER TABLE group_members ADD COLUMN role TEXT NOT NULL DEFAULT 'member';
+    UPDATE group_members SET role = 'owner' WHERE user_id =
+        (SELECT admin FROM groups WHERE groups.group_id = group_members.group_id);",
+    // 6: broadcast channels
+    "ALTER TABLE groups ADD COLUMN channel INTEGER NOT NULL DEFAULT 0;",
+    // 7: handles registered to users
+    "CREATE TABLE handles (
+        handle TEXT PRIMARY KEY,
+        user_id TEXT NOT NULL UNIQUE,
+        public_key TEXT NOT NULL,
+        claimed_at INTEGER NOT NULL
+    );",
+    // 8: contact requests and blocked users
+    "ALTER TABLE dms ADD COLUMN requester TEXT;
+    CREATE TABLE blocks (
+        user_id TEXT NOT NULL,
+        blocked_id TEXT NOT NULL,
+        blocked_at INTEGER NOT NULL DEFAULT (unixepoch()),
+        PRIMARY KEY (user_id, blocked_id)
+    );",
+    // 9: group invites and the keys of the server
+    "CREATE TABLE invites (
+        invite_id TEXT PRIMARY KEY,
+        group_id TEXT NOT NULL REFERENCES groups (group_id) ON DELETE CASCADE,
+        uses_left INTEGER,
+        expires_at INTEGER
+    );
+    CREATE TABLE server_keys (
+        purpose TEXT PRIMARY KEY,
+        sealed_key BLOB NOT NULL
+    );",
+];
+
+/// Handle to the SQLite database
+struct Database {
+    conn: Mutex<Connection>,
+    /// Key encrypting the session keys at rest
+    key: Vec<u8>,
+}
+
+impl Database {
+    /// Open the database, creating it and its key if missing, and run pending migrations
+    fn open(config: &StorageConfig) -> StoreResult<Self> {
+        let key = load_or_create_key(&config.get_key_path())?;
+
+        let mut conn = Connection::open(&config.path)?;
+        conn.execute_batch("PRAGMA journal_mode = WAL; PRAGMA foreign_keys = ON;")?;
+        migrate(&mut conn)?;
+
+        Ok(Database {
+            conn: Mutex::new(conn),
+            key,
+        })
+    }
+
+    fn seal(&self, session_key: &[u8]) -> StoreResult<Vec<u8>> {
+        encrypt_bytes(session_key, self.encryption())
+    }
+
+    fn open_sealed(&self, sealed: &[u8]) -> StoreResult<Vec<u8>> {
+        decrypt_bytes(sealed, self.encryption())
+    }
+
+    fn encryption(&self) -> EncryptionConfig {
+        EncryptionConfig {
+            algo: SymmetricAlgo::AES256,
+            encryption_key: Some(self.key.clone()),
+        }
+    }
+
+    /// Load every group, all members are marked inactive
+    fn load_groups(&self) -> StoreResult<Vec<GroupChat>> {
+        let conn = self.conn.lock().unwrap();
+        let mut members = load_members(&conn, "SELECT group_id, user_id FROM group_members")?;
+        let mut roles = load_roles(&conn)?;
+
+        let mut stmt =
+            conn.prepare("SELECT group_id, name, admin, session_key, channel FROM groups")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, String>(1)?,
+                row.get::<_, String>(2)?,
+                row.get::<_, Vec<u8>>(3)?,
+                row.get::<_, bool>(4)?,
+            ))
+        })?;
+
+        let mut groups = Vec::new();
+        for row in rows {
+            let (group_id, group_name, admin, sealed, channel) = row?;
+            groups.push(GroupChat {
+                group_name,
+                members: members.remove(&group_id).unwrap_or_default(),
+                session_key: self.open_sealed(&sealed)?,
+                admin,
+                roles: roles.remove(&group_id).unwrap_or_default(),
+                channel,
+                group_id,
+            });
+        }
+
+        Ok(groups)
+    }
+
+    /// Load every DM session, all members are marked inactive
+    fn load_dms(&self) -> StoreResult<Vec<DmChat>> {
+        let conn = self.conn.lock().unwrap();
+        let mut members = load_members(&conn, "SELECT dm_id, user_id FROM dm_members")?;
+
+        let mut stmt = conn.prepare("SELECT dm_id, session_key, requester FROM dms")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get::<_, String>(0)?,
+                row.get::<_, Vec<u8>>(1)?,
+                row.get::<_, Option<String>>(2)?,
+            ))
+        })?;
+
+        let mut dms = Vec::new();
+        for row in rows {
+            let (dm_id, sealed, requester) = row?;
+            dms.push(DmChat {
+                members: members.remove(&dm_id).unwrap_or_default(),
+                session_key: self.open_sealed(&sealed)?,
+                requester,
+                dm_id,
+            });
+        }
+
+        Ok(dms)
+    }
+
+    /// Insert or update a group along with its members, replacing the stored ones
+    fn save_group(&self, group: &GroupChat) -> StoreResult<()> {
+        let sealed = self.seal(&group.session_key)?;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "INSERT INTO groups (group_id, name, admin, session_key, channel)
+             VALUES (?1, ?2, ?3, ?4, ?5)
+             ON CONFLICT (group_id) DO UPDATE SET name = ?2, admin = ?3, session_key = ?4",
+            params![
+                group.group_id,
+                group.group_name,
+                group.admin,
+                sealed,
+                group.channel
+            ],
+        )?;
+        tx.execute(
+            "DELETE FROM group_members WHERE group_id = ?1",
+            params![group.group_id],
+        )?;
+        {
+            let mut stmt = tx.prepare(
+                "INSERT INTO group_members (group_id, user_id, role) VALUES (?1, ?2, ?3)",
+            )?;
+            for user_id in group.members.keys() {
+                let role = group.role_of(user_id).unwrap_or_default();
+                stmt.execute(params![group.group_id, user_id, role.as_str()])?;
+            }
+        }
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Delete a group, its members and its history
+    fn delete_group(&self, group_id: &str) -> StoreResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        tx.execute("DELETE FROM groups WHERE group_id = ?1", params![group_id])?;
+        tx.execute("DELETE FROM history WHERE chat_id = ?1", params![group_id])?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Insert a DM session along with its members, an existing session is kept as is
+    fn save_dm(&self, dm: &DmChat) -> StoreResult<()> {
+        let sealed = self.seal(&dm.session_key)?;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        tx.execute(
+            "INSERT OR IGNORE INTO dms (dm_id, session_key, requester) VALUES (?1, ?2, ?3)",
+            params![dm.dm_id, sealed, dm.requester],
+        )?;
+        save_members(
+            &tx,
+            "INSERT OR IGNORE INTO dm_members (dm_id, user_id) VALUES (?1, ?2)",
+            &dm.dm_id,
+            dm.members.keys(),
+        )?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Clear the requester of a DM session once its contact request is accepted
+    fn accept_dm(&self, dm_id: &str) -> StoreResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "UPDATE dms SET requester = NULL WHERE dm_id = ?1",
+            params![dm_id],
+        )?;
+        Ok(())
+    }
+
+    /// Delete a DM session along with its members and history
+    fn delete_dm(&self, dm_id: &str) -> StoreResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        tx.execute("DELETE FROM dms WHERE dm_id = ?1", params![dm_id])?;
+        tx.execute("DELETE FROM history WHERE chat_id = ?1", params![dm_id])?;
+
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Load every revoked `(user_id, device_id)` pair
+    fn load_revoked(&self) -> StoreResult<Vec<(String, String)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT user_id, device_id FROM revoked_devices")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Record a revoked device
+    fn save_revoked(&self, user_id: &str, device_id: &str) -> StoreResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR IGNORE INTO revoked_devices (user_id, device_id) VALUES (?1, ?2)",
+            params![user_id, device_id],
+        )?;
+        Ok(())
+    }
+
+    /// Load every blocked `(user_id, blocked_id)` pair
+    fn load_blocked(&self) -> StoreResult<Vec<(String, String)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT user_id, blocked_id FROM blocks")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Record a blocked user
+    fn save_blocked(&self, user_id: &str, blocked_id: &str) -> StoreResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT OR IGNORE INTO blocks (user_id, blocked_id) VALUES (?1, ?2)",
+            params![user_id, blocked_id],
+        )?;
+        Ok(())
+    }
+
+    /// Forget a blocked user
+    fn delete_blocked(&self, user_id: &str, blocked_id: &str) -> StoreResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "DELETE FROM blocks WHERE user_id = ?1 AND blocked_id = ?2",
+            params![user_id, blocked_id],
+        )?;
+        Ok(())
+    }
+
+    /// Load every registered handle
+    fn load_handles(&self) -> StoreResult<Vec<HandleEntry>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare("SELECT handle, user_id, public_key, claimed_at FROM handles")?;
+        let rows = stmt.query_map([], |row| {
+            Ok(HandleEntry {
+                handle: row.get(0)?,
+                user_id: row.get(1)?,
+                public_key: row.get(2)?,
+                claimed_at: row.get(3)?,
+            })
+        })?;
+
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Register a handle, replacing the previous handle of the user
+    fn save_handle(&self, entry: &HandleEntry) -> StoreResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM handles WHERE user_id = ?1",
+            params![entry.user_id],
+        )?;
+        tx.execute(
+            "INSERT INTO handles (handle, user_id, public_key, claimed_at) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                entry.handle,
+                entry.user_id,
+                entry.public_key,
+                entry.claimed_at
+            ],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Load every invite, keyed by invite id
+    fn load_invites(&self) -> StoreResult<Vec<(String, Invite)>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt =
+            conn.prepare("SELECT invite_id, group_id, uses_left, expires_at FROM invites")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((
+                row.get(0)?,
+                Invite {
+                    group_id: row.get(1)?,
+                    uses_left: row.get(2)?,
+                    expires_at: row.get::<_, Option<i64>>(3)?.map(|at| at as u64),
+                },
+            ))
+        })?;
+
+        Ok(rows.collect::<Result<Vec<_>, _>>()?)
+    }
+
+    /// Record an invite
+    fn save_invite(&self, invite_id: &str, invite: &Invite) -> StoreResult<()> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO invites (invite_id, group_id, uses_left, expires_at) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                invite_id,
+                invite.group_id,
+                invite.uses_left,
+                invite.expires_at.map(|at| at as i64)
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Count a use of an invite, it is deleted after its last use
+    fn use_invite(&self, invite_id: &str) -> StoreResult<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+        tx.execute(
+            "DELETE FROM invites WHERE invite_id = ?1 AND uses_left = 1",
+            params![invite_id],
+        )?;
+        tx.execute(
+            "UPDATE invites SET uses_left = uses_left - 1 WHERE invite_id = ?1 AND uses_left > 1",
+            params![invite_id],
+        )?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Delete the invites used up or expired at `now`
+    fn purge_invites(&self, now: u64) -> StoreResult<usize> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute(
+            "DELETE FROM invites WHERE uses_left = 0 OR expires_at <= ?1",
+            params![now as i64],
+        )?)
+    }
+
+    /// Load every key of the server, keyed by purpose
+    fn load_keys(&self) -> StoreResult<HashMap<String, Vec<u8>>> {
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare("SELECT purpose, sealed_key FROM server_keys")?;
+        let rows = stmt.query_map([], |row| {
+            Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+        })?;
+
+        let mut keys = HashMap::new();
+        for row in rows {
+            let (purpose, sealed) = row?;
+            keys.insert(purpose, self.open_sealed(&sealed)?);
+        }
+        Ok(keys)
+    }
+
+    /// Record a key of the server
+    fn save_key(&self, purpose: &str, key: &[u8]) -> StoreResult<()> {
+        let sealed = self.seal(key)?;
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO server_keys (purpose, sealed_key) VALUES (?1, ?2)",
+            params![purpose, sealed],
+        )?;
+        Ok(())
+    }
+
+    /// Queue a packet unless the unexpired packets of the user would exceed `max_bytes`
+    fn enqueue_offline(
+        &self,
+        user_id: &str,
+        queued: &QueuedPacket,
+        max_bytes: usize,
+        now: u64,
+    ) -> StoreResult<bool> {
+        let packet = bincode::encode_to_vec(&queued.packet, bincode::config::standard())?;
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        let used: i64 = tx.query_row(
+            "SELECT COALESCE(SUM(size), 0) FROM offline_queue WHERE user_id = ?1 AND expires_at > ?2",
+            params![user_id, now as i64],
+            |row| row.get(0),
+        )?;
+        if (used as usize).saturating_add(queued.size()) > max_bytes {
+            return Ok(false);
+        }
+
+        tx.execute(
+            "INSERT INTO offline_queue (user_id, packet, size, expires_at) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                user_id,
+                packet,
+                queued.size() as i64,
+                queued.expires_at as i64
+            ],
+        )?;
+
+        tx.commit()?;
+        Ok(true)
+    }
+
+    /// Remove and return the queued packets of a user, oldest first
+    fn take_offline(&self, user_id: &str, now: u64) -> StoreResult<Vec<QueuedPacket>> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction()?;
+
+        let rows = {
+            let mut stmt = tx.prepare(
+                "SELECT packet, expires_at FROM offline_queue
+                 WHERE user_id = ?1 AND expires_at > ?2 ORDER BY seq",
+            )?;
+            stmt.query_map(params![user_id, now as i64], |row| {
+                Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, i64>(1)?))
+            })?
+            .collect::<Result<Vec<_>, _>>()?
+        };
+        tx.execute(
+            "DELETE FROM offline_queue WHERE user_id = ?1",
+            params![user_id],
+        )?;
+        tx.commit()?;
+
+        let mut queued = Vec::new();
+        for (packet, expires_at) in rows {
+            let (packet, _): (Packet, usize) =
+                bincode::decode_from_slice(&packet, bincode::config::standard())?;
+            queued.push(QueuedPacket {
+                packet,
+                expires_at: expires_at as u64,
+            });
+        }
+
+        Ok(queued)
+    }
+
+    /// Delete the queued packets expired at `now`
+    fn purge_offline(&self, now: u64) -> StoreResult<usize> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute(
+            "DELETE FROM offline_queue WHERE expires_at <= ?1",
+            params![now as i64],
+        )?)
+    }
+
+    /// Insert a history entry, returns its id
+    fn append_history(&self, entry: &HistoryEntry) -> StoreResult<u64> {
+        let conn = self.conn.lock().unwrap();
+        conn.execute(
+            "INSERT INTO history (chat_id, timestamps, payload, stored_at) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                entry.chat_id,
+                entry.timestamps as i64,
+                entry.payload,
+                entry.stored_at as i64
+            ],
+        )?;
+
+        Ok(conn.last_insert_rowid() as u64)
+    }
+
+    /// Load up to `limit` entries of a chat preceding `before`, oldest first
+    fn load_history(
+        &self,
+        chat_id: &str,
+        before: Option<HistoryCursor>,
+        limit: usize,
+    ) -> StoreResult<Vec<HistoryEntry>> {
+        let (column, bound) = match before {
+            Some(HistoryCursor::Id(id)) => ("id", id as i64),
+            Some(HistoryCursor::Timestamp(ts)) => ("timestamps", ts as i64),
+            None => ("id", i64::MAX),
+        };
+
+        let conn = self.conn.lock().unwrap();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT id, timestamps, payload, stored_at FROM history
+             WHERE chat_id = ?1 AND {} < ?2 ORDER BY id DESC LIMIT ?3",
+            column
+        ))?;
+        let rows = stmt.query_map(params![chat_id, bound, limit as i64], |row| {
+            Ok(HistoryEntry {
+                id: row.get::<_, i64>(0)? as u64,
+                chat_id: chat_id.to_string(),
+                timestamps: row.get::<_, i64>(1)? as u128,
+                payload: row.get(2)?,
+                stored_at: row.get::<_, i64>(3)? as u64,
+            })
+        })?;
+
+        let mut entries = rows.collect::<Result<Vec<_>, _>>()?;
+        entries.reverse();
+        Ok(entries)
+    }
+
+    /// Delete the history entries stored before `stored_before`
+    fn purge_history(&self, stored_before: u64) -> StoreResult<usize> {
+        let conn = self.conn.lock().unwrap();
+        Ok(conn.execute(
+            "DELETE FROM history WHERE stored_at < ?1",
+            params![stored_before as i64],
+        )?)
+    }
+}
+
+/// Store persisting DM sessions, groups, queued messages and history in SQLite
+/// Connected clients and active flags only live in memory
+pub struct SqliteStore {
+    memory: MemoryStore,
+    db: Arc<Database>,
+    /// Keys of the server, keyed by purpose
+    keys: AsyncMutex<HashMap<String, Vec<u8>>>,
+    /// Serializes inserts and updates, so the database and memory can't disagree on a stored chat
+    writes: AsyncMutex<()>,
+}
+
+impl SqliteStore {
+    /// Open the database and load the stored DM sessions and groups
+    pub async fn open(config: &StorageConfig) -> StoreResult<Self> {
+        let opened = config.clone();
+        let (db, groups, dms, invites, revoked, blocked, handles, keys) =
+            tokio::task::spawn_blocking(move || -> StoreResult<_> {
+                let db = Database::open(&opened)?;
+                let groups = db.load_groups()?;
+                let dms = db.load_dms()?;
+                let invites = db.load_invites()?;
+                let revoked = db.load_revoked()?;
+                let blocked = db.load_blocked()?;
+                let handles = db.load_handles()?;
+                let keys = db.load_keys()?;
+                Ok((db, groups, dms, invites, revoked, blocked, handles, keys))
+            })
+            .await??;
+        println!(
+            "🗄️ Loaded {} groups and {} DM sessions from {}",
+            groups.len(),
+            dms.len(),
+            config.path
+        );
+
+        Ok(SqliteStore {
+            memory: MemoryStore::with_chats(dms, groups, invites, revoked, blocked, handles),
+            db: Arc::new(db),
+            keys: AsyncMutex::new(keys),
+            writes: AsyncMutex::new(()),
+        })
+    }
+
+    /// Run database work on the blocking thread pool, the async workers stay free meanwhile
+    async fn blocking<T, F>(&self, work: F) -> StoreResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Database) -> StoreResult<T> + Send + 'static,
+    {
+        let db = self.db.clone();
+        tokio::task::spawn_blocking(move || work(&db)).await?
+    }
+}
+
+#[async_trait]
+impl Store for SqliteStore {
+    async fn add_client(&self, client: Client) -> Option<Client> {
+        self.memory.add_client(client).await
+    }
+
+    async fn get_client(&self, user_id: &str, device_id: &str) -> Option<Client> {
+        self.memory.get_client(user_id, device_id).await
+    }
+
+    async fn get_devices(&self, user_id: &str) -> Vec<Client> {
+        self.memory.get_devices(user_id).await
+    }
+
+    async fn remove_client(
+        &self,
+        user_id: &str,
+        device_id: &str,
+        ticket_id: &str,
+    ) -> Option<Client> {
+        self.memory
+            .remove_client(user_id, device_id, ticket_id)
+            .await
+    }
+
+    async fn set_client_chats(
+        &self,
+        user_id: &str,
+        device_id: &str,
+        dms: Vec<String>,
+        groups: Vec<String>,
+    ) {
+        self.memory
+            .set_client_chats(user_id, device_id, dms, groups)
+            .await
+    }
+
+    async fn add_client_dm(&self, user_id: &str, dm_id: &str) {
+        self.memory.add_client_dm(user_id, dm_id).await
+    }
+
+    async fn add_client_group(&self, user_id: &str, group_id: &str) {
+        self.memory.add_client_group(user_id, group_id).await
+    }
+
+    async fn remove_client_group(&self, user_id: &str, group_id: &str) {
+        self.memory.remove_client_group(user_id, group_id).await
+    }
+
+    async fn suspend_session(&self, ticket_id: &str, session: SuspendedSession) {
+        self.memory.suspend_session(ticket_id, session).await
+    }
+
+    async fn take_session(&self, ticket_id: &str) -> Option<SuspendedSession> {
+        self.memory.take_session(ticket_id).await
+    }
+
+    async fn purge_sessions(&self, now: u64) {
+        self.memory.purge_sessions(now).await
+    }
+
+    async fn revoke_device(&self, user_id: &str, device_id: &str) -> StoreResult<()> {
+        let (user, device) = (user_id.to_string(), device_id.to_string());
+        self.blocking(move |db| db.save_revoked(&user, &device))
+            .await?;
+        self.memory.revoke_device(user_id, device_id).await
+    }
+
+    async fn is_device_revoked(&self, user_id: &str, device_id: &str) -> bool {
+        self.memory.is_device_revoked(user_id, device_id).await
+    }
+
+    async fn block_user(&self, user_id: &str, blocked_id: &str) -> StoreResult<()> {
+        let (user, blocked) = (user_id.to_string(), blocked_id.to_string());
+        self.blocking(move |db| db.save_blocked(&user, &blocked))
+            .await?;
+        self.memory.block_user(user_id, blocked_id).await
+    }
+
+    async fn unblock_user(&self, user_id: &str, blocked_id: &str) -> StoreResult<bool> {
+        let (user, blocked) = (user_id.to_string(), blocked_id.to_string());
+        self.blocking(move |db| db.delete_blocked(&user, &blocked))
+            .await?;
+        self.memory.unblock_user(user_id, blocked_id).await
+    }
+
+    async fn is_blocked(&self, user_id: &str, blocked_id: &str) -> bool {
+        self.memory.is_blocked(user_id, blocked_id).await
+    }
+
+    async fn get_blocked(&self, user_id: &str) -> Vec<String> {
+        self.memory.get_blocked(user_id).await
+    }
+
+    async fn set_status(&self, user_id: &str, status: UserStatus) {
+        self.memory.set_status(user_id, status).await
+    }
+
+    async fn get_status(&self, user_id: &str) -> UserStatus {
+        self.memory.get_status(user_id).await
+    }
+
+    async fn claim_handle(&self, entry: HandleEntry) -> StoreResult<bool> {
+        let _guard = self.writes.lock().await;
+        if let Some(owner) = self.memory.get_handle(&entry.handle).await {
+            return Ok(owner.user_id == entry.user_id);
+        }
+
+        let saved = entry.clone();
+        self.blocking(move |db| db.save_handle(&saved)).await?;
+        self.memory.claim_handle(entry).await
+    }
+
+    async fn get_handle(&self, handle: &str) -> Option<HandleEntry> {
+        self.memory.get_handle(handle).await
+    }
+
+    async fn handle_of(&self, user_id: &str) -> Option<String> {
+        self.memory.handle_of(user_id).await
+    }
+
+    async fn get_user_chats(&self, user_id: &str) -> (Vec<DmChat>, Vec<GroupChat>) {
+        self.memory.get_user_chats(user_id).await
+    }
+
+    async fn get_dm(&self, dm_id: &str) -> Option<DmChat> {
+        self.memory.get_dm(dm_id).await
+    }
+
+    async fn insert_dm(&self, dm: DmChat) -> StoreResult<DmChat> {
+        let _guard = self.writes.lock().await;
+        if let Some(stored) = self.memory.get_dm(&dm.dm_id).await {
+            return Ok(stored);
+        }
+
+        let saved = dm.clone();
+        self.blocking(move |db| db.save_dm(&saved)).await?;
+        self.memory.insert_dm(dm).await
+    }
+
+    async fn set_dm_member_active(&self, dm_id: &str, user_id: &str, active: bool) -> bool {
+        self.memory
+            .set_dm_member_active(dm_id, user_id, active)
+            .await
+    }
+
+    async fn accept_dm(&self, dm_id: &str) -> StoreResult<Option<DmChat>> {
+        let _guard = self.writes.lock().await;
+        let id = dm_id.to_string();
+        self.blocking(move |db| db.accept_dm(&id)).await?;
+        self.memory.accept_dm(dm_id).await
+    }
+
+    async fn delete_dm(&self, dm_id: &str) -> StoreResult<Option<DmChat>> {
+        let _guard = self.writes.lock().await;
+        let id = dm_id.to_string();
+        self.blocking(move |db| db.delete_dm(&id)).await?;
+        self.memory.delete_dm(dm_id).await
+    }
+
+    async fn get_group(&self, group_id: &str) -> Option<GroupChat> {
+        self.memory.get_group(group_id).await
+    }
+
+    async fn insert_group(&self, group: GroupChat) -> StoreResult<GroupChat> {
+        let _guard = self.writes.lock().await;
+        if let Some(stored) = self.memory.get_group(&group.group_id).await {
+            return Ok(stored);
+        }
+
+        let saved = group.clone();
+        self.blocking(move |db| db.save_group(&saved)).await?;
+        self.memory.insert_group(group).await
+    }
+
+    async fn set_group_member_active(&self, group_id: &str, user_id: &str, active: bool) -> bool {
+        self.memory
+            .set_group_member_active(group_id, user_id, active)
+            .await
+    }
+
+    async fn update_group(
+        &self,
+        group_id: &str,
+        change: GroupChange,
+    ) -> StoreResult<Option<GroupChat>> {
+        let _guard = self.writes.lock().await;
+        let Some(mut group) = self.memory.get_group(group_id).await else {
+            return Ok(None);
+        };
+
+        group.apply(&change);
+        self.blocking(move |db| db.save_group(&group)).await?;
+        self.memory.update_group(group_id, change).await
+    }
+
+    async fn delete_group(&self, group_id: &str) -> StoreResult<Option<GroupChat>> {
+        let _guard = self.writes.lock().await;
+        let id = group_id.to_string();
+        self.blocking(move |db| db.delete_group(&id)).await?;
+        self.memory.delete_group(group_id).await
+    }
+
+    async fn insert_invite(&self, invite_id: &str, invite: Invite) -> StoreResult<()> {
+        let (id, saved) = (invite_id.to_string(), invite.clone());
+        self.blocking(move |db| db.save_invite(&id, &saved)).await?;
+        self.memory.insert_invite(invite_id, invite).await
+    }
+
+    async fn get_invite(&self, invite_id: &str, now: u64) -> Option<Invite> {
+        self.memory.get_invite(invite_id, now).await
+    }
+
+    async fn redeem_invite(&self, invite_id: &str, now: u64) -> StoreResult<bool> {
+        let _guard = self.writes.lock().await;
+        if self.memory.get_invite(invite_id, now).await.is_none() {
+            return self.memory.redeem_invite(invite_id, now).await;
+        }
+
+        let id = invite_id.to_string();
+        self.blocking(move |db| db.use_invite(&id)).await?;
+        self.memory.redeem_invite(invite_id, now).await
+    }
+
+    async fn purge_invites(&self, now: u64) -> StoreResult<usize> {
+        self.blocking(move |db| db.purge_invites(now)).await?;
+        self.memory.purge_invites(now).await
+    }
+
+    async fn server_key(&self, purpose: &str) -> StoreResult<Vec<u8>> {
+        let mut keys = self.keys.lock().await;
+        if let Some(key) = keys.get(purpose) {
+            return Ok(key.clone());
+        }
+
+        let key = generate_session_data().0;
+        let (name, saved) = (purpose.to_string(), key.clone());
+        self.blocking(move |db| db.save_key(&name, &saved)).await?;
+        keys.insert(purpose.to_string(), key.clone());
+        Ok(key)
+    }
+
+    async fn enqueue_offline(
+        &self,
+        user_id: &str,
+        queued: QueuedPacket,
+        max_bytes: usize,
+    ) -> StoreResult<bool> {
+        let user = user_id.to_string();
+        self.blocking(move |db| db.enqueue_offline(&user, &queued, max_bytes, unix_now()))
+            .await
+    }
+
+    async fn take_offline(&self, user_id: &str) -> StoreResult<Vec<QueuedPacket>> {
+        let user = user_id.to_string();
+        self.blocking(move |db| db.take_offline(&user, unix_now()))
+            .await
+    }
+
+    async fn purge_offline(&self, now: u64) -> StoreResult<usize> {
+        self.blocking(move |db| db.purge_offline(now)).await
+    }
+
+    async fn append_history(&self, entry: HistoryEntry) -> StoreResult<u64> {
+        self.blocking(move |db| db.append_history(&entry)).await
+    }
+
+    async fn load_history(
+        &self,
+        chat_id: &str,
+        before: Option<HistoryCursor>,
+        limit: usize,
+    ) -> StoreResult<Vec<HistoryEntry>> {
+        let chat = chat_id.to_string();
+        self.blocking(move |db| db.load_history(&chat, before, limit))
+            .await
+    }
+
+    async fn purge_history(&self, stored_before: u64) -> StoreResult<usize> {
+        self.blocking(move |db| db.purge_history(stored_before))
+            .await
+    }
+}
+
+/// Oldest SQLite the migrations run on, `unixepoch()` came with 3.38
+const MIN_SQLITE_VERSION: i32 = 3_038_000;
+
+/// Apply the migrations newer than the schema version of the database
+fn migrate(conn: &mut Connection) -> StoreResult<()> {
+    if rusqlite::version_number() < MIN_SQLITE_VERSION {
+        return Err(format!(
+            "SQLite {} is too old, 3.38 or newer is needed",
+            rusqlite::version()
+        )
+        .into());
+    }
+    conn.execute_batch(
+        "CREATE TABLE IF NOT EXISTS schema_migrations (
+            version INTEGER PRIMARY KEY,
+            applied_at INTEGER NOT NULL DEFAULT (unixepoch())
+        );",
+    )?;
+    let version: usize = conn
+        .query_row("SELECT MAX(version) FROM schema_migrations", [], |row| {
+            row.get::<_, Option<usize>>(0)
+        })
+        .optional()?
+        .flatten()
+        .unwrap_or(0);
+
+    if version > MIGRATIONS.len() {
+        return Err(format!(
+            "Database schema version {} is newer than this server supports ({})",
+            version,
+            MIGRATIONS.len()
+        )
+        .into());
+    }
+
+    for (index, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        let tx = conn.transaction()?;
+        tx.execute_batch(migration)?;
+        tx.execute(
+            "INSERT INTO schema_migrations (version) VALUES (?1)",
+            params![index + 1],
+        )?;
+        tx.commit()?;
+        println!("🗄️ Applied database migration {}", index + 1);
+    }
+
+    Ok(())
+}
+
+/// Load `(chat_id, user_id)` rows into members maps keyed by chat id
+fn load_members(
+    conn: &Connection,
+    query: &str,
+) -> StoreResult<HashMap<String, HashMap<String, bool>>> {
+    let mut stmt = conn.prepare(query)?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, String>(1)?))
+    })?;
+
+    let mut members: HashMap<String, HashMap<String, bool>> = HashMap::new();
+    for row in rows {
+        let (chat_id, user_id) = row?;
+        members.entry(chat_id).or_default().insert(user_id, false);
+    }
+
+    Ok(members)
+}
+
+/// Load the roles of the group members, the owner and members with the default role are left out
+fn load_roles(conn: &Connection) -> StoreResult<HashMap<String, HashMap<String, GroupRole>>> {
+    let mut stmt = conn.prepare(
+        "SELECT m.group_id, m.user_id, m.role FROM group_members m
+         JOIN groups g ON g.group_id = m.group_id
+         WHERE m.role != 'owner'
+           AND m.role != CASE WHEN g.channel THEN 'read-only' ELSE 'member' END",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok((
+            row.get::<_, String>(0)?,
+            row.get::<_, String>(1)?,
+            row.get::<_, String>(2)?,
+        ))
+    })?;
+
+    let mut roles: HashMap<String, HashMap<String, GroupRole>> = HashMap::new();
+    for row in rows {
+        let (group_id, user_id, role) = row?;
+        roles
+            .entry(group_id)
+            .or_default()
+            .insert(user_id, role.parse()?);
+    }
+
+    Ok(roles)
+}
+
+fn save_members<'a>(
+    tx: &Transaction,
+    query: &str,
+    chat_id: &str,
+    members: impl Iterator<Item = &'a String>,
+) -> StoreResult<()> {
+    let mut stmt = tx.prepare(query)?;
+    for user_id in members {
+        stmt.execute(params![chat_id, user_id])?;
+    }
+    Ok(())
+}
+
+/// Read the storage key, or generate it on first start
+fn load_or_create_key(path: &Path) -> StoreResult<Vec<u8>> {
+    if path.exists() {
+        let key = hex::decode(fs::read_to_string(path)?.trim())?;
+        if key.len() != 32 {
+            return Err(format!("Invalid storage key in {}", path.display()).into());
+        }
+        return Ok(key);
+    }
+
+    let (key, _) = generate_session_data();
+    write_private_file(path, hex::encode(&key).as_bytes())?;
+    println!("🔑 Generated storage key {}", path.display());
+
+    Ok(key)
+}
+
+/// Write a file only readable by the owner
+fn write_private_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)?.write_all(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(conn: &Connection) -> usize {
+        conn.query_row("SELECT MAX(version) FROM schema_migrations", [], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    fn tables(conn: &Connection) -> Vec<String> {
+        let mut stmt = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap();
+        stmt.query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn migrates_an_empty_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        assert_eq!(version(&conn), MIGRATIONS.len());
+        for table in [
+            "blocks",
+            "dm_members",
+            "dms",
+            "group_members",
+            "groups",
+            "handles",
+            "history",
+            "invites",
+            "offline_queue",
+            "revoked_devices",
+            "server_keys",
+        ] {
+            assert!(tables(&conn).iter().any(|t| t == table), "{table}");
+        }
+
+        // Nothing is left to apply the second time
+        migrate(&mut conn).unwrap();
+        let applied: usize = conn
+            .query_row("SELECT COUNT(*) FROM schema_migrations", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(applied, MIGRATIONS.len());
+    }
+
+    #[test]
+    fn gives_the_admin_of_old_groups_the_owner_role() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE schema_migrations (
+                version INTEGER PRIMARY KEY,
+                applied_at INTEGER NOT NULL DEFAULT (unixepoch())
+            );",
+        )
+        .unwrap();
+        // A database of a server that only knew the first 4 migrations
+        for (index, migration) in MIGRATIONS.iter().take(4).enumerate() {
+            conn.execute_batch(migration).unwrap();
+            conn.execute(
+                "INSERT INTO schema_migrations (version) VALUES (?1)",
+                params![index + 1],
+            )
+            .unwrap();
+        }
+        conn.execute_batch(
+            "INSERT INTO groups (group_id, name, admin, session_key) VALUES ('group', 'group', 'alice', x'00');
+            INSERT INTO group_members (group_id, user_id) VALUES ('group', 'alice'), ('group', 'bob');",
+        )
+        .unwrap();
+
+        migrate(&mut conn).unwrap();
+        assert_eq!(version(&conn), MIGRATIONS.len());
+        let roles: Vec<(String, String)> = conn
+            .prepare("SELECT user_id, role FROM group_members ORDER BY user_id")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let roles: Vec<(&str, &str)> = roles
+            .iter()
+            .map(|(user, role)| (user.as_str(), role.as_str()))
+            .collect();
+        assert_eq!(roles, vec![("alice", "owner"), ("bob", "member")]);
+        let channel: bool = conn
+            .query_row("SELECT channel FROM groups", [], |row| row.get(0))
+            .unwrap();
+        assert!(!channel);
+    }
+
+    #[test]
+    fn refuses_a_newer_database() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        migrate(&mut conn).unwrap();
+        conn.execute(
+            "INSERT INTO schema_migrations (version) VALUES (?1)",
+            params![MIGRATIONS.len() + 1],
+        )
+        .unwrap();
+        assert!(migrate(&mut conn).is_err());
+    }
+}
@@ -0,0 +1,455 @@
+//! Behaviour every store has to share, run against each backend
+
+use super::{MemoryStore, SqliteStore, Store};
+use crate::{
+    SlowClientPolicy, StorageBackend, StorageConfig,
+    ticket::unix_now,
+    types::{Client, DmChat, GroupChange, GroupChat, HistoryEntry, Outbox, QueuedPacket},
+};
+use common::{
+    net::{ChatMessageKind, Packet},
+    types::{GroupRole, HistoryCursor},
+};
+use std::{
+    collections::HashMap,
+    fs,
+    sync::{Arc, atomic::AtomicBool},
+};
+use tokio::sync::{Notify, mpsc::channel};
+use uuid::Uuid;
+
+const MAX_BYTES: usize = 10;
+
+/// A database in the temp directory, removed once dropped
+struct TempDb {
+    config: StorageConfig,
+}
+
+impl TempDb {
+    fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("null-talk-{}.db", Uuid::new_v4().simple()));
+        TempDb {
+            config: StorageConfig {
+                backend: StorageBackend::Sqlite,
+                path: path.to_string_lossy().to_string(),
+                key_path: None,
+            },
+        }
+    }
+}
+
+impl Drop for TempDb {
+    fn drop(&mut self) {
+        for suffix in ["", "-wal", "-shm"] {
+            let _ = fs::remove_file(format!("{}{}", self.config.path, suffix));
+        }
+        let _ = fs::remove_file(self.config.get_key_path());
+    }
+}
+
+/// Every backend, the database of the SQLite store is kept as long as its `TempDb`
+async fn stores() -> Vec<(&'static str, Arc<dyn Store>, Option<TempDb>)> {
+    let db = TempDb::new();
+    let sqlite = SqliteStore::open(&db.config).await.unwrap();
+    vec![
+        ("memory", Arc::new(MemoryStore::default()), None),
+        ("sqlite", Arc::new(sqlite), Some(db)),
+    ]
+}
+
+fn client(user_id: &str, device_id: &str, ticket_id: &str) -> Client {
+    let (queue, _) = channel(1);
+    Client {
+        username: user_id.to_string(),
+        user_id: user_id.to_string(),
+        public_key: String::new(),
+        device_id: device_id.to_string(),
+        connected_at: 0,
+        session_key: String::new(),
+        dms: Vec::new(),
+        groups: Vec::new(),
+        outbox: Outbox {
+            queue,
+            policy: SlowClientPolicy::Drop,
+            closing: Arc::new(AtomicBool::new(false)),
+        },
+        ticket_id: ticket_id.to_string(),
+        kick: Arc::new(Notify::new()),
+    }
+}
+
+fn queued(payload: &[u8], expires_at: u64) -> QueuedPacket {
+    QueuedPacket {
+        packet: Packet {
+            kind: ChatMessageKind::DirectMessage("dm".to_string()),
+            payload: payload.to_vec(),
+        },
+        expires_at,
+    }
+}
+
+fn entry(chat_id: &str, timestamps: u128, stored_at: u64) -> HistoryEntry {
+    HistoryEntry {
+        id: 0,
+        chat_id: chat_id.to_string(),
+        timestamps,
+        payload: vec![timestamps as u8],
+        stored_at,
+    }
+}
+
+fn group(admin: &str, members: &[&str]) -> GroupChat {
+    GroupChat {
+        group_name: "group".to_string(),
+        group_id: "group".to_string(),
+        members: members.iter().map(|m| (m.to_string(), false)).collect(),
+        session_key: vec![7; 32],
+        admin: admin.to_string(),
+        roles: HashMap::new(),
+        channel: false,
+    }
+}
+
+fn payloads(queue: &[QueuedPacket]) -> Vec<Vec<u8>> {
+    queue.iter().map(|q| q.packet.payload.clone()).collect()
+}
+
+fn timestamps(entries: &[HistoryEntry]) -> Vec<u128> {
+    entries.iter().map(|e| e.timestamps).collect()
+}
+
+#[tokio::test]
+async fn offline_queue_keeps_to_the_quota() {
+    for (backend, store, _db) in stores().await {
+        let expires_at = unix_now() + 60;
+        assert!(
+            store
+                .enqueue_offline("bob", queued(&[1; 4], expires_at), MAX_BYTES)
+                .await
+                .unwrap(),
+            "{backend}"
+        );
+        assert!(
+            store
+                .enqueue_offline("bob", queued(&[2; 4], expires_at), MAX_BYTES)
+                .await
+                .unwrap(),
+            "{backend}"
+        );
+        assert!(
+            !store
+                .enqueue_offline("bob", queued(&[3; 4], expires_at), MAX_BYTES)
+                .await
+                .unwrap(),
+            "{backend}"
+        );
+        // The quota is per user
+        assert!(
+            store
+                .enqueue_offline("carol", queued(&[4; 4], expires_at), MAX_BYTES)
+                .await
+                .unwrap(),
+            "{backend}"
+        );
+
+        let taken = store.take_offline("bob").await.unwrap();
+        assert_eq!(payloads(&taken), vec![vec![1; 4], vec![2; 4]], "{backend}");
+        assert!(
+            store.take_offline("bob").await.unwrap().is_empty(),
+            "{backend}"
+        );
+        assert!(
+            store
+                .enqueue_offline("bob", queued(&[3; 4], expires_at), MAX_BYTES)
+                .await
+                .unwrap(),
+            "{backend}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn offline_queue_drops_expired_packets() {
+    for (backend, store, _db) in stores().await {
+        let now = unix_now();
+        // Expired packets neither count against the quota nor are taken
+        assert!(
+            store
+                .enqueue_offline("bob", queued(&[1; 8], now - 1), MAX_BYTES)
+                .await
+                .unwrap(),
+            "{backend}"
+        );
+        assert!(
+            store
+                .enqueue_offline("bob", queued(&[2; 8], now + 60), MAX_BYTES)
+                .await
+                .unwrap(),
+            "{backend}"
+        );
+        assert_eq!(
+            payloads(&store.take_offline("bob").await.unwrap()),
+            vec![vec![2; 8]],
+            "{backend}"
+        );
+
+        store
+            .enqueue_offline("bob", queued(&[3], now + 60), MAX_BYTES)
+            .await
+            .unwrap();
+        store
+            .enqueue_offline("bob", queued(&[4], now + 120), MAX_BYTES)
+            .await
+            .unwrap();
+        assert_eq!(store.purge_offline(now + 60).await.unwrap(), 1, "{backend}");
+        assert_eq!(
+            payloads(&store.take_offline("bob").await.unwrap()),
+            vec![vec![4]],
+            "{backend}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn history_pages_back_from_a_cursor() {
+    for (backend, store, _db) in stores().await {
+        let mut ids = Vec::new();
+        for ts in [10, 20, 30, 40, 50] {
+            ids.push(store.append_history(entry("group", ts, 100)).await.unwrap());
+        }
+        store.append_history(entry("other", 60, 100)).await.unwrap();
+        assert!(ids.windows(2).all(|ids| ids[0] < ids[1]), "{backend}");
+
+        let latest = store.load_history("group", None, 2).await.unwrap();
+        assert_eq!(timestamps(&latest), vec![40, 50], "{backend}");
+        assert_eq!(latest[0].id, ids[3], "{backend}");
+
+        let page = store
+            .load_history("group", Some(HistoryCursor::Id(latest[0].id)), 2)
+            .await
+            .unwrap();
+        assert_eq!(timestamps(&page), vec![20, 30], "{backend}");
+        let page = store
+            .load_history("group", Some(HistoryCursor::Timestamp(20)), 2)
+            .await
+            .unwrap();
+        assert_eq!(timestamps(&page), vec![10], "{backend}");
+        assert!(
+            store
+                .load_history("missing", None, 2)
+                .await
+                .unwrap()
+                .is_empty(),
+            "{backend}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn history_keeps_to_the_retention() {
+    for (backend, store, _db) in stores().await {
+        store.append_history(entry("group", 10, 100)).await.unwrap();
+        store.append_history(entry("group", 20, 100)).await.unwrap();
+        store.append_history(entry("group", 30, 200)).await.unwrap();
+
+        assert_eq!(store.purge_history(150).await.unwrap(), 2, "{backend}");
+        let left = store.load_history("group", None, 10).await.unwrap();
+        assert_eq!(timestamps(&left), vec![30], "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn devices_connect_and_get_revoked() {
+    for (backend, store, _db) in stores().await {
+        assert!(
+            store
+                .add_client(client("alice", "laptop", "t1"))
+                .await
+                .is_none(),
+            "{backend}"
+        );
+        assert!(
+            store
+                .add_client(client("alice", "phone", "t2"))
+                .await
+                .is_none(),
+            "{backend}"
+        );
+        assert_eq!(store.get_devices("alice").await.len(), 2, "{backend}");
+
+        // A new connection of the phone replaces the previous one
+        let replaced = store.add_client(client("alice", "phone", "t3")).await;
+        assert_eq!(
+            replaced.map(|c| c.ticket_id),
+            Some("t2".to_string()),
+            "{backend}"
+        );
+        assert!(
+            store.remove_client("alice", "phone", "t2").await.is_none(),
+            "{backend}"
+        );
+        assert!(
+            store.remove_client("alice", "phone", "t3").await.is_some(),
+            "{backend}"
+        );
+        let devices = store.get_devices("alice").await;
+        assert_eq!(devices.len(), 1, "{backend}");
+        assert_eq!(devices[0].device_id, "laptop", "{backend}");
+
+        store.revoke_device("alice", "phone").await.unwrap();
+        assert!(store.is_device_revoked("alice", "phone").await, "{backend}");
+        assert!(
+            !store.is_device_revoked("alice", "laptop").await,
+            "{backend}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn group_roles_follow_changes() {
+    for (backend, store, _db) in stores().await {
+        store
+            .insert_group(group("alice", &["alice", "bob", "carol"]))
+            .await
+            .unwrap();
+
+        let changed = store
+            .update_group(
+                "group",
+                GroupChange::SetRole("bob".to_string(), GroupRole::Moderator),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            changed.role_of("bob"),
+            Some(GroupRole::Moderator),
+            "{backend}"
+        );
+        assert_eq!(
+            changed.role_of("carol"),
+            Some(GroupRole::Member),
+            "{backend}"
+        );
+
+        store
+            .update_group("group", GroupChange::TransferOwnership("carol".to_string()))
+            .await
+            .unwrap();
+        let group = store.get_group("group").await.unwrap();
+        assert_eq!(group.role_of("carol"), Some(GroupRole::Owner), "{backend}");
+        assert_eq!(group.role_of("alice"), Some(GroupRole::Admin), "{backend}");
+
+        store
+            .update_group("group", GroupChange::RemoveMember("bob".to_string()))
+            .await
+            .unwrap();
+        assert_eq!(
+            store.get_group("group").await.unwrap().role_of("bob"),
+            None,
+            "{backend}"
+        );
+    }
+}
+
+#[tokio::test]
+async fn blocks_are_one_way() {
+    for (backend, store, _db) in stores().await {
+        store.block_user("alice", "bob").await.unwrap();
+        assert!(store.is_blocked("alice", "bob").await, "{backend}");
+        assert!(!store.is_blocked("bob", "alice").await, "{backend}");
+        assert_eq!(
+            store.get_blocked("alice").await,
+            vec!["bob".to_string()],
+            "{backend}"
+        );
+
+        assert!(
+            store.unblock_user("alice", "bob").await.unwrap(),
+            "{backend}"
+        );
+        assert!(
+            !store.unblock_user("alice", "bob").await.unwrap(),
+            "{backend}"
+        );
+        assert!(store.get_blocked("alice").await.is_empty(), "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn contact_requests_wait_for_acceptance() {
+    for (backend, store, _db) in stores().await {
+        let dm = DmChat {
+            dm_id: "dm".to_string(),
+            members: HashMap::from([("alice".to_string(), true), ("bob".to_string(), false)]),
+            session_key: vec![9; 32],
+            requester: Some("alice".to_string()),
+        };
+        store.insert_dm(dm.clone()).await.unwrap();
+        // Opening the same chat again keeps the pending request
+        let existing = store
+            .insert_dm(DmChat {
+                requester: None,
+                ..dm
+            })
+            .await
+            .unwrap();
+        assert_eq!(existing.requester.as_deref(), Some("alice"), "{backend}");
+
+        let accepted = store.accept_dm("dm").await.unwrap().unwrap();
+        assert_eq!(accepted.requester, None, "{backend}");
+        assert_eq!(
+            store.get_dm("dm").await.unwrap().requester,
+            None,
+            "{backend}"
+        );
+
+        assert!(store.delete_dm("dm").await.unwrap().is_some(), "{backend}");
+        assert!(store.get_dm("dm").await.is_none(), "{backend}");
+        assert!(store.accept_dm("dm").await.unwrap().is_none(), "{backend}");
+    }
+}
+
+#[tokio::test]
+async fn sqlite_keeps_chats_across_restarts() {
+    let db = TempDb::new();
+    let store = SqliteStore::open(&db.config).await.unwrap();
+    store
+        .insert_group(group("alice", &["alice", "bob"]))
+        .await
+        .unwrap();
+    store
+        .update_group(
+            "group",
+            GroupChange::SetRole("bob".to_string(), GroupRole::Admin),
+        )
+        .await
+        .unwrap();
+    store.block_user("alice", "carol").await.unwrap();
+    store
+        .enqueue_offline("bob", queued(&[1], unix_now() + 60), MAX_BYTES)
+        .await
+        .unwrap();
+    store
+        .append_history(entry("group", 10, unix_now()))
+        .await
+        .unwrap();
+    let key = store.server_key("ticket").await.unwrap();
+    drop(store);
+
+    let store = SqliteStore::open(&db.config).await.unwrap();
+    let group = store.get_group("group").await.unwrap();
+    assert_eq!(group.session_key, vec![7; 32]);
+    assert_eq!(group.role_of("alice"), Some(GroupRole::Owner));
+    assert_eq!(group.role_of("bob"), Some(GroupRole::Admin));
+    assert!(store.is_blocked("alice", "carol").await);
+    assert_eq!(
+        payloads(&store.take_offline("bob").await.unwrap()),
+        vec![vec![1]]
+    );
+    assert_eq!(
+        timestamps(&store.load_history("group", None, 10).await.unwrap()),
+        vec![10]
+    );
+    assert_eq!(store.server_key("ticket").await.unwrap(), key);
+}
@@ -0,0 +1,83 @@
+//! Resumption tickets.
+//!
+//! A ticket is issued at the end of every handshake. When the connection
+//! closes, the DM and group memberships of the client are suspended
+//! instead of dropped. Presenting the ticket on the next connection skips
+//! the signature verification and restores those memberships.
+//! A ticket can be redeemed once, within `ticket_lifetime` seconds after
+//! the connection it was issued on has closed.
+//! Tickets are bearer tokens, so they are neither sent nor accepted over
+//! plain TCP or unencrypted WebSocket connections.
+//! Suspended sessions are only kept in memory, tickets don't survive a restart.
+
+use crate::{
+    state::ServerState,
+    types::{ResumptionTicket, SuspendedSession},
+};
+use common::{
+    types::{EncryptionConfig, SymmetricAlgo},
+    utils::enc::{decrypt_bytes, encrypt_bytes, hash_string},
+};
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Purpose of the server key encrypting resumption tickets
+const TICKET_KEY: &str = "ticket";
+
+/// Current unix timestamp in seconds
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
+async fn ticket_encryption(
+    state: &ServerState,
+) -> Result<EncryptionConfig, Box<dyn std::error::Error + Send + Sync>> {
+    Ok(EncryptionConfig {
+        algo: SymmetricAlgo::AES256,
+        encryption_key: Some(state.store.server_key(TICKET_KEY).await?),
+    })
+}
+
+/// Issue a new ticket for the given user
+/// Returns the ticket id and the encrypted ticket
+pub async fn issue_ticket(
+    state: &ServerState,
+    user_id: &str,
+) -> Result<(String, Vec<u8>), Box<dyn std::error::Error + Send + Sync>> {
+    let ticket = ResumptionTicket {
+        ticket_id: hash_string(&Uuid::new_v4().to_string()),
+        user_id: user_id.to_string(),
+    };
+
+    let encoded = bincode::encode_to_vec(&ticket, bincode::config::standard())?;
+    let sealed = encrypt_bytes(&encoded, ticket_encryption(state).await?)?;
+
+    Ok((ticket.ticket_id, sealed))
+}
+
+/// Redeem a ticket presented by `user_id`
+/// Returns the suspended session if the ticket is valid and has not expired
+pub async fn redeem_ticket(
+    state: &ServerState,
+    sealed: &[u8],
+    user_id: &str,
+) -> Option<SuspendedSession> {
+    let encoded = decrypt_bytes(sealed, ticket_encryption(state).await.ok()?).ok()?;
+    let (ticket, _): (ResumptionTicket, usize) =
+        bincode::decode_from_slice(&encoded, bincode::config::standard()).ok()?;
+
+    if ticket.user_id != user_id {
+        return None;
+    }
+
+    // Taking the session makes the ticket single use
+    let session = state.store.take_session(&ticket.ticket_id).await?;
+    if session.expires_at <= unix_now() {
+        return None;
+    }
+
+    Some(session)
+}
@@ -1,22 +1,295 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+};
 
-use common::net::StreamWriter;
+use crate::{SlowClientPolicy, handlers::event_packet};
+use bincode::{Decode, Encode};
+use common::{
+    net::Packet,
+    types::{GroupPermission, GroupRole, MessageStatus, Presence, ServerEvent},
+};
+use rsa::RsaPublicKey;
+use tokio::sync::{
+    Notify,
+    mpsc::{Sender, error::TrySendError},
+};
 
-/// Represents a connected client
+/// Represents a connected client, one per device of a user
 #[derive(Clone)]
 pub struct Client {
     /// username of the client
     pub username: String,
     /// user ID of the client
     pub user_id: String,
+    /// public key of the client, in OpenSSH format
+    pub public_key: String,
+    /// device the client connects from, unique among the devices of the user
+    pub device_id: String,
+    /// unix timestamp (seconds) the device connected at
+    pub connected_at: u64,
     /// session key of the client
     pub session_key: String,
     /// direct message chats the client is part of
     pub dms: Vec<String>,
     /// group chats the client is part of
     pub groups: Vec<String>,
-    /// Stream writer for the client
-    pub writer: StreamWriter,
+    /// Packets waiting to be written to the connection
+    pub outbox: Outbox,
+    /// id of the resumption ticket issued to the client, identifies the connection
+    pub ticket_id: String,
+    /// Notified to close the connection, e.g. when the device is revoked
+    pub kick: Arc<Notify>,
+}
+
+impl Client {
+    /// Queue a packet for the device without waiting, false if it wasn't queued
+    /// A device whose queue is full is dropped or disconnected, as the policy of its outbox says
+    pub fn push(&self, packet: Packet) -> bool {
+        self.try_push(Outgoing {
+            packet,
+            delivery: None,
+        })
+        .is_ok()
+    }
+
+    /// Queue a packet delivered to the user, its delivery is settled once the writer takes it
+    /// A device whose queue is full misses the packet
+    pub fn push_delivery(&self, packet: Packet, delivery: &Arc<PendingDelivery>) -> bool {
+        delivery.enter();
+        let pushed = self.try_push(Outgoing {
+            packet,
+            delivery: Some(delivery.clone()),
+        });
+        if let Err(err) = &pushed {
+            if matches!(err, TrySendError::Full(_)) {
+                delivery.missed();
+            }
+            delivery.leave();
+        }
+        pushed.is_ok()
+    }
+
+    fn try_push(&self, outgoing: Outgoing) -> Result<(), TrySendError<Outgoing>> {
+        let pushed = self.outbox.queue.try_send(outgoing);
+        if matches!(pushed, Err(TrySendError::Full(_)))
+            && self.outbox.policy == SlowClientPolicy::Disconnect
+            && !self.outbox.closing.swap(true, Ordering::Relaxed)
+        {
+            println!(
+                "🐢 Disconnecting slow client: {} ({})",
+                &self.user_id[..8],
+                self.device_id
+            );
+            self.kick.notify_one();
+        }
+        pushed
+    }
+}
+
+/// Bounded queue of the packets waiting to be written to a connection, drained by its writer task
+#[derive(Clone)]
+pub struct Outbox {
+    pub queue: Sender<Outgoing>,
+    /// What happens once the queue is full
+    pub policy: SlowClientPolicy,
+    /// Set once the connection is being closed for not keeping up
+    pub closing: Arc<AtomicBool>,
+}
+
+/// A packet waiting in an outbox
+pub struct Outgoing {
+    pub packet: Packet,
+    /// Set for packets delivered to the user, they are queued offline if the connection closes first
+    pub delivery: Option<Arc<PendingDelivery>>,
+}
+
+/// How a packet reached a recipient, from worst to best
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Delivery {
+    /// The queue of the recipient is full
+    Dropped,
+    /// Queued until a device connects
+    Queued,
+    /// Taken by the writer of at least one connected device
+    Delivered,
+}
+
+/// Delivery of a packet to a recipient, shared by the outboxes it waits in
+/// Counted in the status of the message once a device took it, or once it is settled everywhere
+pub struct PendingDelivery {
+    progress: Mutex<DeliveryProgress>,
+}
+
+struct DeliveryProgress {
+    outcome: Delivery,
+    /// outboxes the packet still waits in
+    outboxes: usize,
+    /// a device of the recipient didn't get the packet, its queue was full
+    missed: bool,
+    /// still being pushed to the devices of the recipient
+    pushing: bool,
+    /// taken once the outcome is counted
+    report: Option<Arc<StatusReport>>,
+}
+
+impl PendingDelivery {
+    pub fn new(report: Option<Arc<StatusReport>>) -> Arc<Self> {
+        Arc::new(Self {
+            progress: Mutex::new(DeliveryProgress {
+                outcome: Delivery::Dropped,
+                outboxes: 0,
+                missed: false,
+                pushing: false,
+                report,
+            }),
+        })
+    }
+
+    /// Keep the best outcome so far
+    pub fn settle(&self, delivery: Delivery) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.outcome = progress.outcome.max(delivery);
+        let report = progress.ready();
+        drop(progress);
+        report_delivery(report);
+    }
+
+    /// Pushed to the devices of the recipient in between, it isn't counted before all of them were tried
+    pub fn pushing(&self, pushing: bool) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.pushing = pushing;
+        let report = progress.ready();
+        drop(progress);
+        report_delivery(report);
+    }
+
+    /// The writer of a connection took the packet
+    pub fn taken(&self) {
+        self.leave();
+        self.settle(Delivery::Delivered);
+    }
+
+    /// The connection closed with the packet still in its outbox
+    /// True if no other device got or still waits for it, so it has to be delivered again
+    pub fn abandoned(&self) -> bool {
+        let mut progress = self.progress.lock().unwrap();
+        progress.outboxes = progress.outboxes.saturating_sub(1);
+        progress.outboxes == 0 && progress.outcome != Delivery::Delivered
+    }
+
+    fn missed(&self) {
+        self.progress.lock().unwrap().missed = true;
+    }
+
+    fn enter(&self) {
+        self.progress.lock().unwrap().outboxes += 1;
+    }
+
+    fn leave(&self) {
+        let mut progress = self.progress.lock().unwrap();
+        progress.outboxes = progress.outboxes.saturating_sub(1);
+    }
+}
+
+impl DeliveryProgress {
+    /// The report and what to count in it, once a device took the packet
+    fn ready(&mut self) -> Option<(Arc<StatusReport>, Delivery, bool)> {
+        if self.outcome != Delivery::Delivered || self.pushing {
+            return None;
+        }
+        let report = self.report.take()?;
+        Some((report, self.outcome, self.missed))
+    }
+}
+
+impl Drop for PendingDelivery {
+    fn drop(&mut self) {
+        let progress = self.progress.get_mut().unwrap();
+        if let Some(report) = progress.report.take() {
+            report_delivery(Some((report, progress.outcome, progress.missed)));
+        }
+    }
+}
+
+fn report_delivery(report: Option<(Arc<StatusReport>, Delivery, bool)>) {
+    if let Some((report, outcome, missed)) = report {
+        report.record(outcome);
+        // Delivered to some of its devices only, the others count as dropped
+        if missed && outcome == Delivery::Delivered {
+            report.record(Delivery::Dropped);
+        }
+    }
+}
+
+/// Status of a message, sent to the device it came from once every recipient is settled
+pub struct StatusReport {
+    status: Mutex<MessageStatus>,
+    /// outbox and device id of the sending device, if it is connected
+    sender: Option<(Sender<Outgoing>, String)>,
+}
+
+impl StatusReport {
+    pub fn new(sender: Option<&Client>, status: MessageStatus) -> Arc<Self> {
+        Arc::new(Self {
+            status: Mutex::new(status),
+            sender: sender.map(|client| (client.outbox.queue.clone(), client.device_id.clone())),
+        })
+    }
+
+    /// Count the outcome of delivering the message to a recipient
+    pub fn record(&self, delivery: Delivery) {
+        let mut status = self.status.lock().unwrap();
+        match delivery {
+            Delivery::Delivered => status.delivered += 1,
+            Delivery::Queued => status.queued += 1,
+            Delivery::Dropped => status.dropped += 1,
+        }
+    }
+}
+
+impl Drop for StatusReport {
+    fn drop(&mut self) {
+        let Some((outbox, device_id)) = &self.sender else {
+            return;
+        };
+        let status = self.status.get_mut().unwrap();
+        let Some(packet) = event_packet(&ServerEvent::MessageStatus(status.clone())) else {
+            return;
+        };
+        let outgoing = Outgoing {
+            packet,
+            delivery: None,
+        };
+        if let Err(TrySendError::Full(_)) = outbox.try_send(outgoing) {
+            println!("🐢 Status of a message dropped for device {}", device_id);
+        }
+    }
+}
+
+/// Presence and status line chosen by a user, kept while it is offline
+#[derive(Debug, Clone, Default)]
+pub struct UserStatus {
+    /// presence shown while at least one device is connected
+    pub presence: Presence,
+    /// custom status line
+    pub status: Option<String>,
+}
+
+/// A handle registered to a user, unique among every user
+#[derive(Debug, Clone)]
+pub struct HandleEntry {
+    /// the handle, lowercase
+    pub handle: String,
+    /// user ID the handle belongs to
+    pub user_id: String,
+    /// public key of the user, in OpenSSH format
+    pub public_key: String,
+    /// unix timestamp (seconds) the handle was claimed at
+    pub claimed_at: u64,
 }
 
 /// Represents a direct message chat
@@ -28,6 +301,8 @@ pub struct DmChat {
     pub members: HashMap<String, bool>,
     /// session key for the direct message chat
     pub session_key: Vec<u8>,
+    /// member that opened the chat, set until the other member accepts the contact request
+    pub requester: Option<String>,
 }
 
 /// Represents a group chat
@@ -41,6 +316,382 @@ pub struct GroupChat {
     pub members: HashMap<String, bool>,
     /// session key for the group chat
     pub session_key: Vec<u8>,
-    /// admin's user_id of the group chat
+    /// admin's user_id of the group chat, it owns the group
     pub admin: String,
+    /// roles of the other members, the default role if not set
+    pub roles: HashMap<String, GroupRole>,
+    /// broadcast channel, members are read-only subscribers unless given another role
+    pub channel: bool,
+}
+
+impl GroupChat {
+    /// Role of a member, `None` if it isn't part of the group
+    pub fn role_of(&self, user_id: &str) -> Option<GroupRole> {
+        if !self.members.contains_key(user_id) {
+            return None;
+        }
+        if self.admin == user_id {
+            return Some(GroupRole::Owner);
+        }
+        Some(
+            self.roles
+                .get(user_id)
+                .copied()
+                .unwrap_or(self.default_role()),
+        )
+    }
+
+    /// Role of the members that weren't given one, subscribers only read channels
+    pub fn default_role(&self) -> GroupRole {
+        match self.channel {
+            true => GroupRole::ReadOnly,
+            false => GroupRole::Member,
+        }
+    }
+
+    /// Whether a member can post, in a channel only publishers can
+    pub fn can_post(&self, user_id: &str) -> bool {
+        self.role_of(user_id)
+            .is_some_and(|role| role.can(GroupPermission::Post))
+    }
+
+    /// Apply a change, a member that isn't part of the group is ignored
+    pub fn apply(&mut self, change: &GroupChange) {
+        match change {
+            GroupChange::AddMember(user_id) => {
+                self.members.entry(user_id.clone()).or_insert(false);
+            }
+            GroupChange::RemoveMember(user_id) => {
+                self.members.remove(user_id);
+                self.roles.remove(user_id);
+            }
+            GroupChange::Rename(name) => self.group_name = name.clone(),
+            GroupChange::SetRole(user_id, role) => {
+                if !self.members.contains_key(user_id) || self.admin == *user_id {
+                    return;
+                }
+                match role {
+                    // The owner only changes through a transfer
+                    GroupRole::Owner => None,
+                    role if *role == self.default_role() => self.roles.remove(user_id),
+                    role => self.roles.insert(user_id.clone(), *role),
+                };
+            }
+            GroupChange::TransferOwnership(user_id) => {
+                if self.members.contains_key(user_id) {
+                    self.roles.remove(user_id);
+                    let previous = std::mem::replace(&mut self.admin, user_id.clone());
+                    self.roles.insert(previous, GroupRole::Admin);
+                }
+            }
+            GroupChange::Rekey(session_key) => self.session_key = session_key.clone(),
+        }
+    }
+}
+
+/// A change made to a group by one of its members
+#[derive(Debug, Clone)]
+pub enum GroupChange {
+    /// add a member, inactive until it opens the group
+    AddMember(String),
+    RemoveMember(String),
+    Rename(String),
+    /// give a member another role, the owner only changes through a transfer
+    SetRole(String, GroupRole),
+    /// make a member the owner, the previous owner becomes an admin
+    TransferOwnership(String),
+    /// replace the session key
+    Rekey(Vec<u8>),
+}
+
+/// Contents of a resumption ticket, only ever sent to the client encrypted
+#[derive(Debug, Encode, Decode)]
+pub struct ResumptionTicket {
+    /// unique identifier of the ticket
+    pub ticket_id: String,
+    /// user ID the ticket was issued to
+    pub user_id: String,
+}
+
+/// Contents of a group invite token, only ever handed out sealed
+#[derive(Debug, Encode, Decode)]
+pub struct InviteToken {
+    /// unique identifier of the invite
+    pub invite_id: String,
+    /// group ID the invite is for
+    pub group_id: String,
+}
+
+/// A group invite that can still be redeemed
+#[derive(Debug, Clone)]
+pub struct Invite {
+    /// group ID the invite is for
+    pub group_id: String,
+    /// how many more times the invite can be redeemed, unlimited if `None`
+    pub uses_left: Option<u32>,
+    /// unix timestamp (seconds) after which the invite expires, never if `None`
+    pub expires_at: Option<u64>,
+}
+
+impl Invite {
+    /// Whether the invite is used up or expired at `now`
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.uses_left == Some(0) || self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// Memberships of a disconnected client, kept until its ticket is redeemed or expires
+#[derive(Debug, Clone)]
+pub struct SuspendedSession {
+    /// user ID of the disconnected client
+    pub user_id: String,
+    /// direct message chats the client was part of
+    pub dms: Vec<String>,
+    /// group chats the client was part of
+    pub groups: Vec<String>,
+    /// unix timestamp (seconds) after which the session and its ticket expire
+    pub expires_at: u64,
+}
+
+/// Result of a successful handshake
+pub struct HandshakeOutcome {
+    /// username sent by the client
+    pub username: String,
+    /// session key of the connection
+    pub session_key: Vec<u8>,
+    /// public key of the client
+    pub public_key: RsaPublicKey,
+    /// device sent by the client
+    pub device_id: String,
+    /// id of the resumption ticket issued at the end of the handshake
+    pub ticket_id: String,
+    /// session restored from the ticket presented by the client
+    pub resumed: Option<SuspendedSession>,
+}
+
+/// A packet waiting for an offline recipient
+#[derive(Debug, Clone)]
+pub struct QueuedPacket {
+    /// the packet as sent by its sender
+    pub packet: Packet,
+    /// unix timestamp (seconds) after which the packet is dropped
+    pub expires_at: u64,
+}
+
+impl QueuedPacket {
+    /// Size of the packet counted against the queue quota of the recipient
+    pub fn size(&self) -> usize {
+        self.packet.payload.len()
+    }
+}
+
+/// A message kept in the history of a DM session or group
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    /// id assigned by the store, increasing in the order messages were received
+    pub id: u64,
+    /// DM session or group the message was sent to
+    pub chat_id: String,
+    /// timestamp of the message, as set by its sender
+    pub timestamps: u128,
+    /// the encoded message, its content is encrypted with the session key
+    pub payload: Vec<u8>,
+    /// unix timestamp (seconds) the message was received at
+    pub stored_at: u64,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use common::net::ChatMessageKind;
+    use tokio::sync::mpsc::{Receiver, channel};
+
+    fn device(
+        device_id: &str,
+        queue_len: usize,
+        policy: SlowClientPolicy,
+    ) -> (Client, Receiver<Outgoing>) {
+        let (queue, rx) = channel(queue_len);
+        let client = Client {
+            username: "user".to_string(),
+            user_id: "0".repeat(64),
+            public_key: String::new(),
+            device_id: device_id.to_string(),
+            connected_at: 0,
+            session_key: String::new(),
+            dms: Vec::new(),
+            groups: Vec::new(),
+            outbox: Outbox {
+                queue,
+                policy,
+                closing: Arc::new(AtomicBool::new(false)),
+            },
+            ticket_id: String::new(),
+            kick: Arc::new(Notify::new()),
+        };
+        (client, rx)
+    }
+
+    fn message() -> Packet {
+        Packet {
+            kind: ChatMessageKind::GroupMessage("group".to_string()),
+            payload: vec![1, 2, 3],
+        }
+    }
+
+    fn status() -> MessageStatus {
+        MessageStatus {
+            session_id: "group".to_string(),
+            timestamps: 1,
+            message_id: "message".to_string(),
+            delivered: 0,
+            queued: 0,
+            dropped: 0,
+            refused: 0,
+        }
+    }
+
+    /// Push to the devices the way a delivery does, true if one of them took the packet
+    fn push(devices: &[&Client], delivery: &Arc<PendingDelivery>) -> bool {
+        delivery.pushing(true);
+        let mut pushed = false;
+        for device in devices {
+            pushed |= device.push_delivery(message(), delivery);
+        }
+        delivery.pushing(false);
+        pushed
+    }
+
+    /// The status the sending device got, if any
+    fn received_status(rx: &mut Receiver<Outgoing>) -> Option<MessageStatus> {
+        let outgoing = rx.try_recv().ok()?;
+        let (event, _): (ServerEvent, usize) =
+            bincode::decode_from_slice(&outgoing.packet.payload, bincode::config::standard())
+                .ok()?;
+        match event {
+            ServerEvent::MessageStatus(status) => Some(status),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn delivered_once_the_writer_takes_it() {
+        let (sender, mut sender_rx) = device("sender", 4, SlowClientPolicy::Drop);
+        let (recipient, mut rx) = device("phone", 4, SlowClientPolicy::Drop);
+        let delivery = PendingDelivery::new(Some(StatusReport::new(Some(&sender), status())));
+
+        assert!(push(&[&recipient], &delivery));
+        drop(delivery);
+        assert!(received_status(&mut sender_rx).is_none());
+
+        rx.try_recv().unwrap().delivery.unwrap().taken();
+        let status = received_status(&mut sender_rx).unwrap();
+        assert_eq!((status.delivered, status.queued, status.dropped), (1, 0, 0));
+    }
+
+    #[test]
+    fn queued_when_no_device_is_connected() {
+        let (sender, mut sender_rx) = device("sender", 4, SlowClientPolicy::Drop);
+        let delivery = PendingDelivery::new(Some(StatusReport::new(Some(&sender), status())));
+
+        assert!(!push(&[], &delivery));
+        delivery.settle(Delivery::Queued);
+        drop(delivery);
+        let status = received_status(&mut sender_rx).unwrap();
+        assert_eq!((status.delivered, status.queued, status.dropped), (0, 1, 0));
+    }
+
+    #[test]
+    fn dropped_when_the_queue_is_full() {
+        let (sender, mut sender_rx) = device("sender", 4, SlowClientPolicy::Drop);
+        let (recipient, _rx) = device("phone", 1, SlowClientPolicy::Drop);
+        assert!(recipient.push(message()));
+        let delivery = PendingDelivery::new(Some(StatusReport::new(Some(&sender), status())));
+
+        assert!(!push(&[&recipient], &delivery));
+        drop(delivery);
+        let status = received_status(&mut sender_rx).unwrap();
+        assert_eq!((status.delivered, status.queued, status.dropped), (0, 0, 1));
+    }
+
+    #[test]
+    fn full_queue_kicks_a_slow_device() {
+        let (recipient, _rx) = device("phone", 1, SlowClientPolicy::Disconnect);
+        assert!(recipient.push(message()));
+        assert!(!recipient.push(message()));
+        assert!(recipient.outbox.closing.load(Ordering::Relaxed));
+    }
+
+    #[test]
+    fn requeued_when_the_connection_closes_first() {
+        let (sender, mut sender_rx) = device("sender", 4, SlowClientPolicy::Drop);
+        let (recipient, mut rx) = device("phone", 4, SlowClientPolicy::Drop);
+        let delivery = PendingDelivery::new(Some(StatusReport::new(Some(&sender), status())));
+        assert!(push(&[&recipient], &delivery));
+        drop(delivery);
+
+        rx.close();
+        let delivery = rx.try_recv().unwrap().delivery.unwrap();
+        assert!(delivery.abandoned());
+        assert!(received_status(&mut sender_rx).is_none());
+
+        // Delivered again to the offline queue
+        delivery.settle(Delivery::Queued);
+        drop(delivery);
+        let status = received_status(&mut sender_rx).unwrap();
+        assert_eq!((status.delivered, status.queued, status.dropped), (0, 1, 0));
+    }
+
+    #[test]
+    fn not_requeued_when_another_device_took_it() {
+        let (laptop, mut laptop_rx) = device("laptop", 4, SlowClientPolicy::Drop);
+        let (phone, mut phone_rx) = device("phone", 4, SlowClientPolicy::Drop);
+        let delivery = PendingDelivery::new(None);
+        assert!(push(&[&laptop, &phone], &delivery));
+        drop(delivery);
+
+        laptop_rx.try_recv().unwrap().delivery.unwrap().taken();
+        phone_rx.close();
+        let delivery = phone_rx.try_recv().unwrap().delivery.unwrap();
+        assert!(!delivery.abandoned());
+    }
+
+    #[test]
+    fn full_queue_on_some_devices() {
+        let (sender, mut sender_rx) = device("sender", 4, SlowClientPolicy::Drop);
+        let (laptop, mut laptop_rx) = device("laptop", 4, SlowClientPolicy::Drop);
+        let (phone, _phone_rx) = device("phone", 1, SlowClientPolicy::Drop);
+        let (tablet, _tablet_rx) = device("tablet", 1, SlowClientPolicy::Drop);
+        assert!(phone.push(message()));
+        assert!(tablet.push(message()));
+        let delivery = PendingDelivery::new(Some(StatusReport::new(Some(&sender), status())));
+
+        assert!(push(&[&phone, &laptop, &tablet], &delivery));
+        drop(delivery);
+        laptop_rx.try_recv().unwrap().delivery.unwrap().taken();
+
+        // Counted once as delivered, and once as dropped for the devices that missed it
+        let status = received_status(&mut sender_rx).unwrap();
+        assert_eq!((status.delivered, status.queued, status.dropped), (1, 0, 1));
+    }
+
+    #[test]
+    fn status_waits_for_every_recipient() {
+        let (sender, mut sender_rx) = device("sender", 4, SlowClientPolicy::Drop);
+        let (alice, mut alice_rx) = device("alice", 4, SlowClientPolicy::Drop);
+        let report = StatusReport::new(Some(&sender), status());
+
+        let online = PendingDelivery::new(Some(report.clone()));
+        assert!(push(&[&alice], &online));
+        drop(online);
+        let offline = PendingDelivery::new(Some(report));
+        assert!(!push(&[], &offline));
+        offline.settle(Delivery::Queued);
+        drop(offline);
+        assert!(received_status(&mut sender_rx).is_none());
+
+        alice_rx.try_recv().unwrap().delivery.unwrap().taken();
+        let status = received_status(&mut sender_rx).unwrap();
+        assert_eq!((status.delivered, status.queued, status.dropped), (1, 1, 0));
+    }
 }